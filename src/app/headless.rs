@@ -1,46 +1,99 @@
 use crate::hifive1b::Hifive1b;
-use crate::utils::map_to_unixsocket;
-use std::io::{self, Read};
+use crate::utils::{map_to_unixsocket, IOChannel};
+use std::io::{self, Read, Write};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::spawn;
 use std::thread::JoinHandle;
+use std::time::Instant;
 
+use super::load_cpu;
 use crate::cli;
-use crate::cpu::{create_cpu_thread, AddrBus, CPU};
+use crate::cpu::{create_cpu_thread, AddrBus, Host, CPU};
 use crate::events::{CpuJob, Event};
 
-fn input_thread(sender: &Sender<Event>, output: Option<&Sender<u8>>) {
+fn input_thread(sender: &Sender<Event>, outputs: &[Sender<u8>]) {
     println!("Use ^D to terminate.");
     let mut buffer = [0; 1];
     while let Ok(size) = io::stdin().read(&mut buffer) {
         if size == 0 {
             break;
         }
-        if let Some(output) = output {
+        for output in outputs {
             output.send(buffer[0]).unwrap();
         }
     }
     sender.send(Event::ExitApp).unwrap();
 }
 
-fn create_input_thread(sender: Sender<Event>, output: Option<Sender<u8>>) -> JoinHandle<()> {
-    spawn(move || input_thread(&sender, output.as_ref()))
+fn create_input_thread(sender: Sender<Event>, outputs: Vec<Sender<u8>>) -> JoinHandle<()> {
+    spawn(move || input_thread(&sender, &outputs))
 }
 
-pub fn headless(config: &cli::Config) {
+/// Drops everything sent by the UART, but keeps it connected
+fn discard_uart((uart_tx, uart_rx): IOChannel) {
+    spawn(move || {
+        let _keep_connected = uart_tx;
+        while uart_rx.recv().is_ok() {}
+    });
+}
+
+/// Prints the output of the console of the host, as soon as it is written.
+fn print_console(console_rx: Receiver<u8>) -> JoinHandle<()> {
+    spawn(move || {
+        while let Ok(data) = console_rx.recv() {
+            let mut output = vec![data];
+            output.extend(console_rx.try_iter());
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(&output);
+            let _ = stdout.flush();
+        }
+    })
+}
+
+pub fn headless(config: &cli::Config) -> anyhow::Result<()> {
     let (event_sender, event_receiver): (Sender<Event>, Receiver<Event>) = channel();
     let (cpu_sender, cpu_reader): (Sender<CpuJob>, Receiver<CpuJob>) = channel();
 
-    let mut hifive1b = Hifive1b::new(event_sender.clone());
+    let mut hifive1b = Hifive1b::new(event_sender.clone(), config.harts);
+
+    // The reference for the lockstep mode replays the frames the UARTs receive.
+    // It sees the same GPIO pins.
+    let mut reference = None;
+    let mut inputs = Vec::new();
+    if config.lockstep {
+        let mut reference_hifive1b = Hifive1b::new(event_sender.clone(), config.harts);
+        let mut memory_map = reference_hifive1b.memory.take().unwrap();
+        memory_map.gpio.share_pins(hifive1b.gpio_pins.clone());
+        discard_uart(reference_hifive1b.uart0channel.take().unwrap());
+        discard_uart(reference_hifive1b.uart1channel.take().unwrap());
+        reference = Some(load_cpu(config, memory_map, true)?);
+    }
+
+    // The console of the host shares stdio with UART0
+    let mut host = None;
+    let mut console_printer = None;
+    if config.semihosting || config.syscalls {
+        let ((console_tx, console_rx), services) = Host::new(
+            config.sandbox.clone(),
+            config.arguments.clone(),
+            config.environment.clone(),
+            config.semihosting,
+            config.syscalls,
+        );
+        inputs.push(console_tx);
+        console_printer = Some(print_console(console_rx));
+        host = Some(services);
+    }
 
     let uart0 = hifive1b.uart0channel.take().unwrap();
     if let Some(path) = &config.uart0 {
         map_to_unixsocket(uart0, path.clone());
-        create_input_thread(event_sender.clone(), None);
+        create_input_thread(event_sender.clone(), inputs);
     } else {
         let (uart_tx, uart_rx) = uart0;
-        create_input_thread(event_sender.clone(), Some(uart_tx));
+        inputs.push(uart_tx);
+        create_input_thread(event_sender.clone(), inputs);
         spawn(move || loop {
             while let Ok(data) = uart_rx.recv() {
                 print!("{:}", data as char);
@@ -53,15 +106,19 @@ pub fn headless(config: &cli::Config) {
         map_to_unixsocket(uart1, path.clone());
     }
 
+    if let Some(path) = &config.gpio {
+        hifive1b.gpio_pins.map_to_unixsocket(path.clone());
+    }
+
     let memory_map = hifive1b.memory.take().unwrap();
 
-    let cpu_val = if config.bin {
-        let entry = config.entryaddress;
-        let baseaddress = config.baseaddress;
-        CPU::from_bin(&config.file, memory_map, entry, baseaddress)
-    } else {
-        CPU::from_elf(&config.file, memory_map)
-    };
+    let mut cpu_val = load_cpu(config, memory_map, false)?;
+    if let Some(host) = host {
+        cpu_val.set_host(host)?;
+    }
+    if let Some(reference) = reference {
+        cpu_val.set_reference(reference)?;
+    }
     let cpu = Arc::new(Mutex::new(cpu_val));
 
     cpu_job_loop(
@@ -71,9 +128,12 @@ pub fn headless(config: &cli::Config) {
         event_sender,
         cpu_reader,
         &cpu_sender,
+        console_printer,
     );
+    Ok(())
 }
 
+#[allow(clippy::collapsible_match)]
 fn cpu_job_loop(
     config: &cli::Config,
     cpu: &Arc<Mutex<CPU<impl AddrBus + Send + 'static>>>,
@@ -81,7 +141,9 @@ fn cpu_job_loop(
     event_sender: Sender<Event>,
     cpu_reader: Receiver<CpuJob>,
     cpu_sender: &Sender<CpuJob>,
+    console_printer: Option<JoinHandle<()>>,
 ) {
+    let start = Instant::now();
     create_cpu_thread(&Arc::clone(cpu), event_sender, cpu_reader);
 
     cpu_sender.send(CpuJob::AutoStep).unwrap();
@@ -95,11 +157,16 @@ fn cpu_job_loop(
             }
             Event::CpuPanic(err) => {
                 let cpu = cpu.lock().unwrap();
-                println!("\nUnrecoverable error, last instructions:");
-                for data in cpu.last_n_instructions(10).iter().flatten() {
-                    let (addr, instruction) = data;
+                println!(
+                    "\nUnrecoverable error, last instructions of hart {}:",
+                    cpu.hart_id
+                );
+                for (addr, instruction) in cpu.last_n_instructions(10) {
                     println!("0x{addr:08X}:{}", instruction.print());
                 }
+                if let Some(exception) = &cpu.last_exception {
+                    println!("The last trap was taken for: {exception}");
+                }
                 panic!(
                     "\n{}",
                     &format!(
@@ -109,7 +176,8 @@ fn cpu_job_loop(
                 )
             }
             Event::InterruptUart => {
-                cpu_sender.send(CpuJob::CheckInterrupts).unwrap();
+                // The CPU may have stopped already, before this event was received
+                let _ = cpu_sender.send(CpuJob::CheckInterrupts);
             }
             Event::ExitApp => {
                 break;
@@ -118,14 +186,45 @@ fn cpu_job_loop(
         }
     }
 
+    // All output of the console is printed before the results
+    if let Some(printer) = console_printer {
+        cpu.lock().unwrap().disconnect_console();
+        let _ = printer.join();
+    }
+
+    if config.benchmark {
+        let elapsed = start.elapsed();
+        let executed = cpu.lock().unwrap().executed_instructions;
+        println!(
+            "Executed {executed} instructions in {:.3} s, {} MIPS",
+            elapsed.as_secs_f64(),
+            // Instructions per microsecond are millions of instructions per second
+            u128::from(executed) / elapsed.as_micros().max(1)
+        );
+    }
+
     if config.testing {
+        // The result of the test is reported by the hart that executed ebreak,
+        // or by the exit code of a program that exits through the host
         let cpu = cpu.lock().unwrap();
-        let reg = cpu.register.read(17);
-        if reg != 93 {
-            println!("Test failed: {:}", cpu.register.read(10));
+        let passed = cpu
+            .exit_code
+            .map_or(cpu.register.read(17) == 93, |code| code == 0);
+        if !passed {
+            match cpu.exit_code {
+                Some(code) => println!("Test failed: {code}"),
+                None => println!("Test failed: {:}", cpu.register.read(10)),
+            }
+            if let Some(exception) = &cpu.last_exception {
+                println!("The last trap was taken for: {exception}");
+            }
         }
-        assert!(cpu.register.read(17) == 93, "Test failed");
+        assert!(passed, "Test failed");
     } else {
         println!("Done!");
+        let exit_code = cpu.lock().unwrap().exit_code;
+        if let Some(code) = exit_code.filter(|code| *code != 0) {
+            std::process::exit(code);
+        }
     }
 }
@@ -1,3 +1,35 @@
 pub mod headless;
 mod render_tui;
 pub mod tui;
+
+use crate::cli;
+use crate::cpu::CPU;
+use crate::hifive1b;
+
+/// Sets up the memory map and the CPU as configured, shared by both front ends.
+/// Only the emulator that is not the lockstep reference writes back to the flash, SD card and EEPROM images.
+fn load_cpu(
+    config: &cli::Config,
+    mut memory_map: hifive1b::Memory,
+    reference: bool,
+) -> anyhow::Result<CPU<hifive1b::Memory>> {
+    memory_map.set_uart_pacing(config.uart_pacing);
+    if let Some(path) = &config.flash {
+        memory_map.attach_flash_image(path, !reference)?;
+    }
+    if let Some(path) = &config.sd_card {
+        memory_map.attach_sd_card(path, !reference)?;
+    }
+    if let Some(path) = &config.eeprom {
+        memory_map.attach_eeprom_image(path, !reference)?;
+    }
+    memory_map.set_temperature(config.temperature);
+    if config.syscalls {
+        memory_map.map_user_ram();
+    }
+    let mut cpu_val = CPU::from_images(&config.images, config.entry, memory_map)?;
+    cpu_val.register.rve |= config.rve;
+    cpu_val.set_harts(config.harts, config.quantum);
+    cpu_val.engine = config.engine;
+    Ok(cpu_val)
+}
@@ -2,6 +2,7 @@
 use crate::cpu::AddrBus;
 use crate::cpu::Register;
 use crate::cpu::CPU;
+use crate::hifive1b::{GpioPins, PwmDuty};
 use crate::instructions::Instruction;
 use crate::utils::UserInputManager;
 use anyhow::Error;
@@ -15,7 +16,8 @@ use crossterm::event::KeyCode;
 
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
-    text::{Span, Text},
+    style::{Color, Style},
+    text::{Line, Span, Text},
     widgets::{Block, Clear, Paragraph},
     Frame,
 };
@@ -24,6 +26,10 @@ use super::tui::Job;
 
 pub struct ViewState {
     pub uart: String,
+    /// The hart whose state is shown
+    pub hart: usize,
+    gpio: GpioPins,
+    pwm: PwmDuty,
     user_input_manager: UserInputManager,
     auto_step: bool,
     show_help: bool,
@@ -31,9 +37,12 @@ pub struct ViewState {
 }
 
 impl ViewState {
-    pub fn new() -> Self {
+    pub fn new(gpio: GpioPins, pwm: PwmDuty) -> Self {
         ViewState {
             uart: String::new(),
+            hart: 0,
+            gpio,
+            pwm,
             user_input_manager: UserInputManager::new(),
             auto_step: false,
             show_help: true,
@@ -87,9 +96,16 @@ impl ViewState {
                 KeyCode::Char('i') => {
                     self.insert_mode = true;
                 }
+                KeyCode::Char('n') => {
+                    // Wraps around to hart 0 on the next draw
+                    self.hart += 1;
+                }
                 KeyCode::Char('q') => {
                     return Job::Exit;
                 }
+                KeyCode::Char('r') => {
+                    return Job::Reset;
+                }
                 KeyCode::Char('s') => {
                     return Job::Step(1);
                 }
@@ -114,14 +130,9 @@ impl ViewState {
         for _ in last_inst.len()..log_height - 2 {
             last_instruction_list.push('\n');
         }
-        for temp_last_inst in last_inst {
-            match temp_last_inst {
-                Some((addr, cur_inst)) => {
-                    last_instruction_list
-                        .push_str(format!("0x{:08X}: {:}\n", addr, cur_inst.print()).as_str());
-                }
-                None => last_instruction_list.push('\n'),
-            }
+        for (addr, cur_inst) in last_inst {
+            last_instruction_list
+                .push_str(format!("0x{:08X}: {:}\n", addr, cur_inst.print()).as_str());
         }
         let text = { Text::from(last_instruction_list) };
         Paragraph::new(text).block(Block::bordered().title(vec![Span::from("Last Instructions")]))
@@ -149,6 +160,10 @@ impl ViewState {
 
     fn register_table(rf: &Register) -> (Paragraph<'_>, Paragraph<'_>) {
         fn register_to_str(dest: &mut String, num: usize, comment: &str, rf: &Register) {
+            // Registers that do not exist, e.g. x16-x31 in RV32E, are not shown
+            if num >= rf.count() {
+                return;
+            }
             let _ = writeln!(dest, "x{num:<2} /{:} | {comment}", rf.to_string(num));
         }
 
@@ -195,10 +210,19 @@ impl ViewState {
         (left, right)
     }
 
-    fn render_registers(register_block: Rect, registers: &Register, frame: &mut Frame) {
-        let register_file_table = Block::bordered()
-            .title(vec![Span::from("Registers")])
+    fn render_registers<T: AddrBus>(register_block: Rect, cpu: &CPU<T>, frame: &mut Frame) {
+        let title = if cpu.hart_count() > 1 {
+            format!("Registers of hart {}/{}", cpu.hart_id, cpu.hart_count())
+        } else {
+            String::from("Registers")
+        };
+        let mut register_file_table = Block::bordered()
+            .title(vec![Span::from(title)])
             .title_alignment(Alignment::Left);
+        if let Some(exception) = &cpu.last_exception {
+            register_file_table =
+                register_file_table.title_bottom(format!("Last trap: {exception}"));
+        }
 
         let register_chunks = Layout::default()
             .direction(Direction::Horizontal)
@@ -208,12 +232,75 @@ impl ViewState {
                 vertical: 1,
             }));
 
-        let (left, right) = ViewState::register_table(registers);
+        let (left, right) = ViewState::register_table(&cpu.register);
         frame.render_widget(left, register_chunks[0]);
         frame.render_widget(right, register_chunks[1]);
         frame.render_widget(register_file_table, register_block);
     }
 
+    /// The RGB LED and the level of every pin, colored by who drives it
+    fn render_gpio(&self, gpio_block: Rect, frame: &mut Frame) {
+        let levels = self.gpio.levels();
+        let outputs = self.gpio.outputs();
+        let iof = self.gpio.iof();
+        let (driven, _levels) = self.gpio.driven();
+        let led = match self.gpio.led() {
+            (false, false, false) => Span::styled("○ off", Style::new().fg(Color::DarkGray)),
+            (red, green, blue) => Span::styled(
+                "● on",
+                Style::new().fg(Color::Rgb(
+                    u8::from(red) * 0xFF,
+                    u8::from(green) * 0xFF,
+                    u8::from(blue) * 0xFF,
+                )),
+            ),
+        };
+        let mut lines = vec![Line::from(vec![Span::from("LED    "), led])];
+        for first in [0, 16] {
+            let mut spans = vec![Span::from(format!("{first:>2}-{:<2}  ", first + 15))];
+            for pin in first..first + 16 {
+                let bit = 1 << pin;
+                let style = if iof & bit != 0 {
+                    Style::new().fg(Color::DarkGray)
+                } else if outputs & bit != 0 {
+                    Style::new().fg(Color::Yellow)
+                } else if driven & bit != 0 {
+                    Style::new().fg(Color::Cyan)
+                } else {
+                    Style::new()
+                };
+                spans.push(Span::styled(format!("{} ", levels >> pin & 1), style));
+            }
+            lines.push(Line::from(spans));
+        }
+        let paragraph = Paragraph::new(lines).block(Block::bordered().title(vec![Span::from(
+            "GPIO [yellow: output, cyan: driven from outside, gray: I/O function]",
+        )]));
+        frame.render_widget(paragraph, gpio_block);
+    }
+
+    /// The duty cycle of every comparator output, the outputs 1 to 3 of PWM1 can drive the LED
+    fn render_pwm(&self, pwm_block: Rect, frame: &mut Frame) {
+        let led = [Color::Green, Color::Blue, Color::Red];
+        let mut lines = Vec::with_capacity(3);
+        for controller in 0..3 {
+            let mut spans = vec![Span::from(format!("PWM{controller}"))];
+            for channel in 0..4 {
+                let text = format!(" {:>6.1}%", self.pwm.duty(controller, channel) * 100.0);
+                let style = if controller == 1 && channel > 0 {
+                    Style::new().fg(led[channel - 1])
+                } else {
+                    Style::new()
+                };
+                spans.push(Span::styled(text, style));
+            }
+            lines.push(Line::from(spans));
+        }
+        let paragraph = Paragraph::new(lines)
+            .block(Block::bordered().title(vec![Span::from("PWM duty cycles [cmp0-3]")]));
+        frame.render_widget(paragraph, pwm_block);
+    }
+
     fn render_io(&mut self, io_block: Rect, frame: &mut Frame) {
         let right_block_down = Block::bordered()
             .title(vec![Span::from("UART0 TX")])
@@ -307,6 +394,7 @@ impl ViewState {
             .constraints(
                 [
                     Constraint::Length(22),
+                    Constraint::Length(5),
                     Constraint::Fill(1),
                     Constraint::Length(3),
                 ]
@@ -315,8 +403,14 @@ impl ViewState {
             .split(chunks[1]);
 
         let register_block = right_chunks[0];
-        let io_block = right_chunks[1];
-        let input_block = right_chunks[2];
+        let peripheral_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Fill(1), Constraint::Length(42)].as_ref())
+            .split(right_chunks[1]);
+        let gpio_block = peripheral_chunks[0];
+        let pwm_block = peripheral_chunks[1];
+        let io_block = right_chunks[2];
+        let input_block = right_chunks[3];
 
         let paragraph = ViewState::instruction_log_block(log_block, cpu);
         f.render_widget(paragraph, log_block);
@@ -326,17 +420,19 @@ impl ViewState {
         let paragraph = ViewState::next_instruction_block(next_block, cpu);
         f.render_widget(paragraph, next_block);
 
-        ViewState::render_registers(register_block, &cpu.register, f);
+        ViewState::render_registers(register_block, cpu, f);
+        self.render_gpio(gpio_block, f);
+        self.render_pwm(pwm_block, f);
         self.render_io(io_block, f);
         self.render_input(input_block, f);
 
         if self.show_help {
             let block = Block::bordered().title("Help");
             let help_message = Paragraph::new(
-                "Key shortcuts:\n'a' to enable auto-step\n'h' for help\n's' to step one instruction\n'q' to quit\n'i' to enter insert mode\n  'ENTER' to send your input to the uart\n  'ESC' to leave the insert mode",
+                "Key shortcuts:\n'a' to enable auto-step\n'h' for help\n's' to step one instruction\n'n' to show the next hart\n'q' to quit\n'r' to reload the program and reset\n'i' to enter insert mode\n  'ENTER' to send your input to the uart\n  'ESC' to leave the insert mode",
             )
             .block(block);
-            let popup_area = centered_rect(60, 29, area);
+            let popup_area = centered_rect(60, 33, area);
             f.render_widget(Clear, popup_area);
             f.render_widget(help_message, popup_area);
         }
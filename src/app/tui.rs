@@ -1,12 +1,13 @@
-use crate::hifive1b::Hifive1b;
-use crate::utils::map_to_unixsocket;
+use crate::hifive1b::{GpioPins, Hifive1b, PwmDuty};
+use crate::utils::{map_to_unixsocket, IOChannel};
 use std::io;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread::{spawn, JoinHandle};
-use std::time::Duration;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, SystemTime};
 
 use crossterm::{
     event::MouseEventKind,
@@ -15,9 +16,10 @@ use crossterm::{
 };
 use ratatui::{prelude::CrosstermBackend, Terminal};
 
+use super::load_cpu;
 use super::render_tui::ViewState;
 use crate::cli;
-use crate::cpu::{create_cpu_thread, AddrBus, CPU};
+use crate::cpu::{create_cpu_thread, AddrBus, Host, CPU};
 use crate::events::{CpuJob, Event};
 
 pub enum Job {
@@ -25,6 +27,8 @@ pub enum Job {
     AutoStepOn,
     AutoStepOff,
     ReadUart(String),
+    /// Reloads the program and resets the system
+    Reset,
     Idle,
     Exit,
 }
@@ -55,14 +59,97 @@ fn create_input_thread(sender: Sender<Event>) -> JoinHandle<()> {
     spawn(move || input_thread(&sender))
 }
 
+/// How often `--watch` looks at the files of the program
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The modification times of the files, `None` for a file that is missing, e.g. while it is rebuilt
+fn modification_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|file| file.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Sends `Event::ProgramChanged` when the files changed on disk.
+/// They have to be complete and unchanged for one interval, so a build that still writes them is not loaded.
+fn create_watch_thread(paths: Vec<PathBuf>, sender: Sender<Event>) -> JoinHandle<()> {
+    spawn(move || {
+        let mut loaded = modification_times(&paths);
+        let mut previous = loaded.clone();
+        loop {
+            sleep(WATCH_INTERVAL);
+            let current = modification_times(&paths);
+            if current == previous && current != loaded && current.iter().all(Option::is_some) {
+                if sender.send(Event::ProgramChanged).is_err() {
+                    break;
+                }
+                loaded.clone_from(&current);
+            }
+            previous = current;
+        }
+    })
+}
+
+/// Reads the program again and resets the system with it, the UART pane shows how it went.
+/// The memory and with it the channels of the UARTs stay the same, so connected sockets are kept.
+fn reset<T: AddrBus>(cpu: &Arc<Mutex<CPU<T>>>, config: &cli::Config, input_app: &mut ViewState) {
+    let reloaded = config.read_images().and_then(|images| {
+        cpu.lock()
+            .unwrap()
+            .reload(&images, config.entry, config.rve)
+    });
+    let message = match reloaded {
+        Ok(()) => String::from("[Reset]"),
+        Err(err) => format!("[Reset failed: {err:#}]"),
+    };
+    if !input_app.uart.is_empty() && !input_app.uart.ends_with('\n') {
+        input_app.uart.push('\n');
+    }
+    input_app.uart.push_str(&message);
+    input_app.uart.push('\n');
+}
+
+/// Connects the UART pane to the console of the host as well.
+/// The pane shows the output of both and what is typed is sent to both.
+fn share_with_console(
+    (uart_tx, uart_rx): IOChannel,
+    (console_tx, console_rx): IOChannel,
+) -> IOChannel {
+    let (pane_tx, pane_rx) = channel();
+    let (input_tx, input_rx) = channel();
+    for output in [uart_rx, console_rx] {
+        let pane_tx = pane_tx.clone();
+        spawn(move || {
+            while let Ok(data) = output.recv() {
+                if pane_tx.send(data).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    spawn(move || {
+        while let Ok(data) = input_rx.recv() {
+            let _ = uart_tx.send(data);
+            let _ = console_tx.send(data);
+        }
+    });
+    (input_tx, pane_rx)
+}
+
 fn event_loop_tui<T: AddrBus>(
+    config: &cli::Config,
     input: &Receiver<Event>,
     cpu: &Arc<Mutex<CPU<T>>>,
     cpu_sender: &Sender<CpuJob>,
-    uart_rx: &Receiver<u8>,
-    uart_tx: &Sender<u8>,
+    (uart_tx, uart_rx): &IOChannel,
+    gpio_pins: GpioPins,
+    pwm_duty: PwmDuty,
 ) -> anyhow::Result<()> {
-    let mut input_app = ViewState::new();
+    let mut input_app = ViewState::new(gpio_pins, pwm_duty);
 
     // Why?
     enable_raw_mode()?;
@@ -71,8 +158,10 @@ fn event_loop_tui<T: AddrBus>(
     let _ = terminal.clear();
 
     {
-        let cpu = cpu.lock().unwrap();
-        terminal.draw(|f| input_app.ui(f, &cpu))?;
+        let mut cpu = cpu.lock().unwrap();
+        cpu.with_hart(input_app.hart, |cpu| {
+            terminal.draw(|f| input_app.ui(f, cpu))
+        })?;
     }
 
     loop {
@@ -94,6 +183,7 @@ fn event_loop_tui<T: AddrBus>(
                     cpu_sender.send(CpuJob::CheckInterrupts)?;
                     Job::Idle
                 }
+                Event::ProgramChanged => Job::Reset,
             },
             Err(_) => Job::Idle,
         };
@@ -115,6 +205,7 @@ fn event_loop_tui<T: AddrBus>(
             Job::AutoStepOff => {
                 cpu_sender.send(CpuJob::Stop)?;
             }
+            Job::Reset => reset(cpu, config, &mut input_app),
         }
 
         while let Ok(msg) = uart_rx.try_recv() {
@@ -126,8 +217,11 @@ fn event_loop_tui<T: AddrBus>(
         }
 
         {
-            let cpu = cpu.lock().unwrap();
-            terminal.draw(|f| input_app.ui(f, &cpu))?;
+            let mut cpu = cpu.lock().unwrap();
+            input_app.hart %= cpu.hart_count();
+            cpu.with_hart(input_app.hart, |cpu| {
+                terminal.draw(|f| input_app.ui(f, cpu))
+            })?;
         }
     }
 
@@ -144,14 +238,14 @@ fn event_loop_tui<T: AddrBus>(
     Ok(())
 }
 
-pub fn tui(config: &cli::Config) {
+pub fn tui(config: &cli::Config) -> anyhow::Result<()> {
     let (_tx, mut tui_reader): (mpsc::Sender<u8>, mpsc::Receiver<u8>) = mpsc::channel();
     let (mut tui_writer, _rx): (mpsc::Sender<u8>, mpsc::Receiver<u8>) = mpsc::channel();
 
     let (event_sender, event_reader): (Sender<Event>, Receiver<Event>) = channel();
     let (cpu_sender, cpu_reader): (Sender<CpuJob>, Receiver<CpuJob>) = channel();
 
-    let mut hifive1b = Hifive1b::new(event_sender.clone());
+    let mut hifive1b = Hifive1b::new(event_sender.clone(), config.harts);
 
     let uart0 = hifive1b.uart0channel.take().unwrap();
     if let Some(path) = &config.uart0 {
@@ -167,29 +261,46 @@ pub fn tui(config: &cli::Config) {
         map_to_unixsocket(uart1, path.clone());
     }
 
-    let memory_map = hifive1b.memory.take().unwrap();
+    if let Some(path) = &config.gpio {
+        hifive1b.gpio_pins.map_to_unixsocket(path.clone());
+    }
 
-    let cpu_val = {
-        if config.bin {
-            let entry = config.entryaddress;
-            let baseaddress = config.baseaddress;
-            CPU::from_bin(&config.file, memory_map, entry, baseaddress)
-        } else {
-            CPU::from_elf(&config.file, memory_map)
-        }
-    };
+    let memory_map = hifive1b.memory.take().unwrap();
+    let mut cpu_val = load_cpu(config, memory_map, false)?;
+    if config.semihosting || config.syscalls {
+        let (console, host) = Host::new(
+            config.sandbox.clone(),
+            config.arguments.clone(),
+            config.environment.clone(),
+            config.semihosting,
+            config.syscalls,
+        );
+        (tui_writer, tui_reader) = share_with_console((tui_writer, tui_reader), console);
+        cpu_val.set_host(host)?;
+    }
 
     let cpu = Arc::new(Mutex::new(cpu_val));
 
     create_input_thread(event_sender.clone());
+    if config.watch {
+        let paths = config
+            .images
+            .iter()
+            .map(|image| image.path.clone())
+            .collect();
+        create_watch_thread(paths, event_sender.clone());
+    }
     create_cpu_thread(&Arc::clone(&cpu), event_sender, cpu_reader);
     if let Err(e) = event_loop_tui(
+        config,
         &event_reader,
         &Arc::clone(&cpu),
         &cpu_sender,
-        &tui_reader,
-        &tui_writer,
+        &(tui_writer, tui_reader),
+        hifive1b.gpio_pins,
+        hifive1b.pwm_duty,
     ) {
         println!("{e}");
     }
+    Ok(())
 }
@@ -1,10 +1,14 @@
 use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
 
 use anyhow::anyhow;
 use anyhow::Context;
 use clap::Parser;
 
+use crate::cpu::{Engine, Format, Image};
+
 #[derive(Parser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
     /// If set, no TUI is started.
     ///
@@ -13,6 +17,18 @@ struct Args {
     #[arg(long, default_value_t = false, verbatim_doc_comment)]
     headless: bool,
 
+    /// If set, the program is reloaded and the system reset whenever the file or one of the images changes on disk.
+    ///
+    /// Like pressing `r` in the TUI, UART sockets stay connected. A file is loaded once it was not written to
+    /// for a moment, so the build has finished. Only available with the TUI.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with("headless"),
+        verbatim_doc_comment
+    )]
+    watch: bool,
+
     /// If set, connects UART0 TX/RX to the specified unix socket.
     /// If not set, the UART0 will be mapped to stdio.
     #[arg(long, verbatim_doc_comment)]
@@ -23,6 +39,21 @@ struct Args {
     #[arg(long, verbatim_doc_comment)]
     uart1: Option<std::path::PathBuf>,
 
+    /// If set, the UARTs send and receive with the baud rate set by their `div` register.
+    ///
+    /// Every frame takes as many core cycles as it would on the Hifive1b, so the FIFOs fill up.
+    /// Without pacing, frames are exchanged as fast as possible.
+    #[arg(long, default_value_t = false, verbatim_doc_comment)]
+    uart_pacing: bool,
+
+    /// If set, the GPIO pins can be controlled through the specified unix socket.
+    ///
+    /// Every line is one command and is answered with one line:
+    /// `set <pin> <0|1>` drives the pin from outside, e.g. to press a button, `release <pin>` lets it float again,
+    /// `get [pin]` reads the level of one or all pins and `led` the state of the red, green and blue LED.
+    #[arg(long, value_name = "SOCKET", verbatim_doc_comment)]
+    gpio: Option<std::path::PathBuf>,
+
     /// If set, the emulation result will be checked.
     ///
     /// TRIOPS will probe the registers according to the riscv-software-src/riscv-tests.
@@ -31,17 +62,31 @@ struct Args {
     #[arg(long, default_value_t = false, verbatim_doc_comment)]
     testing: bool,
 
+    /// If set, the number of executed instructions and the execution speed are reported.
+    ///
+    /// Only available without the TUI, see `tests/bench.sh`.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires("headless"),
+        verbatim_doc_comment
+    )]
+    benchmark: bool,
+
     /// If set, the provided file is treated as pure binary
     ///
-    /// When used, the entry address and base address can also be set.
+    /// When used, the base address can also be set.
+    /// Without the flag, files ending in `.hex` or `.ihex` are Intel HEX files, files ending in `.srec`, `.s19`,
+    /// `.s28`, `.s37` or `.mot` are Motorola S-record files and all others are ELF files.
     #[arg(long, default_value_t = false, verbatim_doc_comment)]
     bin: bool,
 
     /// The entry address, where execution is started / PC is set to.
     ///
+    /// Overrides the entry address of the file, a bin file is entered at its base address.
     /// Can be in hex or decimal.
-    #[arg(long, default_value_t = String::from("0x20000000"), requires("bin"))]
-    entryaddress: String,
+    #[arg(long, visible_alias = "entryaddress", verbatim_doc_comment)]
+    entry: Option<String>,
 
     /// The base address, where the bin file is loaded to. Must be in RAM or ROM.
     ///
@@ -49,60 +94,250 @@ struct Args {
     #[arg(long, default_value_t = String::from("0x20000000"), requires("bin"))]
     baseaddress: String,
 
+    /// An additional image that is loaded after the file. Can be repeated.
+    ///
+    /// ELF, Intel HEX and S-record images are told apart like the file, `FILE@ADDR` loads a bin file at `ADDR`.
+    /// E.g. the file is a bootloader and the image the application in a slot of the flash:
+    /// `triops riotboot.elf --image slot0.bin@0x20001000`. The entry address is the one of the file.
+    #[arg(long = "image", value_name = "FILE[@ADDR]", verbatim_doc_comment)]
+    images: Vec<String>,
+
+    /// If set, the CPU implements the RV32E base ISA with only 16 registers.
+    ///
+    /// ELF files built for RV32E select it on their own, this is mainly for bin files.
+    #[arg(long, default_value_t = false, verbatim_doc_comment)]
+    rve: bool,
+
+    /// The number of harts that share the memory of the Hifive1b.
+    ///
+    /// All harts start at the entry address, they can be told apart by `mhartid`.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=1024), verbatim_doc_comment)]
+    harts: u32,
+
+    /// The number of instructions a hart executes before the next hart is scheduled.
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..), verbatim_doc_comment)]
+    quantum: u32,
+
+    /// How instructions are executed.
+    ///
+    /// The block engine executes basic blocks of instructions lowered to micro-ops and only takes interrupts between blocks.
+    #[arg(long, value_enum, default_value_t = Engine::Interpreter, verbatim_doc_comment)]
+    engine: Engine,
+
+    /// If set, the block engine is checked against the interpreter.
+    ///
+    /// A second, hidden emulator runs the program with the interpreter in lockstep.
+    /// The state of both is compared after every block, the first difference ends the emulation.
+    /// Only available without the TUI, the second emulator replays the frames the UARTs of the first receive.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires("headless"),
+        verbatim_doc_comment
+    )]
+    lockstep: bool,
+
+    /// If set, the SPI flash is backed by the specified image file.
+    ///
+    /// The flash is loaded from the image and firmware that programs or erases the flash writes back to it,
+    /// so its content persists across runs. The image is created if it does not exist.
+    /// A program loaded into the flash address range is not written to the image.
+    #[arg(long, value_name = "IMAGE", verbatim_doc_comment)]
+    flash: Option<std::path::PathBuf>,
+
+    /// If set, an SD card backed by the specified disk image is connected to chip select 0 of SPI1.
+    ///
+    /// The card is a high capacity card of the image size, rounded down to a multiple of 512 KiB.
+    /// Blocks written by the firmware are written to the image.
+    #[arg(long, value_name = "IMAGE", verbatim_doc_comment)]
+    sd_card: Option<std::path::PathBuf>,
+
+    /// If set, the 24C256 EEPROM on the I2C bus is backed by the specified image file.
+    ///
+    /// Like the flash image, it is created if it does not exist and the writes of the firmware persist.
+    /// Without an image, the EEPROM at address 0x50 starts erased.
+    #[arg(long, value_name = "IMAGE", verbatim_doc_comment)]
+    eeprom: Option<std::path::PathBuf>,
+
+    /// The temperature in degrees Celsius that the LM75 sensor at I2C address 0x48 reports.
+    #[arg(
+        long,
+        default_value_t = 25.0,
+        allow_negative_numbers = true,
+        verbatim_doc_comment
+    )]
+    temperature: f64,
+
+    /// If set, the semihosting calls of the program are served by the host.
+    ///
+    /// The console `:tt` shares stdio with UART0, in the TUI the UART pane. Files are opened in the `sandbox`.
+    /// The exit code passed to `SYS_EXIT` becomes the one of TRIOPS, without the TUI.
+    #[arg(long, default_value_t = false, verbatim_doc_comment)]
+    semihosting: bool,
+
+    /// If set, `ecall` makes a Linux system call that is served by the host, instead of taking a trap.
+    ///
+    /// Like with `qemu-user`, programs linked with newlib run without a BSP: `printf` writes to the console,
+    /// `malloc` gets its heap through `brk` and files are opened in the `sandbox`.
+    /// The RAM is replaced by a flat one from 0x10000 up to 0x2000000, where newlib links programs by default.
+    /// The heap follows the program, the stack starts at the end of the RAM. Supported are `openat`, `close`, `read`, `write`, `fstat`,
+    /// `exit`, `exit_group`, `gettimeofday` and `brk`, the exit code becomes the one of TRIOPS.
+    #[arg(long, default_value_t = false, verbatim_doc_comment)]
+    syscalls: bool,
+
+    /// The directory the files of semihosting and system calls are opened in.
+    ///
+    /// Absolute paths and paths with `..` are refused.
+    #[arg(long, value_name = "DIR", default_value = ".", verbatim_doc_comment)]
+    sandbox: std::path::PathBuf,
+
+    /// A variable of the environment of the program, as `KEY=VALUE`. Can be repeated.
+    ///
+    /// Like the arguments, the environment is placed on the initial stack with semihosting or system calls.
+    #[arg(long = "env", value_name = "KEY=VALUE", verbatim_doc_comment)]
+    environment: Vec<String>,
+
     /// Path to the file that should be executed in the emulator
     file: std::path::PathBuf,
+
+    /// The arguments of the program, after `--`.
+    ///
+    /// With semihosting or system calls, the stack starts at the end of the RAM with `argc`, `argv`,
+    /// the environment and an auxiliary vector like on Linux, `a0` and `a1` hold `argc` and `argv`.
+    /// `argv[0]` is the path of the file, `SYS_GETCMDLINE` returns all of them separated by spaces.
+    #[arg(last = true, value_name = "ARGS", verbatim_doc_comment)]
+    arguments: Vec<String>,
 }
 
 /// Little wrapper to do some conversions outside of main
 /// Longterm goal is having a `Config` struct that can be used to save & replay the emulator
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     pub headless: bool,
+    pub watch: bool,
     pub uart0: Option<std::path::PathBuf>,
     pub uart1: Option<std::path::PathBuf>,
+    pub uart_pacing: bool,
+    pub gpio: Option<std::path::PathBuf>,
     pub testing: bool,
-    pub bin: bool,
-    pub entryaddress: usize,
-    pub baseaddress: usize,
-    pub file: Vec<u8>,
+    pub benchmark: bool,
+    /// The file first, followed by the additional images
+    pub images: Vec<Image>,
+    pub entry: Option<u64>,
+    pub rve: bool,
+    pub harts: usize,
+    pub quantum: usize,
+    pub engine: Engine,
+    pub lockstep: bool,
+    pub flash: Option<std::path::PathBuf>,
+    pub sd_card: Option<std::path::PathBuf>,
+    pub eeprom: Option<std::path::PathBuf>,
+    pub temperature: f64,
+    pub semihosting: bool,
+    pub syscalls: bool,
+    pub sandbox: std::path::PathBuf,
+    /// The path of the program followed by its arguments
+    pub arguments: Vec<String>,
+    pub environment: Vec<String>,
 }
 
 impl Config {
     pub fn parse() -> anyhow::Result<Self> {
         let args = Args::parse();
         let path = args.file;
-        let file =
-            std::fs::read(&path).context(format!("Could not read file {}", path.display()))?;
+        let format = if args.bin {
+            Format::Bin
+        } else {
+            format_of(&path)
+        };
+        let mut images = vec![read_image(
+            &path,
+            format,
+            usize_from_str(&args.baseaddress)?,
+        )?];
+        for image in &args.images {
+            images.push(match image.rsplit_once('@') {
+                Some((file, addr)) if usize_from_str(addr).is_ok() => {
+                    read_image(file.as_ref(), Format::Bin, usize_from_str(addr)?)?
+                }
+                _ => read_image(image.as_ref(), format_of(image.as_ref()), 0)?,
+            });
+        }
+
+        clear_socket(args.uart0.as_ref(), "UART")?;
+        clear_socket(args.uart1.as_ref(), "UART")?;
+        clear_socket(args.gpio.as_ref(), "GPIO")?;
 
-        clear_socket(args.uart0.as_ref())?;
-        clear_socket(args.uart1.as_ref())?;
+        if let Some(variable) = args
+            .environment
+            .iter()
+            .find(|variable| !variable.contains('='))
+        {
+            return Err(anyhow!(std::io::ErrorKind::InvalidInput)).context(format!(
+                "The environment variable has no value, expected KEY=VALUE: {variable}"
+            ));
+        }
 
-        let entryaddress = usize_from_str(&args.entryaddress);
-        let baseaddress = usize_from_str(&args.baseaddress);
+        if (args.semihosting || args.syscalls) && !args.sandbox.is_dir() {
+            return Err(anyhow!(std::io::ErrorKind::NotFound)).context(format!(
+                "The sandbox directory does not exist: {}",
+                args.sandbox.display()
+            ));
+        }
+
+        let mut arguments = vec![path.display().to_string()];
+        arguments.extend(args.arguments);
+
+        let entry = args.entry.map(|entry| usize_from_str(&entry)).transpose()?;
         Ok(Self {
             headless: args.headless,
+            watch: args.watch,
             uart0: args.uart0,
             uart1: args.uart1,
+            uart_pacing: args.uart_pacing,
+            gpio: args.gpio,
             testing: args.testing,
-            bin: args.bin,
-            entryaddress,
-            baseaddress,
-            file,
+            benchmark: args.benchmark,
+            images,
+            entry: entry.map(|entry| entry as u64),
+            rve: args.rve,
+            harts: args.harts as usize,
+            quantum: args.quantum as usize,
+            engine: args.engine,
+            lockstep: args.lockstep,
+            flash: args.flash,
+            sd_card: args.sd_card,
+            eeprom: args.eeprom,
+            temperature: args.temperature,
+            semihosting: args.semihosting,
+            syscalls: args.syscalls,
+            sandbox: args.sandbox,
+            arguments,
+            environment: args.environment,
         })
     }
+
+    /// Reads the images from disk again, e.g. after they were rebuilt
+    pub fn read_images(&self) -> anyhow::Result<Vec<Image>> {
+        self.images
+            .iter()
+            .map(|image| read_image(&image.path, image.format, image.base_address))
+            .collect()
+    }
 }
 
-fn clear_socket(uart_path: Option<&std::path::PathBuf>) -> anyhow::Result<()> {
-    if let Some(ref socket_path) = uart_path {
+fn clear_socket(path: Option<&std::path::PathBuf>, backend: &str) -> anyhow::Result<()> {
+    if let Some(ref socket_path) = path {
         if socket_path.exists() {
             let attr = std::fs::metadata(socket_path).context(format!(
-                "Unable to create unixsocket for the UART backend: {}",
+                "Unable to create unixsocket for the {backend} backend: {}",
                 socket_path.display()
             ))?;
             if attr.file_type().is_socket() {
                 let _ = std::fs::remove_file(socket_path);
             } else {
                 return Err(anyhow!(std::io::ErrorKind::AlreadyExists)).context(format!(
-                    "Unable to create unixsocket for the UART backend: {}",
+                    "Unable to create unixsocket for the {backend} backend: {}",
                     socket_path.display()
                 ));
             }
@@ -111,10 +346,33 @@ fn clear_socket(uart_path: Option<&std::path::PathBuf>) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn usize_from_str(text: &str) -> usize {
-    if text.starts_with("0x") {
-        usize::from_str_radix(text.trim_start_matches("0x"), 16).unwrap()
-    } else {
-        text.parse().unwrap()
+/// The format of a file that is not a bin file, by its extension
+fn format_of(path: &Path) -> Format {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("hex" | "ihex") => Format::Hex,
+        Some("srec" | "s19" | "s28" | "s37" | "mot") => Format::Srec,
+        _ => Format::Elf,
     }
 }
+
+fn read_image(path: &Path, format: Format, base_address: usize) -> anyhow::Result<Image> {
+    let data = std::fs::read(path).context(format!("Could not read file {}", path.display()))?;
+    Ok(Image {
+        path: path.to_path_buf(),
+        data,
+        format,
+        base_address,
+    })
+}
+
+fn usize_from_str(text: &str) -> anyhow::Result<usize> {
+    let address = if text.starts_with("0x") {
+        usize::from_str_radix(text.trim_start_matches("0x"), 16)
+    } else {
+        text.parse()
+    };
+    address.context(format!("Invalid address: {text}"))
+}
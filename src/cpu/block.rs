@@ -0,0 +1,477 @@
+//! The basic block execution engine is scoped for this file.
+//! A basic block is a run of instructions that ends with a control flow change. It is decoded
+//! once and lowered to micro-ops, handlers with their operands already taken apart, which run
+//! back to back without fetching, decoding or matching on the instruction.
+//! Interrupts are checked once before a block, the instruction log is updated after it.
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use super::memory::Access;
+use super::{AddrBus, CPU, LOG_LENGTH};
+use crate::instructions::{decode, Immediate, Instruction, Rindex};
+
+macro_rules! add_signed {
+    ($unsigned:expr, $signed:expr) => {{
+        $unsigned.wrapping_add_signed(i64::from($signed))
+    }};
+}
+
+const CACHE_SIZE: usize = 1024;
+
+/// Blocks are cut after this many instructions, which bounds the bytes a block covers
+const MAX_BLOCK_LENGTH: usize = 64;
+
+/// Blocks never cross a page boundary, so only their first address has to be translated
+const PAGE_SIZE: usize = 4096;
+
+/// The granularity the cache tracks the memory that blocks were translated from
+const LINE_SHIFT: u32 = 8;
+
+/// Executes the operation `op` of the instruction at `addr`, after `pc` was advanced past it.
+/// Returns false after executing ebreak, as `execute_fetched` does.
+type Handler<T> = fn(&mut CPU<T>, &MicroOp<T>, u64) -> anyhow::Result<bool>;
+
+/// An instruction lowered to the handler that executes it and its decoded operands
+struct MicroOp<T: AddrBus> {
+    handler: Handler<T>,
+    rd: Rindex,
+    rs1: Rindex,
+    rs2: Rindex,
+    imm: Immediate,
+    /// The distance from the start of the block, its instructions are at consecutive addresses
+    offset: usize,
+    size: usize,
+    /// Kept for the instruction log and for the handler that interprets it
+    instruction: Instruction,
+}
+
+pub struct Block<T: AddrBus> {
+    paddr: usize,
+    /// The number of bytes covered by the instructions
+    size: usize,
+    ops: Vec<MicroOp<T>>,
+}
+
+/// A direct mapped cache of blocks, indexed by the halfword of their physical address.
+/// A store to a line that holds translated code drops all blocks.
+pub struct BlockCache<T: AddrBus> {
+    entries: Vec<Option<Arc<Block<T>>>>,
+    /// The lines that blocks were translated from, by their exact number
+    code_lines: HashSet<usize>,
+    /// Counts the flushes, a running block stops once its code may have been overwritten
+    generation: u64,
+}
+
+impl<T: AddrBus> Default for BlockCache<T> {
+    fn default() -> Self {
+        Self {
+            entries: vec![None; CACHE_SIZE],
+            code_lines: HashSet::new(),
+            generation: 0,
+        }
+    }
+}
+
+impl<T: AddrBus> BlockCache<T> {
+    fn index(paddr: usize) -> usize {
+        (paddr >> 1) % CACHE_SIZE
+    }
+
+    fn get(&self, paddr: usize) -> Option<Arc<Block<T>>> {
+        match &self.entries[Self::index(paddr)] {
+            Some(block) if block.paddr == paddr => Some(Arc::clone(block)),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, block: Arc<Block<T>>) {
+        let lines = block.paddr >> LINE_SHIFT..=(block.paddr + block.size - 1) >> LINE_SHIFT;
+        self.code_lines.extend(lines);
+        let index = Self::index(block.paddr);
+        self.entries[index] = Some(block);
+    }
+
+    /// Drops all blocks if the stored bytes `[paddr, paddr + size)` may overlap one.
+    /// A 32 bit instruction that starts one halfword before the store also overlaps it.
+    pub fn invalidate(&mut self, paddr: usize, size: usize) {
+        let first = paddr.saturating_sub(2) >> LINE_SHIFT;
+        let last = (paddr + size - 1) >> LINE_SHIFT;
+        if (first..=last).any(|line| self.code_lines.contains(&line)) {
+            self.flush();
+        }
+    }
+
+    /// Used by `FENCE.I`, drops all blocks
+    pub fn flush(&mut self) {
+        self.entries.fill(None);
+        self.code_lines.clear();
+        self.generation += 1;
+    }
+}
+
+/// Defines the handler of an instruction that computes `rd` from two registers.
+macro_rules! register_op {
+    ($name:ident, |$rs1:ident, $rs2:ident, $xlen:ident| $value:expr) => {
+        fn $name<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, _: u64) -> anyhow::Result<bool> {
+            let $xlen = cpu.register.csr.xlen;
+            let $rs1 = cpu.register.read(op.rs1);
+            let $rs2 = cpu.register.read(op.rs2);
+            cpu.register.write(op.rd, $value);
+            Ok(true)
+        }
+    };
+}
+
+/// Defines the handler of an instruction that computes `rd` from a register and the immediate.
+macro_rules! immediate_op {
+    ($name:ident, |$rs1:ident, $imm:ident, $xlen:ident| $value:expr) => {
+        fn $name<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, _: u64) -> anyhow::Result<bool> {
+            let $xlen = cpu.register.csr.xlen;
+            let $rs1 = cpu.register.read(op.rs1);
+            let $imm = op.imm;
+            cpu.register.write(op.rd, $value);
+            Ok(true)
+        }
+    };
+}
+
+/// Defines the handler of a conditional branch relative to the instruction.
+macro_rules! branch {
+    ($name:ident, |$rs1:ident, $rs2:ident, $xlen:ident| $taken:expr) => {
+        fn $name<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, addr: u64) -> anyhow::Result<bool> {
+            let $xlen = cpu.register.csr.xlen;
+            let $rs1 = cpu.register.read(op.rs1);
+            let $rs2 = cpu.register.read(op.rs2);
+            if $taken {
+                cpu.register.pc = $xlen.truncate(add_signed!(addr, op.imm));
+            }
+            Ok(true)
+        }
+    };
+}
+
+/// Defines the handler of a load, `$extend` widens the loaded `value` to 64 bit.
+macro_rules! load {
+    ($name:ident, $load:ident, |$value:ident| $extend:expr) => {
+        fn $name<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, _: u64) -> anyhow::Result<bool> {
+            let rs1 = cpu.register.read(op.rs1);
+            let target = cpu.register.csr.xlen.truncate(add_signed!(rs1, op.imm)) as usize;
+            let $value = cpu.$load(target)?;
+            cpu.register.write(op.rd, $extend);
+            Ok(true)
+        }
+    };
+}
+
+/// Defines the handler of a store of the low bytes of `rs2`.
+macro_rules! store {
+    ($name:ident, $store:ident) => {
+        fn $name<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, _: u64) -> anyhow::Result<bool> {
+            let rs1 = cpu.register.read(op.rs1);
+            let rs2 = cpu.register.read(op.rs2);
+            let target = cpu.register.csr.xlen.truncate(add_signed!(rs1, op.imm)) as usize;
+            cpu.$store(target, rs2 as u32)?;
+            Ok(true)
+        }
+    };
+}
+
+register_op!(add, |rs1, rs2, _xlen| rs1.wrapping_add(rs2));
+register_op!(sub, |rs1, rs2, _xlen| rs1.wrapping_sub(rs2));
+register_op!(sll, |rs1, rs2, xlen| rs1 << xlen.shamt(rs2));
+register_op!(slt, |rs1, rs2, xlen| u64::from(
+    xlen.signed(rs1) < xlen.signed(rs2)
+));
+register_op!(sltu, |rs1, rs2, _xlen| u64::from(rs1 < rs2));
+register_op!(xor, |rs1, rs2, _xlen| rs1 ^ rs2);
+register_op!(srl, |rs1, rs2, xlen| rs1 >> xlen.shamt(rs2));
+register_op!(sra, |rs1, rs2, xlen| (xlen.signed(rs1) >> xlen.shamt(rs2))
+    as u64);
+register_op!(or, |rs1, rs2, _xlen| rs1 | rs2);
+register_op!(and, |rs1, rs2, _xlen| rs1 & rs2);
+
+immediate_op!(addi, |rs1, imm, _xlen| add_signed!(rs1, imm));
+immediate_op!(slti, |rs1, imm, xlen| u64::from(
+    xlen.signed(rs1) < i64::from(imm)
+));
+immediate_op!(sltiu, |rs1, imm, xlen| u64::from(
+    rs1 < xlen.truncate(i64::from(imm) as u64)
+));
+immediate_op!(xori, |rs1, imm, _xlen| rs1 ^ i64::from(imm) as u64);
+immediate_op!(ori, |rs1, imm, _xlen| rs1 | i64::from(imm) as u64);
+immediate_op!(andi, |rs1, imm, _xlen| rs1 & i64::from(imm) as u64);
+immediate_op!(slli, |rs1, imm, xlen| rs1 << xlen.shamt(imm as u64));
+immediate_op!(srli, |rs1, imm, xlen| rs1 >> xlen.shamt(imm as u64));
+immediate_op!(
+    srai,
+    |rs1, imm, xlen| (xlen.signed(rs1) >> xlen.shamt(imm as u64)) as u64
+);
+
+branch!(beq, |rs1, rs2, _xlen| rs1 == rs2);
+branch!(bne, |rs1, rs2, _xlen| rs1 != rs2);
+branch!(blt, |rs1, rs2, xlen| xlen.signed(rs1) < xlen.signed(rs2));
+branch!(bge, |rs1, rs2, xlen| xlen.signed(rs1) >= xlen.signed(rs2));
+branch!(bltu, |rs1, rs2, _xlen| rs1 < rs2);
+branch!(bgeu, |rs1, rs2, _xlen| rs1 >= rs2);
+
+load!(lb, load_byte, |value| i64::from(value as i8) as u64);
+load!(lh, load_halfword, |value| i64::from(value as i16) as u64);
+load!(lw, load_word, |value| i64::from(value as i32) as u64);
+load!(lbu, load_byte, |value| u64::from(value));
+load!(lhu, load_halfword, |value| u64::from(value));
+
+store!(sb, store_byte);
+store!(sh, store_halfword);
+store!(sw, store_word);
+
+fn lui<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, _: u64) -> anyhow::Result<bool> {
+    cpu.register.write(op.rd, i64::from(op.imm) as u64);
+    Ok(true)
+}
+
+fn auipc<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, addr: u64) -> anyhow::Result<bool> {
+    cpu.register.write(op.rd, add_signed!(addr, op.imm));
+    Ok(true)
+}
+
+fn jal<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, addr: u64) -> anyhow::Result<bool> {
+    cpu.register.write(op.rd, cpu.register.pc);
+    cpu.register.pc = cpu.register.csr.xlen.truncate(add_signed!(addr, op.imm));
+    Ok(true)
+}
+
+fn jalr<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, _: u64) -> anyhow::Result<bool> {
+    let rs1 = cpu.register.read(op.rs1);
+    let target = cpu.register.csr.xlen.truncate(add_signed!(rs1, op.imm)) & !0b1;
+    cpu.register.write(op.rd, cpu.register.pc);
+    cpu.register.pc = target;
+    Ok(true)
+}
+
+/// All other instructions are left to the interpreter, which expects `pc` at the instruction.
+fn interpret<T: AddrBus>(cpu: &mut CPU<T>, op: &MicroOp<T>, addr: u64) -> anyhow::Result<bool> {
+    cpu.register.pc = addr;
+    cpu.execute_unlogged(addr as usize, op.instruction)
+}
+
+impl<T: AddrBus> MicroOp<T> {
+    /// Lowers the instruction `offset` bytes into the block, on a hart with `registers` registers.
+    /// Compressed instructions are expanded, unless the interpreter does not support them.
+    /// Instructions that name a register the hart lacks are left to the interpreter to reject.
+    fn lower(instruction: Instruction, offset: usize, registers: usize) -> Self {
+        let size = if instruction.is_compressed() { 2 } else { 4 };
+        let expanded = match instruction {
+            Instruction::CFLD(..)
+            | Instruction::CLQ(..)
+            | Instruction::CFLW(..)
+            | Instruction::CFSD(..)
+            | Instruction::CSQ(..)
+            | Instruction::CFSW(..)
+            | Instruction::CNOP(..)
+            | Instruction::CFLDSP(..)
+            | Instruction::CFLWSP(..)
+            | Instruction::CFSDSP(..)
+            | Instruction::CFSWSP(..) => None,
+            _ if instruction.is_compressed() => Some(instruction.decompress()),
+            _ => Some(instruction),
+        }
+        .filter(|expanded| {
+            expanded
+                .registers()
+                .into_iter()
+                .all(|index| index < registers)
+        });
+        let (handler, rd, rs1, rs2, imm): (Handler<T>, _, _, _, _) = match expanded {
+            Some(Instruction::LUI(rd, imm)) => (lui, rd, 0, 0, imm),
+            Some(Instruction::AUIPC(rd, imm)) => (auipc, rd, 0, 0, imm),
+            Some(Instruction::JAL(rd, imm)) => (jal, rd, 0, 0, imm),
+            Some(Instruction::JALR(rd, rs1, imm)) => (jalr, rd, rs1, 0, imm),
+            Some(Instruction::BEQ(rs1, rs2, imm)) => (beq, 0, rs1, rs2, imm),
+            Some(Instruction::BNE(rs1, rs2, imm)) => (bne, 0, rs1, rs2, imm),
+            Some(Instruction::BLT(rs1, rs2, imm)) => (blt, 0, rs1, rs2, imm),
+            Some(Instruction::BGE(rs1, rs2, imm)) => (bge, 0, rs1, rs2, imm),
+            Some(Instruction::BLTU(rs1, rs2, imm)) => (bltu, 0, rs1, rs2, imm),
+            Some(Instruction::BGEU(rs1, rs2, imm)) => (bgeu, 0, rs1, rs2, imm),
+            Some(Instruction::LB(rd, rs1, imm)) => (lb, rd, rs1, 0, imm),
+            Some(Instruction::LH(rd, rs1, imm)) => (lh, rd, rs1, 0, imm),
+            Some(Instruction::LW(rd, rs1, imm)) => (lw, rd, rs1, 0, imm),
+            Some(Instruction::LBU(rd, rs1, imm)) => (lbu, rd, rs1, 0, imm),
+            Some(Instruction::LHU(rd, rs1, imm)) => (lhu, rd, rs1, 0, imm),
+            Some(Instruction::SB(rs1, rs2, imm)) => (sb, 0, rs1, rs2, imm),
+            Some(Instruction::SH(rs1, rs2, imm)) => (sh, 0, rs1, rs2, imm),
+            Some(Instruction::SW(rs1, rs2, imm)) => (sw, 0, rs1, rs2, imm),
+            Some(Instruction::ADDI(rd, rs1, imm)) => (addi, rd, rs1, 0, imm),
+            Some(Instruction::SLTI(rd, rs1, imm)) => (slti, rd, rs1, 0, imm),
+            Some(Instruction::SLTIU(rd, rs1, imm)) => (sltiu, rd, rs1, 0, imm),
+            Some(Instruction::XORI(rd, rs1, imm)) => (xori, rd, rs1, 0, imm),
+            Some(Instruction::ORI(rd, rs1, imm)) => (ori, rd, rs1, 0, imm),
+            Some(Instruction::ANDI(rd, rs1, imm)) => (andi, rd, rs1, 0, imm),
+            Some(Instruction::SLLI(rd, rs1, imm)) => (slli, rd, rs1, 0, imm),
+            Some(Instruction::SRLI(rd, rs1, imm)) => (srli, rd, rs1, 0, imm),
+            Some(Instruction::SRAI(rd, rs1, imm)) => (srai, rd, rs1, 0, imm),
+            Some(Instruction::ADD(rd, rs1, rs2)) => (add, rd, rs1, rs2, 0),
+            Some(Instruction::SUB(rd, rs1, rs2)) => (sub, rd, rs1, rs2, 0),
+            Some(Instruction::SLL(rd, rs1, rs2)) => (sll, rd, rs1, rs2, 0),
+            Some(Instruction::SLT(rd, rs1, rs2)) => (slt, rd, rs1, rs2, 0),
+            Some(Instruction::SLTU(rd, rs1, rs2)) => (sltu, rd, rs1, rs2, 0),
+            Some(Instruction::XOR(rd, rs1, rs2)) => (xor, rd, rs1, rs2, 0),
+            Some(Instruction::SRL(rd, rs1, rs2)) => (srl, rd, rs1, rs2, 0),
+            Some(Instruction::SRA(rd, rs1, rs2)) => (sra, rd, rs1, rs2, 0),
+            Some(Instruction::OR(rd, rs1, rs2)) => (or, rd, rs1, rs2, 0),
+            Some(Instruction::AND(rd, rs1, rs2)) => (and, rd, rs1, rs2, 0),
+            _ => (interpret, 0, 0, 0, 0),
+        };
+        Self {
+            handler,
+            rd,
+            rs1,
+            rs2,
+            imm,
+            offset,
+            size,
+            instruction,
+        }
+    }
+}
+
+impl<T: AddrBus> CPU<T> {
+    /// Decodes and lowers the block starting at `pc`, which is located at `paddr`.
+    /// Returns `None` if not even the first instruction can be decoded, the interpreter
+    /// raises the exception for it then.
+    fn translate_block(&mut self, paddr: usize) -> Option<Arc<Block<T>>> {
+        let xlen = self.register.csr.xlen;
+        let page_end = (self.register.pc as usize | (PAGE_SIZE - 1)) + 1;
+        let mut addr = self.register.pc as usize;
+        let mut ops = Vec::new();
+        while ops.len() < MAX_BLOCK_LENGTH {
+            let offset = addr - self.register.pc as usize;
+            let Ok(low) = self.memory.read_halfword(paddr + offset) else {
+                break;
+            };
+            let word = if low & 0b11 != 0b11 {
+                low
+            } else if addr + 4 <= page_end {
+                let Ok(high) = self.memory.read_halfword(paddr + offset + 2) else {
+                    break;
+                };
+                high << 16 | low
+            } else {
+                break;
+            };
+            let Ok(instruction) = decode(word, xlen) else {
+                break;
+            };
+            let op = MicroOp::lower(instruction, offset, self.register.count());
+            addr += op.size;
+            ops.push(op);
+            if instruction.is_block_end() || addr == page_end {
+                break;
+            }
+        }
+        if ops.is_empty() {
+            return None;
+        }
+        let block = Arc::new(Block {
+            paddr,
+            size: addr - self.register.pc as usize,
+            ops,
+        });
+        self.blocks.insert(Arc::clone(&block));
+        Some(block)
+    }
+
+    /// Like `step`, but executes a whole basic block of up to `limit` instructions.
+    /// Interrupts are only checked before the block, the executed instructions are logged after it.
+    /// Returns false after executing ebreak, together with the number of steps taken.
+    pub(super) fn step_block(&mut self, limit: usize) -> anyhow::Result<(bool, usize)> {
+        self.schedule();
+        self.check_reset();
+        self.check_interrupts();
+        if self.waits_for_interrupt {
+            self.advance_clock(self.remaining_quantum);
+            self.remaining_quantum = 0;
+            return Ok((true, 1));
+        }
+        let addr = self.register.pc as usize;
+        let block = match self.translate(addr, 2, Access::Execute) {
+            Ok(paddr) => self
+                .blocks
+                .get(paddr)
+                .or_else(|| self.translate_block(paddr)),
+            Err(err) => {
+                self.advance_clock(1);
+                self.remaining_quantum -= 1;
+                self.trap_exception(err, addr)?;
+                return Ok((true, 1));
+            }
+        };
+        let Some(block) = block else {
+            self.advance_clock(1);
+            self.remaining_quantum -= 1;
+            return Ok((self.execute()?, 1));
+        };
+
+        let generation = self.blocks.generation;
+        let length = block.ops.len().min(limit).min(self.remaining_quantum);
+        // If PMP permits the whole block at once, every single instruction is permitted as well.
+        // Otherwise the instructions are checked one by one, as the interpreter fetches them.
+        let permitted = self
+            .check_access(block.paddr, block.size, Access::Execute, self.privilege)
+            .is_ok();
+        let base = self.register.pc;
+        let mut steps = 0;
+        // The instructions that completed, a fetch fault or an unrecoverable error ends the
+        // block without executing its instruction
+        let mut executed = 0;
+        let mut result = Ok(true);
+        for op in &block.ops[..length] {
+            let addr = base + op.offset as u64;
+            let next = addr + op.size as u64;
+            self.advance_clock(1);
+            steps += 1;
+            if !permitted {
+                let paddr = block.paddr + op.offset;
+                let fetchable = self
+                    .check_access(paddr, 2, Access::Execute, self.privilege)
+                    .and_then(|()| {
+                        if op.size == 4 {
+                            self.check_access(paddr + 2, 2, Access::Execute, self.privilege)
+                        } else {
+                            Ok(())
+                        }
+                    });
+                if let Err(err) = fetchable {
+                    result = self.trap_exception(err, addr as usize).map(|()| true);
+                    break;
+                }
+            }
+            self.register.pc = next;
+            let outcome = (op.handler)(self, op, addr)
+                .or_else(|err| self.trap_exception(err, addr as usize).map(|()| true));
+            match outcome {
+                Ok(true) => executed += 1,
+                Ok(false) => {
+                    executed += 1;
+                    result = Ok(false);
+                    break;
+                }
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+            // Stop after an exception or once the block itself was overwritten
+            if self.register.pc != next || self.blocks.generation != generation {
+                break;
+            }
+        }
+        self.remaining_quantum -= steps;
+
+        let logged = executed.min(LOG_LENGTH);
+        self.executed_instructions += (executed - logged) as u64;
+        for op in &block.ops[executed - logged..executed] {
+            self.log_instruction(base as usize + op.offset, op.instruction);
+        }
+        result.map(|continue_exec| (continue_exec, steps))
+    }
+}
@@ -0,0 +1,56 @@
+//! The cache of decoded instructions is scoped for this file.
+//! Instructions are cached by their physical address, every store drops the entries it overlaps.
+use crate::instructions::Instruction;
+
+const CACHE_SIZE: usize = 4096;
+
+/// A direct mapped cache, indexed by the halfword of the physical address
+pub struct DecodeCache {
+    entries: Vec<Option<(usize, Instruction)>>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self {
+            entries: vec![None; CACHE_SIZE],
+        }
+    }
+}
+
+impl DecodeCache {
+    fn index(paddr: usize) -> usize {
+        (paddr >> 1) % CACHE_SIZE
+    }
+
+    pub fn get(&self, paddr: usize) -> Option<&Instruction> {
+        match &self.entries[Self::index(paddr)] {
+            Some((tag, instruction)) if *tag == paddr => Some(instruction),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, paddr: usize, instruction: Instruction) {
+        self.entries[Self::index(paddr)] = Some((paddr, instruction));
+    }
+
+    /// Drops the instructions overlapping the stored bytes `[paddr, paddr + size)`.
+    /// A 32 bit instruction that starts one halfword before the store also overlaps it.
+    pub fn invalidate(&mut self, paddr: usize, size: usize) {
+        if size >= 2 * CACHE_SIZE {
+            self.flush();
+            return;
+        }
+        let start = (paddr & !1).saturating_sub(2);
+        for addr in (start..paddr + size).step_by(2) {
+            let entry = &mut self.entries[Self::index(addr)];
+            if matches!(entry, Some((tag, _)) if *tag == addr) {
+                *entry = None;
+            }
+        }
+    }
+
+    /// Used by `FENCE.I`, drops all instructions
+    pub fn flush(&mut self) {
+        self.entries.fill(None);
+    }
+}
@@ -0,0 +1,44 @@
+//! Synchronous exceptions that are raised while executing an instruction.
+//! They travel as `anyhow::Error` up to `CPU::step()`, where the trap is taken.
+use std::fmt;
+
+use super::register::MCAUSE;
+
+#[derive(Clone, Debug)]
+pub struct Exception {
+    pub cause: MCAUSE,
+    /// Written to `mtval`, e.g. the faulting address
+    pub tval: u64,
+    /// The region of the memory map an access fault happened in
+    pub region: Option<&'static str>,
+}
+
+impl Exception {
+    pub fn new(cause: MCAUSE, tval: u64) -> Self {
+        Self {
+            cause,
+            tval,
+            region: None,
+        }
+    }
+
+    pub fn in_region(cause: MCAUSE, tval: u64, region: &'static str) -> Self {
+        Self {
+            cause,
+            tval,
+            region: Some(region),
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Exception {:?} (mtval: 0x{:08X})", self.cause, self.tval)?;
+        if let Some(region) = self.region {
+            write!(f, " in {region}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Exception {}
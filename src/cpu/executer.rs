@@ -1,22 +1,26 @@
 //! This file is scoped to a single function: `exec()`.
 use std::cmp::{max, min};
 
+use super::exception::Exception;
+use super::register::{Privilege, MCAUSE};
 use super::{AddrBus, CPU};
-use crate::instructions::{sign_extend, Instruction, RS1value, RS2value};
+use crate::instructions::{Instruction, RDindex, RS1index, RS1value, RS2index, RS2value};
 
 macro_rules! add_signed {
     ($unsigned:expr, $signed:expr) => {{
-        if $signed.is_negative() {
-            $unsigned.wrapping_sub($signed.unsigned_abs())
-        } else {
-            $unsigned.wrapping_add($signed.unsigned_abs())
-        }
+        $unsigned.wrapping_add_signed(i64::from($signed))
     }};
 }
 
+/// Results of word sized operations are sign extended, truncated again on RV32.
+fn sign_extend_word(value: u32) -> u64 {
+    i64::from(value as i32) as u64
+}
+
 impl<T: AddrBus> CPU<T> {
     /// Executes one instruction.
-    #[allow(clippy::too_many_lines)]
+    /// The divisions spell out the result of a division by zero, as the specification does.
+    #[allow(clippy::too_many_lines, clippy::manual_checked_ops)]
     pub fn exec(
         &mut self,
         // self.register: &mut Register,
@@ -34,6 +38,8 @@ impl<T: AddrBus> CPU<T> {
             "M instruction found but M is not enabled."
         );
 
+        let xlen = self.register.csr.xlen;
+
         // Compressed instructions must be decompressed first, by
         // doing so, they are expanded to a regular instruction.
         // Because the PC is advanced based on instruction size (bytes)
@@ -55,15 +61,22 @@ impl<T: AddrBus> CPU<T> {
             }
         };
 
+        if self.register.rve
+            && actual_instruction
+                .registers()
+                .into_iter()
+                .any(|index| index >= self.register.count())
+        {
+            return Err(Exception::new(MCAUSE::IllegalInstruction, 0).into());
+        }
+
         match *actual_instruction {
             Instruction::LUI(rdindex, uimmediate) => {
-                self.register.write(rdindex, uimmediate as u32);
+                self.register.write(rdindex, i64::from(uimmediate) as u64);
             }
             Instruction::AUIPC(rdindex, uimmediate) => {
-                self.register.write(
-                    rdindex,
-                    (self.register.pc - 4).wrapping_add(uimmediate as u32),
-                );
+                self.register
+                    .write(rdindex, add_signed!(instruction_address, uimmediate));
             }
             Instruction::JAL(rdindex, jimmediate) => {
                 self.register.write(rdindex, self.register.pc);
@@ -71,12 +84,15 @@ impl<T: AddrBus> CPU<T> {
                     (add_signed!(self.register.pc, jimmediate) % 2) == 0,
                     "JAL target addr not 2 byte aligned."
                 );
-                self.register.pc = add_signed!(instruction_address, jimmediate);
+                self.register.pc = xlen.truncate(add_signed!(instruction_address, jimmediate));
             }
             Instruction::JALR(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                let target = add_signed!(rs1, iimmediate) & !0b1;
-                assert!(target % 2 == 0, "JALR target addr not 4 byte aligned.");
+                let target = xlen.truncate(add_signed!(rs1, iimmediate)) & !0b1;
+                assert!(
+                    target.is_multiple_of(2),
+                    "JALR target addr not 4 byte aligned."
+                );
                 self.register.write(rdindex, self.register.pc);
                 self.register.pc = target;
             }
@@ -88,7 +104,7 @@ impl<T: AddrBus> CPU<T> {
                         (add_signed!(instruction_address, bimmediate) % 2) == 0,
                         "Branch target addr not 4 byte aligned."
                     );
-                    self.register.pc = add_signed!(instruction_address, bimmediate);
+                    self.register.pc = xlen.truncate(add_signed!(instruction_address, bimmediate));
                 }
             }
             Instruction::BNE(rs1index, rs2index, bimmediate) => {
@@ -99,29 +115,29 @@ impl<T: AddrBus> CPU<T> {
                         (add_signed!(instruction_address, bimmediate) % 2) == 0,
                         "Branch target addr not 4 byte aligned."
                     );
-                    self.register.pc = add_signed!(instruction_address, bimmediate);
+                    self.register.pc = xlen.truncate(add_signed!(instruction_address, bimmediate));
                 }
             }
             Instruction::BLT(rs1index, rs2index, bimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                if (rs1 as i32) < (rs2 as i32) {
+                if xlen.signed(rs1) < xlen.signed(rs2) {
                     assert!(
                         (add_signed!(instruction_address, bimmediate) % 2) == 0,
                         "Branch target addr not 4 byte aligned."
                     );
-                    self.register.pc = add_signed!(instruction_address, bimmediate);
+                    self.register.pc = xlen.truncate(add_signed!(instruction_address, bimmediate));
                 }
             }
             Instruction::BGE(rs1index, rs2index, bimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                if (rs1 as i32) >= (rs2 as i32) {
+                if xlen.signed(rs1) >= xlen.signed(rs2) {
                     assert!(
                         (add_signed!(instruction_address, bimmediate) % 2) == 0,
                         "Branch target addr not 4 byte aligned."
                     );
-                    self.register.pc = add_signed!(instruction_address, bimmediate);
+                    self.register.pc = xlen.truncate(add_signed!(instruction_address, bimmediate));
                 }
             }
             Instruction::BLTU(rs1index, rs2index, bimmediate) => {
@@ -132,7 +148,7 @@ impl<T: AddrBus> CPU<T> {
                         (add_signed!(instruction_address, bimmediate) % 2) == 0,
                         "Branch target addr not 4 byte aligned."
                     );
-                    self.register.pc = add_signed!(instruction_address, bimmediate);
+                    self.register.pc = xlen.truncate(add_signed!(instruction_address, bimmediate));
                 }
             }
             Instruction::BGEU(rs1index, rs2index, bimmediate) => {
@@ -143,56 +159,56 @@ impl<T: AddrBus> CPU<T> {
                         (add_signed!(instruction_address, bimmediate) % 2) == 0,
                         "Branch target addr not 4 byte aligned."
                     );
-                    self.register.pc = add_signed!(instruction_address, bimmediate);
+                    self.register.pc = xlen.truncate(add_signed!(instruction_address, bimmediate));
                 }
             }
             Instruction::LB(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                let target = add_signed!(rs1, iimmediate) as usize;
-                let value = sign_extend(self.memory.read_byte(target)?, 8);
+                let target = xlen.truncate(add_signed!(rs1, iimmediate)) as usize;
+                let value = i64::from(self.load_byte(target)? as i8) as u64;
                 self.register.write(rdindex, value);
             }
             Instruction::LH(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                let target = add_signed!(rs1, iimmediate) as usize;
-                let value = sign_extend(self.memory.read_halfword(target)?, 16);
+                let target = xlen.truncate(add_signed!(rs1, iimmediate)) as usize;
+                let value = i64::from(self.load_halfword(target)? as i16) as u64;
                 self.register.write(rdindex, value);
             }
             Instruction::LW(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                let target = add_signed!(rs1, iimmediate) as usize;
-                let value = self.memory.read_word(target)?;
+                let target = xlen.truncate(add_signed!(rs1, iimmediate)) as usize;
+                let value = i64::from(self.load_word(target)? as i32) as u64;
                 self.register.write(rdindex, value);
             }
             Instruction::LBU(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                let target = add_signed!(rs1, iimmediate) as usize;
-                let value = self.memory.read_byte(target)?;
+                let target = xlen.truncate(add_signed!(rs1, iimmediate)) as usize;
+                let value = u64::from(self.load_byte(target)?);
                 self.register.write(rdindex, value);
             }
             Instruction::LHU(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                let target = add_signed!(rs1, iimmediate) as usize;
-                let value = self.memory.read_halfword(target)?;
+                let target = xlen.truncate(add_signed!(rs1, iimmediate)) as usize;
+                let value = u64::from(self.load_halfword(target)?);
                 self.register.write(rdindex, value);
             }
             Instruction::SB(rs1index, rs2index, simmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                let target = add_signed!(rs1, simmediate) as usize;
-                self.memory.write_byte(target, rs2)?;
+                let target = xlen.truncate(add_signed!(rs1, simmediate)) as usize;
+                self.store_byte(target, rs2 as u32)?;
             }
             Instruction::SH(rs1index, rs2index, simmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                let target = add_signed!(rs1, simmediate) as usize;
-                self.memory.write_halfword(target, rs2)?;
+                let target = xlen.truncate(add_signed!(rs1, simmediate)) as usize;
+                self.store_halfword(target, rs2 as u32)?;
             }
             Instruction::SW(rs1index, rs2index, simmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                let target = add_signed!(rs1, simmediate) as usize;
-                self.memory.write_word(target, rs2)?;
+                let target = xlen.truncate(add_signed!(rs1, simmediate)) as usize;
+                self.store_word(target, rs2 as u32)?;
             }
             Instruction::ADDI(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
@@ -200,7 +216,7 @@ impl<T: AddrBus> CPU<T> {
             }
             Instruction::SLTI(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                if (rs1 as i32) < iimmediate {
+                if xlen.signed(rs1) < i64::from(iimmediate) {
                     self.register.write(rdindex, 1);
                 } else {
                     self.register.write(rdindex, 0);
@@ -208,7 +224,7 @@ impl<T: AddrBus> CPU<T> {
             }
             Instruction::SLTIU(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                if rs1 < iimmediate as u32 {
+                if rs1 < xlen.truncate(i64::from(iimmediate) as u64) {
                     self.register.write(rdindex, 1);
                 } else {
                     self.register.write(rdindex, 0);
@@ -216,30 +232,33 @@ impl<T: AddrBus> CPU<T> {
             }
             Instruction::XORI(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                self.register.write(rdindex, rs1 ^ iimmediate as u32);
+                self.register
+                    .write(rdindex, rs1 ^ i64::from(iimmediate) as u64);
             }
             Instruction::ORI(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                self.register.write(rdindex, rs1 | iimmediate as u32);
+                self.register
+                    .write(rdindex, rs1 | i64::from(iimmediate) as u64);
             }
             Instruction::ANDI(rdindex, rs1index, iimmediate) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                self.register.write(rdindex, rs1 & iimmediate as u32);
+                self.register
+                    .write(rdindex, rs1 & i64::from(iimmediate) as u64);
             }
             Instruction::SLLI(rdindex, rs1index, iimmediate) => {
-                let shamt = iimmediate & 0b1_1111;
+                let shamt = xlen.shamt(iimmediate as u64);
                 let rs1: RS1value = self.register.read(rs1index);
                 self.register.write(rdindex, rs1 << shamt);
             }
             Instruction::SRLI(rdindex, rs1index, iimmediate) => {
-                let shamt = iimmediate & 0b1_1111;
+                let shamt = xlen.shamt(iimmediate as u64);
                 let rs1: RS1value = self.register.read(rs1index);
                 self.register.write(rdindex, rs1 >> shamt);
             }
             Instruction::SRAI(rdindex, rs1index, iimmediate) => {
-                let shamt = (iimmediate & 0b1_1111) as u32;
+                let shamt = xlen.shamt(iimmediate as u64);
                 let rs1: RS1value = self.register.read(rs1index);
-                let value = sign_extend(rs1 >> shamt, 32 - shamt);
+                let value = (xlen.signed(rs1) >> shamt) as u64;
                 self.register.write(rdindex, value);
             }
             Instruction::ADD(rdindex, rs1index, rs2index) => {
@@ -255,12 +274,12 @@ impl<T: AddrBus> CPU<T> {
             Instruction::SLL(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                self.register.write(rdindex, rs1 << (rs2 & 0b1_1111));
+                self.register.write(rdindex, rs1 << xlen.shamt(rs2));
             }
             Instruction::SLT(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                if (rs1 as i32) < (rs2 as i32) {
+                if xlen.signed(rs1) < xlen.signed(rs2) {
                     self.register.write(rdindex, 1);
                 } else {
                     self.register.write(rdindex, 0);
@@ -283,14 +302,13 @@ impl<T: AddrBus> CPU<T> {
             Instruction::SRL(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                self.register.write(rdindex, rs1 >> (rs2 & 0b1_1111));
+                self.register.write(rdindex, rs1 >> xlen.shamt(rs2));
             }
             Instruction::SRA(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                let shamt = rs2 & 0b1_1111;
-                self.register
-                    .write(rdindex, sign_extend(rs1 >> shamt, 32 - shamt));
+                let value = (xlen.signed(rs1) >> xlen.shamt(rs2)) as u64;
+                self.register.write(rdindex, value);
             }
             Instruction::OR(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
@@ -304,19 +322,50 @@ impl<T: AddrBus> CPU<T> {
             }
             Instruction::FENCE(_rdindex, _rs1index, _iimmediate) => { /* Nop */ }
             Instruction::ECALL() => {
-                self.register.csr.mepc = instruction_address;
-                self.register.csr.mcause = 11; // Environment call from M-Mode
-                self.register.pc = self.register.csr.mtvec;
+                let cause = match self.privilege {
+                    Privilege::User => MCAUSE::EcallFromUser,
+                    Privilege::Supervisor => MCAUSE::EcallFromSupervisor,
+                    Privilege::Machine => MCAUSE::EcallFromMachine,
+                };
+                return Err(Exception::new(cause, 0).into());
             }
             Instruction::EBREAK() => { /* Nop */ }
             Instruction::MRET() => {
+                if self.privilege < Privilege::Machine {
+                    return Err(Exception::new(MCAUSE::IllegalInstruction, 0).into());
+                }
+                self.register
+                    .csr
+                    .mstatus_set_mie(self.register.csr.mstatus_get_mpie());
+                self.register.csr.mstatus_set_mpie(true);
+                self.privilege = self.register.csr.mstatus_get_mpp();
+                self.register.csr.mstatus_set_mpp(Privilege::User);
+                if self.privilege != Privilege::Machine {
+                    self.register.csr.mstatus_set_mprv(false);
+                }
                 self.register.pc = self.register.csr.mepc;
             }
+            Instruction::SRET() => {
+                if self.privilege < Privilege::Supervisor {
+                    return Err(Exception::new(MCAUSE::IllegalInstruction, 0).into());
+                }
+                self.register
+                    .csr
+                    .mstatus_set_sie(self.register.csr.mstatus_get_spie());
+                self.register.csr.mstatus_set_spie(true);
+                self.privilege = self.register.csr.mstatus_get_spp();
+                self.register.csr.mstatus_set_spp(Privilege::User);
+                self.register.csr.mstatus_set_mprv(false);
+                self.register.pc = self.register.csr.sepc;
+            }
             Instruction::CSRRW(rd_index, rs1, i_imm) => {
+                self.check_csr_access(i_imm, true)?;
+                let rs1_value = self.register.read(rs1);
                 self.register.write(rd_index, self.register.csr.read(i_imm));
-                self.register.csr.write(i_imm, self.register.read(rs1));
+                self.register.csr.write(i_imm, rs1_value);
             }
             Instruction::CSRRS(rd_index, rs1, i_imm) => {
+                self.check_csr_access(i_imm, rs1 != 0)?;
                 let csr_value = self.register.csr.read(i_imm);
                 self.register.write(rd_index, csr_value);
                 if rs1 != 0 {
@@ -326,6 +375,7 @@ impl<T: AddrBus> CPU<T> {
                 }
             }
             Instruction::CSRRC(rd_index, rs1, i_imm) => {
+                self.check_csr_access(i_imm, rs1 != 0)?;
                 let csr_value = self.register.csr.read(i_imm);
                 self.register.write(rd_index, csr_value);
                 if rs1 != 0 {
@@ -335,16 +385,18 @@ impl<T: AddrBus> CPU<T> {
                 }
             }
             Instruction::CSRRWI(rd_index, rs1, i_imm) => {
+                self.check_csr_access(i_imm, true)?;
                 // rs1 is actual an immediate
-                let uimm = u32::try_from(rs1).unwrap();
+                let uimm = u64::try_from(rs1).unwrap();
                 if rd_index != 0 {
                     self.register.write(rd_index, self.register.csr.read(i_imm));
                 }
                 self.register.csr.write(i_imm, uimm);
             }
             Instruction::CSRRSI(rd_index, rs1, i_imm) => {
+                self.check_csr_access(i_imm, rs1 != 0)?;
                 // rs1 is actual an immediate
-                let uimm = u32::try_from(rs1).unwrap();
+                let uimm = u64::try_from(rs1).unwrap();
                 let csr_value = self.register.csr.read(i_imm);
                 self.register.write(rd_index, csr_value);
                 if uimm != 0 {
@@ -352,8 +404,9 @@ impl<T: AddrBus> CPU<T> {
                 }
             }
             Instruction::CSRRCI(rd_index, rs1, i_imm) => {
+                self.check_csr_access(i_imm, rs1 != 0)?;
                 // rs1 is actual an immediate
-                let uimm = u32::try_from(rs1).unwrap();
+                let uimm = u64::try_from(rs1).unwrap();
                 let csr_value = self.register.csr.read(i_imm);
                 self.register.write(rd_index, csr_value);
                 if uimm != 0 {
@@ -363,51 +416,44 @@ impl<T: AddrBus> CPU<T> {
             Instruction::MUL(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                // Rust panics if the result of the multiplication overflows.
-                // The RISC-V spec doesn't care and just stores the low 32 bits
-                // For this reason, the multiplication is done on 64-bit numbers and then typecasted.
-                let rs1_64 = u64::from(rs1);
-                let rs2_64 = u64::from(rs2);
-                self.register.write(rdindex, (rs1_64 * rs2_64) as u32);
+                // Only the low XLEN bits of the product are stored
+                self.register.write(rdindex, rs1.wrapping_mul(rs2));
             }
             Instruction::MULH(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                let result: i64 = (i64::from(rs1 as i32)) * (i64::from(rs2 as i32));
-                let high_bytes: u32 = (result >> 32) as u32;
-                self.register.write(rdindex, high_bytes);
+                // The high half of the product needs twice the XLEN to be computed
+                let result = i128::from(xlen.signed(rs1)) * i128::from(xlen.signed(rs2));
+                self.register.write(rdindex, (result >> xlen.bits()) as u64);
             }
             Instruction::MULHSU(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                let result = i64::from(rs1 as i32).wrapping_mul(i64::from(rs2));
-                let high_bytes: u32 = (result >> 32) as u32;
-                self.register.write(rdindex, high_bytes);
+                let result = i128::from(xlen.signed(rs1)) * i128::from(rs2);
+                self.register.write(rdindex, (result >> xlen.bits()) as u64);
             }
             Instruction::MULHU(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
-                let result: u64 = u64::from(rs1) * u64::from(rs2);
-                let high_bytes: u32 = (result >> 32) as u32;
-                self.register.write(rdindex, high_bytes);
+                let result = u128::from(rs1) * u128::from(rs2);
+                self.register.write(rdindex, (result >> xlen.bits()) as u64);
             }
             Instruction::DIV(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
                 if rs2 == 0 {
                     // The spec defines that -1 should be stored.
-                    // In 32-bit two's complement, u32::MAX is -1
-                    self.register.write(rdindex, u32::MAX);
+                    self.register.write(rdindex, u64::MAX);
                 } else {
-                    let result = (rs1 as i32).overflowing_div(rs2 as i32);
-                    self.register.write(rdindex, result.0 as u32);
+                    let result = xlen.signed(rs1).overflowing_div(xlen.signed(rs2));
+                    self.register.write(rdindex, result.0 as u64);
                 }
             }
             Instruction::DIVU(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let rs2: RS2value = self.register.read(rs2index);
                 if rs2 == 0 {
-                    self.register.write(rdindex, u32::MAX);
+                    self.register.write(rdindex, u64::MAX);
                 } else {
                     self.register.write(rdindex, rs1 / rs2);
                 }
@@ -418,8 +464,8 @@ impl<T: AddrBus> CPU<T> {
                 if rs2 == 0 {
                     self.register.write(rdindex, rs1);
                 } else {
-                    let result = (rs1 as i32).overflowing_rem(rs2 as i32);
-                    self.register.write(rdindex, result.0 as u32);
+                    let result = xlen.signed(rs1).overflowing_rem(xlen.signed(rs2));
+                    self.register.write(rdindex, result.0 as u64);
                 }
             }
             Instruction::REMU(rdindex, rs1index, rs2index) => {
@@ -434,106 +480,302 @@ impl<T: AddrBus> CPU<T> {
             Instruction::LRW(rdindex, rs1index) => {
                 let rs1: RS1value = self.register.read(rs1index);
                 let addr = rs1 as usize;
-                let value = self.memory.read_word(addr)?;
-                self.register.write(rdindex, value);
-                //self.memory.reservation = Some((addr, value));
-                self.memory.set_reservation(addr, value);
+                let value = self.load_reserved(addr, 4)?;
+                self.register.write(rdindex, sign_extend_word(value as u32));
             }
             Instruction::SCW(rdindex, rs1index, rs2index) => {
                 let rs1: RS1value = self.register.read(rs1index);
-                let rs2: RS1value = self.register.read(rs2index);
+                let rs2: RS2value = self.register.read(rs2index);
                 let addr = rs1 as usize;
-
-                let value = self.memory.read_word(addr)?;
-
-                self.register.write(rdindex, 1);
-                //if let Some(reservation) = self.memory.reservation {
-                if let Some(reservation) = self.memory.get_reservation() {
-                    if reservation.0 == addr && reservation.1 == value {
-                        self.memory.write_word(addr, rs2)?;
-                        self.register.write(rdindex, 0);
-                    }
-                }
-                //self.memory.reservation = None;
-                self.memory.del_reservation();
+                let stored = self.store_conditional(addr, 4, rs2)?;
+                self.register.write(rdindex, u64::from(!stored));
             }
             Instruction::AMOSWAPW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = org;
-                self.memory.write_word(addr_rs1 as usize, result)?;
+                self.amo_word(rdindex, rs1index, rs2index, |_data, org| org)?;
             }
             Instruction::AMOADDW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = (data as i32).wrapping_add(org as i32);
-                self.memory.write_word(addr_rs1 as usize, result as u32)?;
+                self.amo_word(rdindex, rs1index, rs2index, u32::wrapping_add)?;
             }
             Instruction::AMOXORW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = data ^ org;
-                self.memory.write_word(addr_rs1 as usize, result)?;
+                self.amo_word(rdindex, rs1index, rs2index, |data, org| data ^ org)?;
             }
             Instruction::AMOANDW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = data & org;
-                self.memory.write_word(addr_rs1 as usize, result)?;
+                self.amo_word(rdindex, rs1index, rs2index, |data, org| data & org)?;
             }
             Instruction::AMOORW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = data | org;
-                self.memory.write_word(addr_rs1 as usize, result)?;
+                self.amo_word(rdindex, rs1index, rs2index, |data, org| data | org)?;
             }
             Instruction::AMOMINW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = min(data as i32, org as i32) as u32;
-                self.memory.write_word(addr_rs1 as usize, result)?;
+                self.amo_word(rdindex, rs1index, rs2index, |data, org| {
+                    min(data as i32, org as i32) as u32
+                })?;
             }
             Instruction::AMOMAXW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = max(data as i32, org as i32) as u32;
-                self.memory.write_word(addr_rs1 as usize, result)?;
+                self.amo_word(rdindex, rs1index, rs2index, |data, org| {
+                    max(data as i32, org as i32) as u32
+                })?;
             }
             Instruction::AMOMINUW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = min(data, org);
-                self.memory.write_word(addr_rs1 as usize, result)?;
+                self.amo_word(rdindex, rs1index, rs2index, min)?;
             }
             Instruction::AMOMAXUW(rdindex, rs1index, rs2index) => {
-                let addr_rs1: RS1value = self.register.read(rs1index);
-                let org: RS2value = self.register.read(rs2index);
-                let data = self.memory.read_word(addr_rs1 as usize)?;
-                self.register.write(rdindex, data);
-                let result = max(data, org);
-                self.memory.write_word(addr_rs1 as usize, result)?;
+                self.amo_word(rdindex, rs1index, rs2index, max)?;
+            }
+            /* RV64I */
+            Instruction::LWU(rdindex, rs1index, iimmediate) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let target = add_signed!(rs1, iimmediate) as usize;
+                let value = u64::from(self.load_word(target)?);
+                self.register.write(rdindex, value);
+            }
+            Instruction::LD(rdindex, rs1index, iimmediate) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let target = add_signed!(rs1, iimmediate) as usize;
+                let value = self.load_doubleword(target)?;
+                self.register.write(rdindex, value);
+            }
+            Instruction::SD(rs1index, rs2index, simmediate) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                let target = add_signed!(rs1, simmediate) as usize;
+                self.store_doubleword(target, rs2)?;
+            }
+            Instruction::ADDIW(rdindex, rs1index, iimmediate) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let value = (rs1 as u32).wrapping_add(iimmediate as u32);
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            Instruction::SLLIW(rdindex, rs1index, iimmediate) => {
+                let shamt = iimmediate & 0b1_1111;
+                let rs1: RS1value = self.register.read(rs1index);
+                self.register
+                    .write(rdindex, sign_extend_word((rs1 as u32) << shamt));
+            }
+            Instruction::SRLIW(rdindex, rs1index, iimmediate) => {
+                let shamt = iimmediate & 0b1_1111;
+                let rs1: RS1value = self.register.read(rs1index);
+                self.register
+                    .write(rdindex, sign_extend_word((rs1 as u32) >> shamt));
+            }
+            Instruction::SRAIW(rdindex, rs1index, iimmediate) => {
+                let shamt = iimmediate & 0b1_1111;
+                let rs1: RS1value = self.register.read(rs1index);
+                let value = ((rs1 as i32) >> shamt) as u32;
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            Instruction::ADDW(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                let value = (rs1 as u32).wrapping_add(rs2 as u32);
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            Instruction::SUBW(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                let value = (rs1 as u32).wrapping_sub(rs2 as u32);
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            Instruction::SLLW(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                let value = (rs1 as u32) << (rs2 & 0b1_1111);
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            Instruction::SRLW(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                let value = (rs1 as u32) >> (rs2 & 0b1_1111);
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            Instruction::SRAW(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                let value = ((rs1 as i32) >> (rs2 & 0b1_1111)) as u32;
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            /* RV64M */
+            Instruction::MULW(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                let value = (rs1 as u32).wrapping_mul(rs2 as u32);
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            Instruction::DIVW(rdindex, rs1index, rs2index) => {
+                let rs1 = self.register.read(rs1index) as i32;
+                let rs2 = self.register.read(rs2index) as i32;
+                if rs2 == 0 {
+                    self.register.write(rdindex, u64::MAX);
+                } else {
+                    let result = rs1.overflowing_div(rs2);
+                    self.register.write(rdindex, i64::from(result.0) as u64);
+                }
+            }
+            Instruction::DIVUW(rdindex, rs1index, rs2index) => {
+                let rs1 = self.register.read(rs1index) as u32;
+                let rs2 = self.register.read(rs2index) as u32;
+                if rs2 == 0 {
+                    self.register.write(rdindex, u64::MAX);
+                } else {
+                    self.register.write(rdindex, sign_extend_word(rs1 / rs2));
+                }
+            }
+            Instruction::REMW(rdindex, rs1index, rs2index) => {
+                let rs1 = self.register.read(rs1index) as i32;
+                let rs2 = self.register.read(rs2index) as i32;
+                if rs2 == 0 {
+                    self.register.write(rdindex, i64::from(rs1) as u64);
+                } else {
+                    let result = rs1.overflowing_rem(rs2);
+                    self.register.write(rdindex, i64::from(result.0) as u64);
+                }
+            }
+            Instruction::REMUW(rdindex, rs1index, rs2index) => {
+                let rs1 = self.register.read(rs1index) as u32;
+                let rs2 = self.register.read(rs2index) as u32;
+                let value = if rs2 == 0 { rs1 } else { rs1 % rs2 };
+                self.register.write(rdindex, sign_extend_word(value));
+            }
+            /* RV64A */
+            Instruction::LRD(rdindex, rs1index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let addr = rs1 as usize;
+                let value = self.load_reserved(addr, 8)?;
+                self.register.write(rdindex, value);
+            }
+            Instruction::SCD(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                let addr = rs1 as usize;
+                let stored = self.store_conditional(addr, 8, rs2)?;
+                self.register.write(rdindex, u64::from(!stored));
+            }
+            Instruction::AMOSWAPD(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, |_data, org| org)?;
+            }
+            Instruction::AMOADDD(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, u64::wrapping_add)?;
+            }
+            Instruction::AMOXORD(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, |data, org| data ^ org)?;
+            }
+            Instruction::AMOANDD(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, |data, org| data & org)?;
+            }
+            Instruction::AMOORD(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, |data, org| data | org)?;
+            }
+            Instruction::AMOMIND(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, |data, org| {
+                    min(data as i64, org as i64) as u64
+                })?;
+            }
+            Instruction::AMOMAXD(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, |data, org| {
+                    max(data as i64, org as i64) as u64
+                })?;
+            }
+            Instruction::AMOMINUD(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, min)?;
+            }
+            Instruction::AMOMAXUD(rdindex, rs1index, rs2index) => {
+                self.amo_doubleword(rdindex, rs1index, rs2index, max)?;
+            }
+            /* Zifencei */
+            Instruction::FENCEI(_rdindex, _rs1index, _iimmediate) => {
+                self.decode_cache.flush();
+                self.blocks.flush();
+            }
+            /* Zicond */
+            Instruction::CZEROEQZ(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                self.register.write(rdindex, if rs2 == 0 { 0 } else { rs1 });
+            }
+            Instruction::CZERONEZ(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                self.register.write(rdindex, if rs2 == 0 { rs1 } else { 0 });
+            }
+            /* Zbb and Zba */
+            Instruction::SEXTB(rdindex, rs1index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                self.register.write(rdindex, i64::from(rs1 as i8) as u64);
+            }
+            Instruction::SEXTH(rdindex, rs1index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                self.register.write(rdindex, i64::from(rs1 as i16) as u64);
+            }
+            Instruction::ZEXTH(rdindex, rs1index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                self.register.write(rdindex, rs1 & 0xFFFF);
+            }
+            Instruction::ADDUW(rdindex, rs1index, rs2index) => {
+                let rs1: RS1value = self.register.read(rs1index);
+                let rs2: RS2value = self.register.read(rs2index);
+                self.register
+                    .write(rdindex, rs2.wrapping_add(rs1 & 0xFFFF_FFFF));
+            }
+            /* Zimop, the may-be-operations are not redefined and write zero */
+            Instruction::MOPR(rdindex, _rs1index, _n) => {
+                self.register.write(rdindex, 0);
+            }
+            Instruction::MOPRR(rdindex, _rs1index, _rs2index, _n) => {
+                self.register.write(rdindex, 0);
             }
             Instruction::WFI() => {
                 self.waits_for_interrupt = true;
             }
+            Instruction::SFENCEVMA(_rs1index, _rs2index) => {
+                if self.privilege < Privilege::Supervisor {
+                    return Err(Exception::new(MCAUSE::IllegalInstruction, 0).into());
+                }
+                self.tlb.flush();
+            }
             _ => todo!("{:?}", actual_instruction),
         }
         Ok(())
     }
+
+    /// Reads the word at `rs1`, stores `op(word, rs2)` and writes the old word to `rd`.
+    fn amo_word(
+        &mut self,
+        rdindex: RDindex,
+        rs1index: RS1index,
+        rs2index: RS2index,
+        op: impl Fn(u32, u32) -> u32,
+    ) -> anyhow::Result<()> {
+        let addr = self.register.read(rs1index) as usize;
+        let org = self.register.read(rs2index) as u32;
+        let data = self.amo_load_word(addr)?;
+        self.store_word(addr, op(data, org))?;
+        self.register.write(rdindex, sign_extend_word(data));
+        Ok(())
+    }
+
+    /// Like `amo_word()`, but for the doubleword AMOs of RV64.
+    fn amo_doubleword(
+        &mut self,
+        rdindex: RDindex,
+        rs1index: RS1index,
+        rs2index: RS2index,
+        op: impl Fn(u64, u64) -> u64,
+    ) -> anyhow::Result<()> {
+        let addr = self.register.read(rs1index) as usize;
+        let org = self.register.read(rs2index);
+        let data = self.amo_load_doubleword(addr)?;
+        self.store_doubleword(addr, op(data, org))?;
+        self.register.write(rdindex, data);
+        Ok(())
+    }
+
+    /// The CSR address encodes the lowest privilege level that can access it
+    /// and whether it is read-only. Violations are illegal instructions.
+    fn check_csr_access(&self, csr: u32, write: bool) -> anyhow::Result<()> {
+        let required = Privilege::from_bits(csr >> 8);
+        let read_only = (csr >> 10) & 0b11 == 0b11;
+        if self.privilege < required || (write && read_only) {
+            return Err(Exception::new(MCAUSE::IllegalInstruction, 0).into());
+        }
+        Ok(())
+    }
 }
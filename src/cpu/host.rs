@@ -0,0 +1,451 @@
+//! The services of the host that a program can call are scoped for this file.
+//! Programs call them through semihosting or through emulated system calls.
+//! Files are opened on the host, relative to a sandbox directory, and the console shares stdio with UART0.
+//! The lockstep reference does not call the host, it replays the effects of the calls of the CPU it checks.
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::time::Instant;
+
+use super::{AddrBus, Exception, Xlen, CPU};
+use crate::utils::IOChannel;
+
+/// The end of the auxiliary vector
+const AT_NULL: u64 = 0;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+/// The page size reported to the program, the one of Sv32 and Sv39
+const PAGE_SIZE: u64 = 4096;
+
+/// The `values` as little endian XLEN wide words, one after the other
+fn xlen_bytes(xlen: Xlen, values: &[u64]) -> Vec<u8> {
+    let width = (xlen.bits() / 8) as usize;
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes()[..width].to_vec())
+        .collect()
+}
+
+enum Handle {
+    ConsoleIn,
+    ConsoleOut,
+    File(File),
+}
+
+/// What the program can reach on the host
+pub(super) struct Sandbox {
+    root: PathBuf,
+    pub(super) command_line: String,
+    /// A handle is its index plus one, closed handles are reused
+    handles: Vec<Option<Handle>>,
+    output: Sender<u8>,
+    input: Receiver<u8>,
+    pub(super) start: Instant,
+}
+
+impl Sandbox {
+    /// Opens the file `name` relative to the sandbox directory, returns its handle.
+    /// Absolute paths and paths with `..` are refused, they could leave the sandbox.
+    pub(super) fn open(&mut self, name: &[u8], options: &OpenOptions) -> io::Result<u64> {
+        let path = Path::new(OsStr::from_bytes(name));
+        let sandboxed = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !sandboxed {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let file = options.open(self.root.join(path))?;
+        Ok(self.insert(Handle::File(file)))
+    }
+
+    /// Opens the console for reading or for writing, returns its handle.
+    pub(super) fn open_console(&mut self, read: bool) -> u64 {
+        self.insert(if read {
+            Handle::ConsoleIn
+        } else {
+            Handle::ConsoleOut
+        })
+    }
+
+    fn insert(&mut self, handle: Handle) -> u64 {
+        let index = if let Some(index) = self.handles.iter().position(Option::is_none) {
+            self.handles[index] = Some(handle);
+            index
+        } else {
+            self.handles.push(Some(handle));
+            self.handles.len() - 1
+        };
+        index as u64 + 1
+    }
+
+    fn handle(&mut self, handle: u64) -> io::Result<&mut Handle> {
+        usize::try_from(handle)
+            .ok()
+            .and_then(|handle| self.handles.get_mut(handle.checked_sub(1)?))
+            .and_then(Option::as_mut)
+            .ok_or_else(|| io::ErrorKind::InvalidInput.into())
+    }
+
+    pub(super) fn close(&mut self, handle: u64) -> bool {
+        self.handle(handle).is_ok() && {
+            self.handles[handle as usize - 1] = None;
+            true
+        }
+    }
+
+    /// The console drops the output if nobody listens anymore.
+    pub(super) fn print(&self, data: &[u8]) {
+        for byte in data {
+            let _ = self.output.send(*byte);
+        }
+    }
+
+    /// Returns the number of bytes written.
+    pub(super) fn write(&mut self, handle: u64, data: &[u8]) -> io::Result<usize> {
+        match self.handle(handle)? {
+            Handle::ConsoleOut => {
+                self.print(data);
+                Ok(data.len())
+            }
+            Handle::File(file) => {
+                file.write_all(data)?;
+                Ok(data.len())
+            }
+            Handle::ConsoleIn => Err(io::ErrorKind::PermissionDenied.into()),
+        }
+    }
+
+    /// Reads up to `length` bytes of the console input, less once it is closed.
+    /// Returns `None` while there is no input yet.
+    pub(super) fn read_console(&mut self, length: usize) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        while data.len() < length {
+            match self.input.try_recv() {
+                Ok(byte) => data.push(byte),
+                Err(TryRecvError::Empty) if data.is_empty() => return None,
+                Err(_) => break,
+            }
+        }
+        Some(data)
+    }
+
+    /// Reads up to `length` bytes, less at the end of the file.
+    /// Returns `None` while the console has no input yet.
+    pub(super) fn read(&mut self, handle: u64, length: usize) -> io::Result<Option<Vec<u8>>> {
+        match self.handle(handle)? {
+            Handle::ConsoleIn => Ok(self.read_console(length)),
+            Handle::File(file) => {
+                let mut data = Vec::new();
+                file.take(length as u64).read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+            Handle::ConsoleOut => Err(io::ErrorKind::PermissionDenied.into()),
+        }
+    }
+
+    /// The metadata of the file, `None` for the console
+    pub(super) fn metadata(&mut self, handle: u64) -> io::Result<Option<Metadata>> {
+        match self.handle(handle)? {
+            Handle::File(file) => Ok(Some(file.metadata()?)),
+            Handle::ConsoleIn | Handle::ConsoleOut => Ok(None),
+        }
+    }
+}
+
+/// What a call did, so the lockstep reference can repeat it without the host
+#[derive(Clone)]
+pub(super) struct Call {
+    pub(super) result: u64,
+    /// The bytes written to memory, at their virtual address
+    writes: Vec<(usize, Vec<u8>)>,
+    pub(super) outcome: Outcome,
+}
+
+#[derive(Clone)]
+pub(super) enum Outcome {
+    Done,
+    /// The console has no input yet, the call is made again
+    Retry,
+    Exit(i32),
+    Fault(Exception),
+}
+
+enum Backend {
+    Sandbox(Box<Sandbox>),
+    /// The calls made by the CPU the reference is checking, in order
+    Replay(VecDeque<Call>),
+}
+
+pub struct Host {
+    backend: Backend,
+    /// The arguments of the program, starting with its path, and its environment as `KEY=VALUE`
+    arguments: Vec<String>,
+    environment: Vec<String>,
+    /// The calls are made with the semihosting sequence around `ebreak`
+    pub(super) semihosting: bool,
+    /// `ecall` makes a Linux system call instead of taking a trap
+    pub(super) syscalls: bool,
+}
+
+impl Host {
+    /// The calls are served by the host, files are opened relative to `root`.
+    /// `arguments` and `environment` are passed to the program on its stack, the command line joins the arguments.
+    /// The returned channel is the other end of the console, like the one of a UART.
+    pub fn new(
+        root: PathBuf,
+        arguments: Vec<String>,
+        environment: Vec<String>,
+        semihosting: bool,
+        syscalls: bool,
+    ) -> (IOChannel, Self) {
+        let (input_tx, input_rx) = channel();
+        let (output_tx, output_rx) = channel();
+        let sandbox = Sandbox {
+            root,
+            command_line: arguments.join(" "),
+            handles: Vec::new(),
+            output: output_tx,
+            input: input_rx,
+            start: Instant::now(),
+        };
+        let host = Self {
+            backend: Backend::Sandbox(Box::new(sandbox)),
+            arguments,
+            environment,
+            semihosting,
+            syscalls,
+        };
+        ((input_tx, output_rx), host)
+    }
+
+    /// The same services for the lockstep reference, which replays the calls
+    pub(super) fn replay(&self) -> Self {
+        Self {
+            backend: Backend::Replay(VecDeque::new()),
+            arguments: self.arguments.clone(),
+            environment: self.environment.clone(),
+            semihosting: self.semihosting,
+            syscalls: self.syscalls,
+        }
+    }
+}
+
+impl<T: AddrBus> CPU<T> {
+    /// Serves the semihosting calls or system calls of the program.
+    /// The stack starts at the end of the RAM with the arguments, with system calls the heap starts after the program.
+    pub fn set_host(&mut self, host: Host) -> anyhow::Result<()> {
+        if host.syscalls {
+            let (ram_base, _) = self.memory.ram_range();
+            if !self.memory.is_ram(self.program_break as usize) {
+                self.program_break = ram_base as u64;
+            }
+        }
+        self.set_up_stack(&host.arguments, &host.environment)?;
+        if let Some(reference) = &mut self.reference {
+            reference.set_host(host.replay())?;
+        }
+        self.host = Some(host);
+        Ok(())
+    }
+
+    /// Serves the program again after it was reloaded: the files it opened are closed and the stack is laid out again.
+    pub(super) fn restart_host(&mut self) -> anyhow::Result<()> {
+        if let Some(mut host) = self.host.take() {
+            match &mut host.backend {
+                Backend::Sandbox(sandbox) => {
+                    sandbox.handles.clear();
+                    sandbox.start = Instant::now();
+                }
+                Backend::Replay(calls) => calls.clear(),
+            }
+            self.set_host(host)?;
+        }
+        Ok(())
+    }
+
+    /// Lays out the stack like Linux does for a new process: `argc`, the pointers of `argv` and of the environment,
+    /// each ended by a null pointer, and the auxiliary vector, with the strings above them at the end of the RAM.
+    /// Every hart starts with it, `a0` and `a1` hold `argc` and `argv` for a `main` that is entered directly.
+    fn set_up_stack(&mut self, arguments: &[String], environment: &[String]) -> anyhow::Result<()> {
+        let xlen = self.register.csr.xlen;
+        let (_, ram_limit) = self.memory.ram_range();
+        let strings: Vec<u8> = arguments
+            .iter()
+            .chain(environment)
+            .flat_map(|string| string.bytes().chain([0]))
+            .collect();
+        let auxiliary = [AT_PAGESZ, PAGE_SIZE, AT_ENTRY, self.entry, AT_NULL, 0];
+        let pointer_count = arguments.len() + environment.len() + 3 + auxiliary.len();
+        let strings_addr = ram_limit.saturating_sub(strings.len());
+        // The ABI aligns the stack pointer to 16 bytes
+        let stack_addr =
+            strings_addr.saturating_sub(pointer_count * (xlen.bits() / 8) as usize) & !0xF;
+        if !self.memory.is_ram(stack_addr) {
+            return Err(anyhow::anyhow!(
+                "The arguments and the environment of the program do not fit into the RAM"
+            ));
+        }
+
+        let mut pointers = vec![arguments.len() as u64];
+        let mut addr = strings_addr as u64;
+        for strings in [arguments, environment] {
+            for string in strings {
+                pointers.push(addr);
+                addr += string.len() as u64 + 1;
+            }
+            pointers.push(0);
+        }
+        pointers.extend_from_slice(&auxiliary);
+        self.memory.load_at(strings_addr, &strings)?;
+        self.memory
+            .load_at(stack_addr, &xlen_bytes(xlen, &pointers))?;
+
+        self.stack_pointer = stack_addr as u64;
+        self.main_arguments = (
+            arguments.len() as u64,
+            stack_addr as u64 + u64::from(xlen.bits() / 8),
+        );
+        for register in std::iter::once(&mut self.register).chain(
+            self.harts
+                .iter_mut()
+                .flatten()
+                .map(|hart| &mut hart.register),
+        ) {
+            register.write(2, self.stack_pointer);
+            register.write(10, self.main_arguments.0);
+            register.write(11, self.main_arguments.1);
+        }
+        Ok(())
+    }
+
+    /// Closes the console, the output of later calls is dropped.
+    /// Whoever prints the console knows it received all output once its channel closes.
+    pub fn disconnect_console(&mut self) {
+        if let Some(Host {
+            backend: Backend::Sandbox(sandbox),
+            ..
+        }) = &mut self.host
+        {
+            sandbox.output = channel().0;
+        }
+    }
+
+    /// Makes a call with `operation` and returns false if the program exited.
+    /// `addr` is the instruction that made the call, it is made again from `retry`.
+    /// The lockstep reference does not call `operation`, it replays the next call of the CPU it checks.
+    pub(super) fn call_host(
+        &mut self,
+        addr: usize,
+        retry: usize,
+        operation: fn(&mut Self, &mut Sandbox, &mut Call) -> anyhow::Result<u64>,
+    ) -> anyhow::Result<bool> {
+        let mut host = self.host.take().expect("Call without a host");
+        let call = match &mut host.backend {
+            Backend::Sandbox(sandbox) => {
+                let mut call = Call {
+                    result: 0,
+                    writes: Vec::new(),
+                    outcome: Outcome::Done,
+                };
+                match operation(self, sandbox, &mut call) {
+                    Ok(result) => call.result = result,
+                    Err(err) => match err.downcast::<Exception>() {
+                        Ok(exception) => call.outcome = Outcome::Fault(exception),
+                        Err(err) => {
+                            self.host = Some(host);
+                            return Err(err);
+                        }
+                    },
+                }
+                Ok(call)
+            }
+            Backend::Replay(calls) => calls.pop_front().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "The interpreter made a call to the host the block engine did not make"
+                )
+            }),
+        };
+        let replayed = matches!(host.backend, Backend::Replay(_));
+        self.host = Some(host);
+        let call = call?;
+
+        if replayed {
+            for (addr, data) in &call.writes {
+                for (offset, byte) in data.iter().enumerate() {
+                    // The host faulted at the same byte, the fault is part of the call
+                    if self.store_byte(addr + offset, u32::from(*byte)).is_err() {
+                        break;
+                    }
+                }
+            }
+        } else if let Some(Host {
+            backend: Backend::Replay(calls),
+            ..
+        }) = self
+            .reference
+            .as_mut()
+            .and_then(|reference| reference.host.as_mut())
+        {
+            calls.push_back(call.clone());
+        }
+
+        match call.outcome {
+            Outcome::Done => self.register.write(10, call.result),
+            // The hart does not block the emulator while it waits for the console
+            Outcome::Retry => self.register.pc = retry as u64,
+            Outcome::Exit(code) => {
+                self.exit_code = Some(code);
+                return Ok(false);
+            }
+            Outcome::Fault(exception) => self.trap_exception(exception.into(), addr)?,
+        }
+        Ok(true)
+    }
+
+    pub(super) fn read_memory(&mut self, addr: usize, length: usize) -> anyhow::Result<Vec<u8>> {
+        (addr..addr + length)
+            .map(|addr| Ok(self.load_byte(addr)? as u8))
+            .collect()
+    }
+
+    /// Reads the string at `addr` up to its NUL, without it.
+    pub(super) fn read_string(&mut self, addr: usize) -> anyhow::Result<Vec<u8>> {
+        let mut text = Vec::new();
+        loop {
+            match self.load_byte(addr + text.len())? as u8 {
+                0 => return Ok(text),
+                byte => text.push(byte),
+            }
+        }
+    }
+
+    /// The write is recorded before it is made, the reference faults at the same byte.
+    pub(super) fn write_memory(
+        &mut self,
+        call: &mut Call,
+        addr: usize,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        call.writes.push((addr, data.to_vec()));
+        for (offset, byte) in data.iter().enumerate() {
+            self.store_byte(addr + offset, u32::from(*byte))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the XLEN wide `values` to `addr`, one after the other.
+    pub(super) fn write_xlen(
+        &mut self,
+        call: &mut Call,
+        addr: usize,
+        values: &[u64],
+    ) -> anyhow::Result<()> {
+        let data = xlen_bytes(self.register.csr.xlen, values);
+        self.write_memory(call, addr, &data)
+    }
+}
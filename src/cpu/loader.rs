@@ -0,0 +1,351 @@
+//! Loading programs into the memory is scoped for this file.
+//! Besides ELF files and raw binaries, Intel HEX and Motorola S-record files are understood.
+//! Several images can be loaded at once, e.g. a bootloader and the application it starts.
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use elf::abi;
+use elf::endian::AnyEndian;
+use elf::ElfBytes;
+
+use super::{AddrBus, Xlen, CPU};
+
+/// Set in the ELF header flags of binaries built for the RV32E base ISA
+const EF_RISCV_RVE: u32 = 0x0008;
+
+/// Consecutive bytes and the address they are loaded at
+type Segment = (usize, Vec<u8>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Elf,
+    /// A raw binary, it is loaded at the base address of the image
+    Bin,
+    /// Intel HEX with 32 bit addresses
+    Hex,
+    /// Motorola S-record with 16, 24 or 32 bit addresses
+    Srec,
+}
+
+pub struct Image {
+    /// Where the image was read from, to read it again once it changed
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+    pub format: Format,
+    /// Where a raw binary is loaded, the other formats contain their addresses
+    pub base_address: usize,
+}
+
+/// The bytes of a record written as pairs of hex digits
+fn hex_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&digits[index..index + 2], 16).ok())
+        .collect()
+}
+
+/// The sum of the bytes, modulo 256
+fn checksum(record: &[u8]) -> u8 {
+    record.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Appends `data` to the last segment if it continues it, records are usually in order.
+fn push_data(segments: &mut Vec<Segment>, addr: usize, data: &[u8]) {
+    match segments.last_mut() {
+        Some((start, bytes)) if *start + bytes.len() == addr => bytes.extend_from_slice(data),
+        _ => segments.push((addr, data.to_vec())),
+    }
+}
+
+/// The data and the start address of an Intel HEX file, parsing stops at the end of file record.
+fn parse_hex(text: &str) -> anyhow::Result<(Vec<Segment>, Option<u64>)> {
+    let mut segments = Vec::new();
+    let mut entry = None;
+    // Set by the extended segment and extended linear address records
+    let mut base = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = line
+            .strip_prefix(':')
+            .and_then(hex_bytes)
+            .filter(|record| record.len() >= 5 && record.len() == usize::from(record[0]) + 5)
+            .ok_or_else(|| anyhow!("Line {} is not an Intel HEX record", number + 1))?;
+        if checksum(&record) != 0 {
+            return Err(anyhow!("The checksum of line {} is wrong", number + 1));
+        }
+        let addr = usize::from(u16::from_be_bytes([record[1], record[2]]));
+        let data = &record[4..record.len() - 1];
+        match (record[3], data.len()) {
+            (0x00, _) => push_data(&mut segments, base + addr, data),
+            (0x01, _) => break,
+            (0x02, 2) => base = usize::from(u16::from_be_bytes([data[0], data[1]])) << 4,
+            (0x03, 4) => {
+                let segment = u64::from(u16::from_be_bytes([data[0], data[1]]));
+                let offset = u64::from(u16::from_be_bytes([data[2], data[3]]));
+                entry = Some((segment << 4) + offset);
+            }
+            (0x04, 2) => base = usize::from(u16::from_be_bytes([data[0], data[1]])) << 16,
+            (0x05, 4) => entry = Some(u64::from(u32::from_be_bytes(data.try_into()?))),
+            (kind, _) => {
+                return Err(anyhow!(
+                    "Line {} has the unsupported record type {kind:02X}",
+                    number + 1
+                ))
+            }
+        }
+    }
+    Ok((segments, entry))
+}
+
+/// The data and the start address of an S-record file, header and count records are skipped.
+fn parse_srec(text: &str) -> anyhow::Result<(Vec<Segment>, Option<u64>)> {
+    let mut segments = Vec::new();
+    let mut entry = None;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || anyhow!("Line {} is not an S-record", number + 1);
+        let mut chars = line.strip_prefix('S').ok_or_else(invalid)?.chars();
+        let kind = chars.next().ok_or_else(invalid)?;
+        let address_length = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(invalid()),
+        };
+        // The count covers the address, the data and the checksum
+        let record = hex_bytes(chars.as_str())
+            .filter(|record| {
+                record.len() >= address_length + 2 && record.len() == usize::from(record[0]) + 1
+            })
+            .ok_or_else(invalid)?;
+        if checksum(&record) != 0xFF {
+            return Err(anyhow!("The checksum of line {} is wrong", number + 1));
+        }
+        let addr = record[1..=address_length]
+            .iter()
+            .fold(0, |addr, byte| addr << 8 | usize::from(*byte));
+        let data = &record[address_length + 1..record.len() - 1];
+        match kind {
+            '1' | '2' | '3' => push_data(&mut segments, addr, data),
+            '7' | '8' | '9' => entry = Some(addr as u64),
+            _ => {}
+        }
+    }
+    Ok((segments, entry))
+}
+
+impl<T: AddrBus> CPU<T> {
+    /// Loads the `images` one after the other, a later image overwrites an earlier one where they overlap.
+    /// All harts start at `entry` if it is set, otherwise at the entry address of the first image.
+    pub fn from_images(images: &[Image], entry: Option<u64>, memory: T) -> anyhow::Result<Self> {
+        let mut cpu = Self::new(memory);
+        cpu.load_images(images, entry)?;
+        Ok(cpu)
+    }
+
+    /// Loads the `images` again, e.g. after they were rebuilt, and resets the system like on power-up.
+    /// The RAM is cleared, the files opened through the host are closed and the stack is laid out again.
+    /// `rve` selects the RV32E base ISA even if the ELF header does not.
+    pub fn reload(
+        &mut self,
+        images: &[Image],
+        entry: Option<u64>,
+        rve: bool,
+    ) -> anyhow::Result<()> {
+        self.memory.clear_ram();
+        self.program_break = 0;
+        self.load_images(images, entry)?;
+        self.register.rve |= rve;
+        self.decode_cache.flush();
+        self.blocks.flush();
+        self.instruction_log.clear();
+        self.executed_instructions = 0;
+        self.exit_code = None;
+        self.reset();
+        if let Some(reference) = &mut self.reference {
+            reference.reload(images, entry, rve)?;
+        }
+        self.restart_host()
+    }
+
+    fn load_images(&mut self, images: &[Image], entry: Option<u64>) -> anyhow::Result<()> {
+        let mut first_entry = None;
+        // The XLEN of the first ELF file and its path, the others have to match it
+        let mut first_elf: Option<(Xlen, &Path)> = None;
+        for image in images {
+            let image_entry = self
+                .load_image(image)
+                .with_context(|| format!("Could not load {}", image.path.display()))?;
+            first_entry = first_entry.or(Some(image_entry));
+            if image.format == Format::Elf {
+                let xlen = self.register.csr.xlen;
+                match first_elf {
+                    Some((first_xlen, path)) if first_xlen != xlen => {
+                        return Err(anyhow!(
+                            "{} is a {} bit ELF file, but {} is a {} bit one",
+                            image.path.display(),
+                            xlen.bits(),
+                            path.display(),
+                            first_xlen.bits()
+                        ));
+                    }
+                    Some(_) => {}
+                    None => first_elf = Some((xlen, &image.path)),
+                }
+            }
+        }
+        self.entry = entry
+            .or(first_entry)
+            .ok_or_else(|| anyhow!("There is no image to load"))?;
+        self.register.pc = self.entry;
+        Ok(())
+    }
+
+    /// Returns the entry address of the image, a raw binary or a file without one is entered at its start.
+    fn load_image(&mut self, image: &Image) -> anyhow::Result<u64> {
+        let (segments, entry) = match image.format {
+            Format::Elf => return self.load_elf(&image.data),
+            Format::Bin => (vec![(image.base_address, image.data.clone())], None),
+            Format::Hex => parse_hex(std::str::from_utf8(&image.data)?)?,
+            Format::Srec => parse_srec(std::str::from_utf8(&image.data)?)?,
+        };
+        let start = segments
+            .first()
+            .map(|(addr, _)| *addr as u64)
+            .ok_or_else(|| anyhow!("The image contains no data"))?;
+        for (addr, data) in &segments {
+            self.load_segment(*addr, data)?;
+        }
+        Ok(entry.unwrap_or(start))
+    }
+
+    /// Loads the `PT_LOAD` segments, the ELF class selects the XLEN.
+    /// The content of a segment is loaded at its physical address, like a programmer writes the flash,
+    /// unless only its virtual address is mapped, e.g. because the linker left the physical addresses 0.
+    /// The rest of the segment, its BSS, is zeroed at the virtual address, where the program expects it.
+    fn load_elf(&mut self, file: &[u8]) -> anyhow::Result<u64> {
+        let elffile =
+            ElfBytes::<AnyEndian>::minimal_parse(file).context("Failed to parse the ELF file")?;
+        let header = &elffile.ehdr;
+        if header.e_machine != abi::EM_RISCV {
+            return Err(anyhow!(
+                "The ELF file is built for the machine {}, not for RISC-V ({})",
+                header.e_machine,
+                abi::EM_RISCV
+            ));
+        }
+        if header.endianness != AnyEndian::Little {
+            return Err(anyhow!(
+                "The ELF file is big endian, RISC-V is little endian"
+            ));
+        }
+
+        self.register.csr.xlen = match header.class {
+            elf::file::Class::ELF32 => Xlen::Rv32,
+            elf::file::Class::ELF64 => Xlen::Rv64,
+        };
+        self.register.rve = header.e_flags & EF_RISCV_RVE != 0;
+
+        let segments = elffile
+            .segments()
+            .ok_or_else(|| anyhow!("Could not find segments in the ELF file"))?;
+        let mut loaded = false;
+        for (index, phdr) in segments.iter().enumerate() {
+            if phdr.p_type != abi::PT_LOAD {
+                continue;
+            }
+            let data = elffile
+                .segment_data(&phdr)
+                .with_context(|| format!("Could not read segment {index} of the ELF file"))?;
+            let bss_size = phdr.p_memsz.checked_sub(phdr.p_filesz).ok_or_else(|| {
+                anyhow!("Segment {index} has more data than memory, the ELF file is broken")
+            })?;
+            let addr = if !self.fits(phdr.p_paddr, phdr.p_filesz)
+                && self.fits(phdr.p_vaddr, phdr.p_filesz)
+            {
+                phdr.p_vaddr
+            } else {
+                phdr.p_paddr
+            };
+            self.load_elf_range(index, addr, data)?;
+            // Without BSS, this is where the segment ends at run time
+            let mut bss = phdr.p_vaddr.saturating_add(phdr.p_filesz);
+            if bss_size > 0 {
+                if !self.fits(bss, bss_size) {
+                    bss = addr.saturating_add(phdr.p_filesz);
+                }
+                self.check_elf_range(index, bss, bss_size)?;
+                let zeros = vec![0; usize::try_from(bss_size)?];
+                self.load_elf_range(index, bss, &zeros)?;
+            }
+            // The heap starts after the program, including its BSS
+            self.program_break = self.program_break.max(bss.saturating_add(bss_size));
+            loaded = true;
+        }
+        if !loaded {
+            return Err(anyhow!(
+                "The ELF file has no loadable segments, is it an object file?"
+            ));
+        }
+        Ok(header.e_entry)
+    }
+
+    /// True if the `size` bytes at `addr` are inside one region of the memory map.
+    fn fits(&self, addr: u64, size: u64) -> bool {
+        let (Ok(addr), Ok(size)) = (usize::try_from(addr), usize::try_from(size)) else {
+            return false;
+        };
+        self.memory.region(addr).is_some_and(|region| {
+            addr.checked_add(size)
+                .is_some_and(|end| end <= region.limit)
+        })
+    }
+
+    /// Checks that the `size` bytes of the segment `index` at `addr` fit into one region.
+    fn check_elf_range(&self, index: usize, addr: u64, size: u64) -> anyhow::Result<usize> {
+        let end = addr.saturating_add(size);
+        let Some(region) = usize::try_from(addr)
+            .ok()
+            .and_then(|addr| self.memory.region(addr))
+        else {
+            return Err(anyhow!(
+                "Segment {index} at 0x{addr:08X}..0x{end:08X} is not in mapped memory"
+            ));
+        };
+        if !self.fits(addr, size) {
+            return Err(anyhow!(
+                "Segment {index} at 0x{addr:08X}..0x{end:08X} does not fit into the {} region, which ends at 0x{:08X}",
+                region.name,
+                region.limit
+            ));
+        }
+        Ok(addr as usize)
+    }
+
+    /// Loads `data` of the segment `index` at `addr`.
+    fn load_elf_range(&mut self, index: usize, addr: u64, data: &[u8]) -> anyhow::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let addr = self.check_elf_range(index, addr, data.len() as u64)?;
+        self.memory
+            .load_at(addr, data)
+            .with_context(|| format!("Could not load segment {index}"))
+    }
+
+    /// Loads `data` at `addr`, the heap starts after the last image.
+    fn load_segment(&mut self, addr: usize, data: &[u8]) -> anyhow::Result<()> {
+        self.memory.load_at(addr, data)?;
+        self.program_break = self.program_break.max((addr + data.len()) as u64);
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! The differential lockstep mode is scoped for this file.
+//! A reference CPU executes the same program with the interpreter. After every block of the
+//! block engine, the reference executes as many instructions and both have to agree on the state.
+use super::register::index_to_name;
+use super::{AddrBus, Engine, CPU};
+
+impl<T: AddrBus> CPU<T> {
+    /// Switches to the block engine and runs `reference` in lockstep with it.
+    /// The reference has to be loaded with the same program, on a memory of its own.
+    /// It replays the calls of this CPU to the host instead of making them,
+    /// like the frames its peripherals receive from outside.
+    pub fn set_reference(&mut self, mut reference: CPU<T>) -> anyhow::Result<()> {
+        self.engine = Engine::Block;
+        reference.engine = Engine::Interpreter;
+        self.memory.record_input();
+        reference.memory.replay_input(Vec::new());
+        if let Some(host) = &self.host {
+            reference.set_host(host.replay())?;
+        }
+        self.reference = Some(Box::new(reference));
+        Ok(())
+    }
+
+    /// Like `step`, but interrupts are not checked.
+    fn step_uninterrupted(&mut self) -> anyhow::Result<bool> {
+        self.remaining_quantum -= 1;
+        self.advance_clock(1);
+        self.execute()
+    }
+
+    /// Executes `steps` instructions on the reference and compares it with this CPU.
+    /// The reference takes interrupts at the same points as the block engine, between blocks.
+    pub(super) fn lockstep(&mut self, steps: usize) -> anyhow::Result<()> {
+        let mut reference = self
+            .reference
+            .take()
+            .expect("Lockstep without a reference CPU");
+        reference.memory.replay_input(self.memory.take_input());
+        reference.step()?;
+        for _ in 1..steps {
+            reference.step_uninterrupted()?;
+        }
+        let mismatch = self.mismatch(&reference);
+        self.reference = Some(reference);
+        if let Some(mismatch) = mismatch {
+            return Err(anyhow::anyhow!(
+                "Lockstep mismatch after {} instructions, the block engine differs from the interpreter: {mismatch}",
+                self.executed_instructions
+            ));
+        }
+        Ok(())
+    }
+
+    /// Describes the first difference in the state of the scheduled hart
+    fn mismatch(&self, reference: &Self) -> Option<String> {
+        if self.hart_id != reference.hart_id {
+            return Some(format!(
+                "hart {} != hart {}",
+                self.hart_id, reference.hart_id
+            ));
+        }
+        if self.privilege != reference.privilege {
+            return Some(format!(
+                "privilege {:?} != {:?}",
+                self.privilege, reference.privilege
+            ));
+        }
+        if self.waits_for_interrupt != reference.waits_for_interrupt {
+            return Some(format!(
+                "waits for interrupt {} != {}",
+                self.waits_for_interrupt, reference.waits_for_interrupt
+            ));
+        }
+        let (csr, reference_csr) = (&self.register.csr, &reference.register.csr);
+        let mut state = vec![
+            ("pc", self.register.pc, reference.register.pc),
+            ("mstatus", csr.mstatus.into(), reference_csr.mstatus.into()),
+            ("mie", csr.mie.into(), reference_csr.mie.into()),
+            ("mip", csr.mip.into(), reference_csr.mip.into()),
+            ("mepc", csr.mepc, reference_csr.mepc),
+            ("mcause", csr.mcause, reference_csr.mcause),
+            ("mtval", csr.mtval, reference_csr.mtval),
+            ("sepc", csr.sepc, reference_csr.sepc),
+            ("scause", csr.scause, reference_csr.scause),
+            ("stval", csr.stval, reference_csr.stval),
+            ("satp", csr.satp.into(), reference_csr.satp.into()),
+        ];
+        for index in 0..self.register.count() {
+            state.push((
+                index_to_name(index),
+                self.register.read(index),
+                reference.register.read(index),
+            ));
+        }
+        state
+            .into_iter()
+            .find(|(_name, value, reference)| value != reference)
+            .map(|(name, value, reference)| {
+                format!("{name} is 0x{value:08X}, the interpreter has 0x{reference:08X}")
+            })
+    }
+}
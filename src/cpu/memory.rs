@@ -1,22 +1,73 @@
+use super::exception::Exception;
+use super::register::{Privilege, MCAUSE};
+use super::CPU;
+
 /// This trait is the default interface for the CPU execution to the rest of the system.
 pub trait AddrBus {
-    fn set_reservation(&mut self, addr: usize, value: u32);
+    /// Every hart holds its own LR/SC reservation on the physical address `addr`.
+    fn set_reservation(&mut self, hart: usize, addr: usize, value: u64);
+
+    fn get_reservation(&mut self, hart: usize) -> Option<(usize, u64)>;
+
+    fn del_reservation(&mut self, hart: usize);
 
-    fn get_reservation(&mut self) -> Option<(usize, u32)>;
+    /// Drops the reservations of all other harts that cover the stored bytes.
+    fn invalidate_reservations(&mut self, hart: usize, addr: usize, size: usize);
 
-    fn del_reservation(&mut self);
+    /// Returns the interrupt lines currently raised for the hart, encoded as `mip` bits.
+    fn pending_interrupts(&self, hart: usize) -> u32;
 
-    fn pending_interrupt(&self) -> Option<u32>;
+    /// Advances the time of the system by the given amount of core clock cycles.
+    fn tick(&mut self, cycles: u64);
+
+    /// The frequency of the core clock in Hz, used to convert host time into cycles.
+    fn clock_frequency(&self) -> u64;
+
+    /// The region of the memory map that contains `addr`, if any.
+    fn region(&self, addr: usize) -> Option<Region>;
 
     fn is_ram(&self, addr: usize) -> bool;
 
+    /// The base and the limit of the RAM, e.g. for the stack of a program.
+    fn ram_range(&self) -> (usize, usize);
+
     fn load_ram_at(&mut self, offset: usize, data: &[u8]);
 
+    /// Zeroes the whole RAM, e.g. before a program is loaded again.
+    fn clear_ram(&mut self);
+
     fn is_rom(&self, addr: usize) -> bool;
 
     fn load_rom_at(&mut self, offset: usize, data: &[u8]);
 
-    fn load_at(&mut self, offset: usize, data: &[u8]);
+    /// Loads a program, only RAM and ROM can be loaded.
+    fn load_at(&mut self, addr: usize, data: &[u8]) -> anyhow::Result<()>;
+
+    /// The physical range `(addr, size)` that changed without a store of the CPU,
+    /// e.g. flash programmed by a controller. Cached instructions from it are dropped.
+    fn take_modified_range(&mut self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Returns true once a peripheral requested a reset of the system, e.g. a watchdog.
+    fn take_reset_request(&mut self) -> bool {
+        false
+    }
+
+    /// Returns the peripherals to their reset state, the content of the memory is kept.
+    fn reset(&mut self) {}
+
+    /// Keeps the frames the peripherals receive from outside, for the lockstep reference.
+    fn record_input(&mut self) {}
+
+    /// The frames received since the last call, once `record_input` was called.
+    fn take_input(&mut self) -> Vec<InputFrame> {
+        Vec::new()
+    }
+
+    /// The peripherals receive the `input` of the checked bus, instead of the frames from outside.
+    /// They take them at the same points, as the reference executes the same instructions.
+    fn replay_input(&mut self, _input: Vec<InputFrame>) {}
 
     fn read_byte(&self, addr: usize) -> anyhow::Result<u32>;
 
@@ -43,4 +94,328 @@ pub trait AddrBus {
         self.write_halfword(index + 2, value >> 16)?;
         Ok(())
     }
+
+    fn read_doubleword(&self, index: usize) -> anyhow::Result<u64> {
+        let doubleword =
+            (u64::from(self.read_word(index + 4)?) << 32) + u64::from(self.read_word(index)?);
+        Ok(doubleword)
+    }
+
+    fn write_doubleword(&mut self, index: usize, value: u64) -> anyhow::Result<()> {
+        self.write_word(index, value as u32)?;
+        self.write_word(index + 4, (value >> 32) as u32)?;
+        Ok(())
+    }
+}
+
+/// A frame a peripheral received from outside, e.g. on a UART from the console.
+/// `attempt` counts the attempts of the peripheral to receive, up to the one that took the frame.
+#[derive(Clone, Copy)]
+pub struct InputFrame {
+    pub peripheral: usize,
+    pub attempt: u64,
+    pub data: u8,
+}
+
+/// A region of the memory map `[base, limit)` and the accesses it permits
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub name: &'static str,
+    pub base: usize,
+    pub limit: usize,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Region {
+    /// The `permissions` are written like `rwx`, with a `-` for each denied access.
+    pub fn new(name: &'static str, base: usize, limit: usize, permissions: &str) -> Self {
+        let permissions = permissions.as_bytes();
+        Self {
+            name,
+            base,
+            limit,
+            read: permissions[0] == b'r',
+            write: permissions[1] == b'w',
+            execute: permissions[2] == b'x',
+        }
+    }
+
+    pub fn permits(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+/// The kind of access to memory, used for protection and translation
+#[derive(Clone, Copy)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    pub fn access_fault(self) -> MCAUSE {
+        match self {
+            Access::Read => MCAUSE::LoadAccessFault,
+            Access::Write => MCAUSE::StoreAccessFault,
+            Access::Execute => MCAUSE::InstructionAccessFault,
+        }
+    }
+
+    pub fn page_fault(self) -> MCAUSE {
+        match self {
+            Access::Read => MCAUSE::LoadPageFault,
+            Access::Write => MCAUSE::StorePageFault,
+            Access::Execute => MCAUSE::InstructionPageFault,
+        }
+    }
+}
+
+impl<T: AddrBus> CPU<T> {
+    /// Loads and stores use the privilege in `mstatus.MPP` if `mstatus.MPRV` is set.
+    pub(super) fn data_privilege(&self) -> Privilege {
+        if self.privilege == Privilege::Machine && self.register.csr.mstatus_get_mprv() {
+            self.register.csr.mstatus_get_mpp()
+        } else {
+            self.privilege
+        }
+    }
+
+    /// Raises an access-fault exception if the PMP or the memory map deny the physical access.
+    pub(super) fn check_access(
+        &self,
+        addr: usize,
+        size: usize,
+        access: Access,
+        privilege: Privilege,
+    ) -> anyhow::Result<()> {
+        if !self.register.csr.pmp.check(addr, size, access, privilege) {
+            return Err(Exception::new(access.access_fault(), addr as u64).into());
+        }
+        // The access has to stay within one region of the memory map that permits it
+        let region = match self.last_region.get() {
+            Some(region) if region.base <= addr && addr < region.limit => Some(region),
+            _ => {
+                let region = self.memory.region(addr);
+                self.last_region.set(region);
+                region
+            }
+        };
+        match region {
+            Some(region) if region.permits(access) && addr + size <= region.limit => Ok(()),
+            Some(region) => {
+                Err(Exception::in_region(access.access_fault(), addr as u64, region.name).into())
+            }
+            None => {
+                Err(
+                    Exception::in_region(access.access_fault(), addr as u64, "unmapped memory")
+                        .into(),
+                )
+            }
+        }
+    }
+
+    pub(super) fn load_byte(&mut self, addr: usize) -> anyhow::Result<u32> {
+        let addr = self.translate(addr, 1, Access::Read)?;
+        self.memory.read_byte(addr)
+    }
+
+    pub(super) fn load_halfword(&mut self, addr: usize) -> anyhow::Result<u32> {
+        if let Some(first) = self.page_split(addr, 2, Access::Read) {
+            return Ok(self.load_split(addr, 2, first)? as u32);
+        }
+        let addr = self.translate(addr, 2, Access::Read)?;
+        self.memory.read_halfword(addr)
+    }
+
+    pub(super) fn load_word(&mut self, addr: usize) -> anyhow::Result<u32> {
+        if let Some(first) = self.page_split(addr, 4, Access::Read) {
+            return Ok(self.load_split(addr, 4, first)? as u32);
+        }
+        let addr = self.translate(addr, 4, Access::Read)?;
+        self.memory.read_word(addr)
+    }
+
+    pub(super) fn load_doubleword(&mut self, addr: usize) -> anyhow::Result<u64> {
+        if let Some(first) = self.page_split(addr, 8, Access::Read) {
+            return self.load_split(addr, 8, first);
+        }
+        let addr = self.translate(addr, 8, Access::Read)?;
+        self.memory.read_doubleword(addr)
+    }
+
+    /// Loads an access that crosses a page byte by byte, the `first` bytes are in the page of `addr`.
+    /// Both pages are translated before, a fault is raised for the page that faults.
+    fn load_split(&mut self, addr: usize, size: usize, first: usize) -> anyhow::Result<u64> {
+        let low = self.translate(addr, first, Access::Read)?;
+        let high = self.translate(addr + first, size - first, Access::Read)?;
+        let mut value = 0;
+        for index in (0..size).rev() {
+            let paddr = if index < first {
+                low + index
+            } else {
+                high + index - first
+            };
+            value = value << 8 | u64::from(self.memory.read_byte(paddr)?);
+        }
+        Ok(value)
+    }
+
+    /// An atomic access can not be split, if it crosses a page it raises an access fault.
+    fn check_atomic(&self, addr: usize, size: usize, access: Access) -> anyhow::Result<()> {
+        match self.page_split(addr, size, access) {
+            Some(_) => Err(Exception::new(access.access_fault(), addr as u64).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Atomic memory operations need read and write access, they raise store faults.
+    /// Returns the physical address of the operand.
+    fn amo_translate(&mut self, addr: usize, size: usize) -> anyhow::Result<usize> {
+        self.check_atomic(addr, size, Access::Write)?;
+        let paddr = self.translate(addr, size, Access::Write)?;
+        let privilege = self.data_privilege();
+        self.check_access(paddr, size, Access::Read, privilege)
+            .map_err(|_| Exception::new(MCAUSE::StoreAccessFault, addr as u64))?;
+        Ok(paddr)
+    }
+
+    pub(super) fn amo_load_word(&mut self, addr: usize) -> anyhow::Result<u32> {
+        let paddr = self.amo_translate(addr, 4)?;
+        self.memory.read_word(paddr)
+    }
+
+    pub(super) fn amo_load_doubleword(&mut self, addr: usize) -> anyhow::Result<u64> {
+        let paddr = self.amo_translate(addr, 8)?;
+        self.memory.read_doubleword(paddr)
+    }
+
+    /// A store drops the decoded instructions and the LR/SC reservations of other harts it overlaps.
+    pub(super) fn invalidate_stored(&mut self, paddr: usize, size: usize) {
+        self.decode_cache.invalidate(paddr, size);
+        self.blocks.invalidate(paddr, size);
+        self.memory
+            .invalidate_reservations(self.hart_id, paddr, size);
+    }
+
+    /// A store to a peripheral may change memory on its own, e.g. the flash programmed through SPI.
+    /// The instructions decoded from it are dropped.
+    fn invalidate_modified(&mut self) {
+        if let Some((paddr, size)) = self.memory.take_modified_range() {
+            self.decode_cache.invalidate(paddr, size);
+            self.blocks.invalidate(paddr, size);
+        }
+    }
+
+    fn store(&mut self, addr: usize, size: usize) -> anyhow::Result<usize> {
+        let paddr = self.translate(addr, size, Access::Write)?;
+        self.invalidate_stored(paddr, size);
+        Ok(paddr)
+    }
+
+    pub(super) fn store_byte(&mut self, addr: usize, value: u32) -> anyhow::Result<()> {
+        let addr = self.store(addr, 1)?;
+        self.memory.write_byte(addr, value)?;
+        self.invalidate_modified();
+        Ok(())
+    }
+
+    /// Stores an access that crosses a page byte by byte, the `first` bytes are in the page of `addr`.
+    /// Both pages are translated before, no byte is written if one of them faults.
+    fn store_split(
+        &mut self,
+        addr: usize,
+        size: usize,
+        first: usize,
+        value: u64,
+    ) -> anyhow::Result<()> {
+        let low = self.store(addr, first)?;
+        let high = self.store(addr + first, size - first)?;
+        for index in 0..size {
+            let paddr = if index < first {
+                low + index
+            } else {
+                high + index - first
+            };
+            self.memory
+                .write_byte(paddr, (value >> (index * 8)) as u32 & 0xFF)?;
+        }
+        self.invalidate_modified();
+        Ok(())
+    }
+
+    pub(super) fn store_halfword(&mut self, addr: usize, value: u32) -> anyhow::Result<()> {
+        if let Some(first) = self.page_split(addr, 2, Access::Write) {
+            return self.store_split(addr, 2, first, u64::from(value));
+        }
+        let addr = self.store(addr, 2)?;
+        self.memory.write_halfword(addr, value)?;
+        self.invalidate_modified();
+        Ok(())
+    }
+
+    pub(super) fn store_word(&mut self, addr: usize, value: u32) -> anyhow::Result<()> {
+        if let Some(first) = self.page_split(addr, 4, Access::Write) {
+            return self.store_split(addr, 4, first, u64::from(value));
+        }
+        let addr = self.store(addr, 4)?;
+        self.memory.write_word(addr, value)?;
+        self.invalidate_modified();
+        Ok(())
+    }
+
+    pub(super) fn store_doubleword(&mut self, addr: usize, value: u64) -> anyhow::Result<()> {
+        if let Some(first) = self.page_split(addr, 8, Access::Write) {
+            return self.store_split(addr, 8, first, value);
+        }
+        let addr = self.store(addr, 8)?;
+        self.memory.write_doubleword(addr, value)?;
+        self.invalidate_modified();
+        Ok(())
+    }
+
+    /// Loads `size` bytes and registers the reservation of this hart on their physical address.
+    pub(super) fn load_reserved(&mut self, addr: usize, size: usize) -> anyhow::Result<u64> {
+        self.check_atomic(addr, size, Access::Read)?;
+        let paddr = self.translate(addr, size, Access::Read)?;
+        let value = if size == 8 {
+            self.memory.read_doubleword(paddr)?
+        } else {
+            u64::from(self.memory.read_word(paddr)?)
+        };
+        self.memory.set_reservation(self.hart_id, paddr, value);
+        Ok(value)
+    }
+
+    /// Stores `size` bytes if this hart still holds a reservation on the address.
+    /// Returns true if the store was performed, the reservation is released in any case.
+    pub(super) fn store_conditional(
+        &mut self,
+        addr: usize,
+        size: usize,
+        value: u64,
+    ) -> anyhow::Result<bool> {
+        let paddr = self.amo_translate(addr, size)?;
+        let current = if size == 8 {
+            self.memory.read_doubleword(paddr)?
+        } else {
+            u64::from(self.memory.read_word(paddr)?)
+        };
+        let reserved = self.memory.get_reservation(self.hart_id) == Some((paddr, current));
+        self.memory.del_reservation(self.hart_id);
+        if reserved {
+            if size == 8 {
+                self.store_doubleword(addr, value)?;
+            } else {
+                self.store_word(addr, value as u32)?;
+            }
+        }
+        Ok(reserved)
+    }
 }
@@ -0,0 +1,226 @@
+//! Virtual memory, the Sv32 page table walker and its TLB are scoped for this file.
+//! Every access of the executer is translated here before it reaches the `AddrBus`.
+use super::exception::Exception;
+use super::memory::Access;
+use super::register::Privilege;
+use super::{AddrBus, CPU};
+
+const TLB_SIZE: usize = 64;
+const PAGE_SHIFT: u32 = 12;
+const PAGE_MASK: u32 = (1 << PAGE_SHIFT) - 1;
+
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+const PTE_U: u32 = 1 << 4;
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+
+#[derive(Clone, Copy)]
+struct TlbEntry {
+    // Entries are tagged with the whole `satp`, changing it implicitly misses all entries
+    satp: u32,
+    vpn: u32,
+    // Superpages are split into 4 KiB entries, so `ppn` always refers to a 4 KiB page
+    ppn: u64,
+    pte: u32,
+}
+
+/// A direct mapped translation lookaside buffer
+pub struct Tlb {
+    entries: [Option<TlbEntry>; TLB_SIZE],
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self {
+            entries: [None; TLB_SIZE],
+        }
+    }
+}
+
+impl Tlb {
+    fn lookup(&self, satp: u32, vpn: u32) -> Option<TlbEntry> {
+        self.entries[vpn as usize % TLB_SIZE].filter(|entry| entry.satp == satp && entry.vpn == vpn)
+    }
+
+    fn insert(&mut self, entry: TlbEntry) {
+        self.entries[entry.vpn as usize % TLB_SIZE] = Some(entry);
+    }
+
+    /// Used by `SFENCE.VMA`, flushes the entire TLB regardless of address and ASID
+    pub fn flush(&mut self) {
+        self.entries = [None; TLB_SIZE];
+    }
+}
+
+fn page_fault(access: Access, vaddr: u32) -> anyhow::Error {
+    Exception::new(access.page_fault(), u64::from(vaddr)).into()
+}
+
+/// True if a 32 bit instruction at `addr` continues in the next page. The page offsets of
+/// virtual and physical addresses match, so either can be checked.
+pub(super) fn crosses_page(addr: usize) -> bool {
+    addr & PAGE_MASK as usize == PAGE_MASK as usize - 1
+}
+
+impl<T: AddrBus> CPU<T> {
+    /// Instruction fetches use the current privilege, loads and stores may be altered by MPRV.
+    fn access_privilege(&self, access: Access) -> Privilege {
+        match access {
+            Access::Execute => self.privilege,
+            Access::Read | Access::Write => self.data_privilege(),
+        }
+    }
+
+    fn leaf_permits(&self, pte: u32, access: Access, privilege: Privilege) -> bool {
+        let user_page = pte & PTE_U != 0;
+        let sum = self.register.csr.mstatus_get_sum();
+        match privilege {
+            Privilege::User if !user_page => return false,
+            // S-mode may never execute user pages and only access them with SUM set
+            Privilege::Supervisor if user_page && (matches!(access, Access::Execute) || !sum) => {
+                return false;
+            }
+            _ => (),
+        }
+        match access {
+            Access::Read => {
+                pte & PTE_R != 0 || (pte & PTE_X != 0 && self.register.csr.mstatus_get_mxr())
+            }
+            Access::Write => pte & PTE_W != 0,
+            Access::Execute => pte & PTE_X != 0,
+        }
+    }
+
+    /// Walks the page table without side effects.
+    /// Returns the physical address of the 4 KiB page, the leaf PTE and its address.
+    fn walk(
+        &self,
+        vaddr: u32,
+        access: Access,
+        privilege: Privilege,
+    ) -> anyhow::Result<(u64, u32, u64)> {
+        let vpn = [(vaddr >> 12) & 0x3FF, vaddr >> 22];
+        let mut table = self.register.csr.satp_root();
+        for level in (0..2).rev() {
+            let pte_addr = table + u64::from(vpn[level]) * 4;
+            // The walk is an implicit S-mode access and subject to the PMP
+            if self
+                .check_access(
+                    usize::try_from(pte_addr)?,
+                    4,
+                    Access::Read,
+                    Privilege::Supervisor,
+                )
+                .is_err()
+            {
+                return Err(Exception::new(access.access_fault(), u64::from(vaddr)).into());
+            }
+            let pte = self.memory.read_word(usize::try_from(pte_addr)?)?;
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(page_fault(access, vaddr));
+            }
+            let ppn = u64::from(pte >> 10);
+            if pte & (PTE_R | PTE_X) == 0 {
+                // Pointer to the next level of the page table
+                table = ppn << PAGE_SHIFT;
+                continue;
+            }
+            if !self.leaf_permits(pte, access, privilege) {
+                return Err(page_fault(access, vaddr));
+            }
+            let page = if level == 1 {
+                // Superpages have to be aligned to 4 MiB
+                if ppn & 0x3FF != 0 {
+                    return Err(page_fault(access, vaddr));
+                }
+                ((ppn >> 10) << 22) | (u64::from(vpn[0]) << PAGE_SHIFT)
+            } else {
+                ppn << PAGE_SHIFT
+            };
+            return Ok((page, pte, pte_addr));
+        }
+        Err(page_fault(access, vaddr))
+    }
+
+    fn translation_active(&self, privilege: Privilege) -> bool {
+        privilege != Privilege::Machine && self.register.csr.satp_is_sv32()
+    }
+
+    /// The size of the part of an access that lies in the page of `vaddr`, if the access crosses into the next page
+    /// while translation is active. The pages are translated one by one, they need not be contiguous in physical memory.
+    pub(super) fn page_split(&self, vaddr: usize, size: usize, access: Access) -> Option<usize> {
+        let first = (PAGE_MASK as usize + 1) - (vaddr & PAGE_MASK as usize);
+        (first < size && self.translation_active(self.access_privilege(access))).then_some(first)
+    }
+
+    /// Translates a virtual into a physical address and checks it against the PMP.
+    /// Accessed and dirty bits of the page table entries are set by the hardware.
+    /// With translation active, the access has to stay within one page, see `page_split`.
+    pub(super) fn translate(
+        &mut self,
+        vaddr: usize,
+        size: usize,
+        access: Access,
+    ) -> anyhow::Result<usize> {
+        let privilege = self.access_privilege(access);
+        if !self.translation_active(privilege) {
+            self.check_access(vaddr, size, access, privilege)?;
+            return Ok(vaddr);
+        }
+        let vaddr = vaddr as u32;
+        let satp = self.register.csr.satp;
+        let vpn = vaddr >> PAGE_SHIFT;
+        let cached = self.tlb.lookup(satp, vpn).filter(|entry| {
+            // A store to a clean page has to go through the walk to set the dirty bit
+            !(matches!(access, Access::Write) && entry.pte & PTE_D == 0)
+        });
+        let entry = if let Some(entry) = cached {
+            if !self.leaf_permits(entry.pte, access, privilege) {
+                return Err(page_fault(access, vaddr));
+            }
+            entry
+        } else {
+            let (page, mut pte, pte_addr) = self.walk(vaddr, access, privilege)?;
+            let mut update = pte | PTE_A;
+            if matches!(access, Access::Write) {
+                update |= PTE_D;
+            }
+            if update != pte {
+                // The update is an implicit S-mode store, a page table in read-only memory faults
+                let pte_addr = usize::try_from(pte_addr)?;
+                let stored = self
+                    .check_access(pte_addr, 4, Access::Write, Privilege::Supervisor)
+                    .and_then(|()| self.memory.write_word(pte_addr, update));
+                if stored.is_err() {
+                    return Err(Exception::new(access.access_fault(), u64::from(vaddr)).into());
+                }
+                pte = update;
+                self.invalidate_stored(pte_addr, 4);
+            }
+            let entry = TlbEntry {
+                satp,
+                vpn,
+                ppn: page >> PAGE_SHIFT,
+                pte,
+            };
+            self.tlb.insert(entry);
+            entry
+        };
+        let paddr = usize::try_from((entry.ppn << PAGE_SHIFT) | u64::from(vaddr & PAGE_MASK))?;
+        self.check_access(paddr, size, access, privilege)?;
+        Ok(paddr)
+    }
+
+    /// Translates an address for inspection, e.g. by the TUI, without any side effects.
+    pub fn peek_translate(&self, vaddr: usize, access: Access) -> Option<usize> {
+        let privilege = self.access_privilege(access);
+        if !self.translation_active(privilege) {
+            return Some(vaddr);
+        }
+        let (page, _pte, _pte_addr) = self.walk(vaddr as u32, access, privilege).ok()?;
+        usize::try_from(page | u64::from(vaddr as u32 & PAGE_MASK)).ok()
+    }
+}
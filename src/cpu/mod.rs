@@ -1,92 +1,283 @@
 //! This file is scoped around the `CPU` struct.
 //! If something can not be `impl CPU` it is considered out of scope.
-use std::array;
+use std::cell::Cell;
+use std::collections::VecDeque;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
-
-use elf::abi;
-use elf::endian::AnyEndian;
-use elf::ElfBytes;
+use std::time::{Duration, Instant};
 
 use crate::events::{CpuJob, Event};
 use crate::instructions::{decode, Instruction};
 
-pub use memory::AddrBus;
-pub use register::{index_to_name, Register};
+pub use memory::{AddrBus, InputFrame, Region};
+pub use register::{index_to_name, Privilege, Register, Xlen, MIP_MEIP, MIP_MSIP, MIP_MTIP};
+
+pub use exception::Exception;
+pub use host::Host;
+pub use loader::{Format, Image};
+use memory::Access;
+use mmu::crosses_page;
 
+mod block;
+mod decode_cache;
+mod exception;
 mod executer;
+mod host;
+mod loader;
+mod lockstep;
 mod memory;
+mod mmu;
+mod pmp;
 mod register;
+mod semihosting;
+mod syscalls;
 
 const LOG_LENGTH: usize = 80;
 
+/// How instructions are executed, the interpreter is the reference for the other engines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Engine {
+    /// Fetches, decodes and executes one instruction at a time
+    #[default]
+    Interpreter,
+    /// Executes whole basic blocks of instructions lowered to micro-ops,
+    /// interrupts are only taken between blocks
+    Block,
+}
+
+/// How long the executor sleeps while the CPU waits for an interrupt.
+const IDLE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// All harts share the memory of the `CPU`, the state of the scheduled hart is held by the `CPU`.
+/// The state of the other harts is parked here and swapped in when they are scheduled.
+struct Hart {
+    register: Register,
+    privilege: Privilege,
+    waits_for_interrupt: bool,
+    tlb: mmu::Tlb,
+    instruction_log: VecDeque<(usize, Instruction)>,
+}
+
 pub struct CPU<T: AddrBus> {
     pub register: Register,
     pub memory: T,
+    pub privilege: Privilege,
     pub waits_for_interrupt: bool,
-    instruction_log: [Option<(usize, Instruction)>; LOG_LENGTH],
+    tlb: mmu::Tlb,
+    instruction_log: VecDeque<(usize, Instruction)>,
+    /// Shared by all harts, as they share the memory
+    decode_cache: decode_cache::DecodeCache,
+    blocks: block::BlockCache<T>,
+    /// The region of the memory map the last access hit, most accesses hit it again
+    last_region: Cell<Option<Region>>,
+    pub engine: Engine,
+    /// Executes the same program with the interpreter, used to check the other engines
+    reference: Option<Box<CPU<T>>>,
+    /// The number of instructions executed by all harts
+    pub executed_instructions: u64,
+    /// The last exception a trap was taken for, e.g. to tell which region an access fault hit
+    pub last_exception: Option<Exception>,
+    /// The `mhartid` of the hart that is currently scheduled
+    pub hart_id: usize,
+    harts: Vec<Option<Hart>>,
+    /// The harts are scheduled round-robin, each runs for this many steps
+    quantum: usize,
+    remaining_quantum: usize,
+    /// The harts run in parallel, the clock advances by one cycle once every hart took a step
+    round_steps: usize,
+    /// Where all harts start, after loading and after every reset
+    entry: u64,
+    host: Option<Host>,
+    /// Set once the program exits through a call to the host, 0 if it succeeded
+    pub exit_code: Option<i32>,
+    /// The end of the heap, moved by the `brk` system call
+    program_break: u64,
+    /// Where the stack of all harts starts, after loading and after every reset
+    stack_pointer: u64,
+    /// `argc` and `argv` of the program, in `a0` and `a1` after loading and after every reset
+    main_arguments: (u64, u64),
 }
 
 impl<T: AddrBus> CPU<T> {
-    pub fn from_elf(file: &[u8], memory: T) -> Self {
-        let mut cpu = Self {
+    /// A CPU without a program, the images are loaded into `memory` by `from_images`.
+    fn new(memory: T) -> Self {
+        Self {
             register: Register::default(),
             memory,
+            privilege: Privilege::Machine,
             waits_for_interrupt: false,
-            instruction_log: array::from_fn(|_| None),
-        };
-        cpu.register.csr.mie = 1;
-
-        let elffile =
-            ElfBytes::<AnyEndian>::minimal_parse(file).expect("Failed to parse provided ELF file");
-
-        if let Some(segments) = elffile.segments() {
-            for phdr in segments {
-                if phdr.p_type == abi::PT_LOAD {
-                    if let Ok(addr) = usize::try_from(phdr.p_paddr) {
-                        let data = elffile.segment_data(&phdr).unwrap();
-                        cpu.memory.load_at(addr, data);
-                    } else {
-                        panic!("Could not get PT_LOAD address in your ELF file.");
-                    }
-                }
-            }
-        } else {
-            panic!("Could not find segments in your ELF file.");
+            tlb: mmu::Tlb::default(),
+            instruction_log: VecDeque::with_capacity(LOG_LENGTH),
+            decode_cache: decode_cache::DecodeCache::default(),
+            blocks: block::BlockCache::default(),
+            last_region: Cell::new(None),
+            engine: Engine::Interpreter,
+            reference: None,
+            executed_instructions: 0,
+            last_exception: None,
+            hart_id: 0,
+            harts: vec![None],
+            quantum: 1,
+            remaining_quantum: 0,
+            round_steps: 0,
+            entry: 0,
+            host: None,
+            exit_code: None,
+            program_break: 0,
+            stack_pointer: 0,
+            main_arguments: (0, 0),
         }
+    }
 
-        cpu.register.pc =
-            u32::try_from(elffile.ehdr.e_entry).expect("Failed to read start address e_entry");
+    /// The registers of the hart `hart_id` after a reset, it starts at the entry address.
+    fn reset_register(&self, hart_id: usize) -> Register {
+        let mut register = Register::default();
+        register.pc = self.entry;
+        register.rve = self.register.rve;
+        register.write(2, self.stack_pointer);
+        register.write(10, self.main_arguments.0);
+        register.write(11, self.main_arguments.1);
+        register.csr.xlen = self.register.csr.xlen;
+        register.csr.mhartid = hart_id as u32;
+        register
+    }
 
-        cpu
+    /// Adds harts until there are `count`, they all start at the entry address.
+    /// Every hart runs for `quantum` steps before the next one is scheduled.
+    pub fn set_harts(&mut self, count: usize, quantum: usize) {
+        for hart_id in self.harts.len()..count {
+            self.harts.push(Some(Hart {
+                register: self.reset_register(hart_id),
+                privilege: Privilege::Machine,
+                waits_for_interrupt: false,
+                tlb: mmu::Tlb::default(),
+                instruction_log: VecDeque::with_capacity(LOG_LENGTH),
+            }));
+        }
+        self.quantum = quantum.max(1);
     }
 
-    pub fn from_bin(file: &[u8], memory: T, entry_address: usize, base_address: usize) -> Self {
-        let mut cpu = Self {
-            register: Register::default(),
-            memory,
-            waits_for_interrupt: false,
-            instruction_log: array::from_fn(|_| None),
-        };
+    /// Resets all harts and the peripherals, the loaded program stays in memory.
+    /// Hart 0 is scheduled first again, the instruction log is kept to see what led to the reset.
+    pub fn reset(&mut self) {
+        self.memory.reset();
+        let count = self.hart_count();
+        self.select_hart(0);
+        self.harts.truncate(1);
+        self.register = self.reset_register(0);
+        self.privilege = Privilege::Machine;
+        self.waits_for_interrupt = false;
+        self.tlb = mmu::Tlb::default();
+        self.last_exception = None;
+        self.set_harts(count, self.quantum);
+        self.remaining_quantum = self.quantum;
+        self.round_steps = 0;
+    }
 
-        cpu.memory.load_at(base_address, file);
+    /// Resets the system if a peripheral requested it, returns true if so.
+    /// Like interrupts, the reset is only taken between instructions or blocks.
+    fn check_reset(&mut self) -> bool {
+        let requested = self.memory.take_reset_request();
+        if requested {
+            self.reset();
+        }
+        requested
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.harts.len()
+    }
 
-        cpu.register.pc = entry_address as u32;
+    /// Swaps the state of the hart `hart_id` in, the one of the scheduled hart is parked.
+    fn select_hart(&mut self, hart_id: usize) {
+        if hart_id == self.hart_id {
+            return;
+        }
+        let mut hart = self.harts[hart_id]
+            .take()
+            .expect("The state of a parked hart is missing");
+        std::mem::swap(&mut self.register, &mut hart.register);
+        std::mem::swap(&mut self.privilege, &mut hart.privilege);
+        std::mem::swap(&mut self.waits_for_interrupt, &mut hart.waits_for_interrupt);
+        std::mem::swap(&mut self.tlb, &mut hart.tlb);
+        std::mem::swap(&mut self.instruction_log, &mut hart.instruction_log);
+        self.harts[self.hart_id] = Some(hart);
+        self.hart_id = hart_id;
+    }
 
-        cpu
+    /// Runs `f` with the state of the given hart, without changing the scheduling.
+    pub fn with_hart<R>(&mut self, hart_id: usize, f: impl FnOnce(&mut Self) -> R) -> R {
+        let scheduled = self.hart_id;
+        self.select_hart(hart_id);
+        let result = f(self);
+        self.select_hart(scheduled);
+        result
+    }
+
+    pub fn all_harts_wait(&self) -> bool {
+        self.waits_for_interrupt
+            && self
+                .harts
+                .iter()
+                .flatten()
+                .all(|hart| hart.waits_for_interrupt)
+    }
+
+    /// Returns true if an interrupt has occured on any hart
+    pub fn check_all_interrupts(&mut self) -> bool {
+        let mut occured = self.check_reset();
+        for hart_id in 0..self.hart_count() {
+            occured |= self.with_hart(hart_id, Self::check_interrupts);
+        }
+        if let Some(reference) = &mut self.reference {
+            reference.check_all_interrupts();
+        }
+        occured
     }
 
+    /// Decodes the instruction at the virtual address `addr` without side effects.
     pub fn instruction_at_addr(&self, addr: usize) -> anyhow::Result<Instruction> {
-        decode(self.memory.read_word(addr)?)
+        let paddr = self
+            .peek_translate(addr, Access::Execute)
+            .ok_or_else(|| anyhow::anyhow!("Address 0x{addr:08X} is not mapped"))?;
+        decode(self.memory.read_word(paddr)?, self.register.csr.xlen)
+    }
+
+    /// Fetches the instruction at `pc`, a 32 bit instruction may cross a page boundary.
+    /// Instructions within one page are taken from the decode cache, the ones that cross
+    /// into the next page are translated again on every fetch, as its mapping may change.
+    fn fetch(&mut self) -> anyhow::Result<Instruction> {
+        let addr = self.register.pc as usize;
+        let paddr = self.translate(addr, 2, Access::Execute)?;
+        if let Some(instruction) = self.decode_cache.get(paddr) {
+            if !instruction.is_compressed() {
+                // The upper half may be covered by another PMP entry
+                self.check_access(paddr + 2, 2, Access::Execute, self.privilege)?;
+            }
+            return Ok(*instruction);
+        }
+        let low = self.memory.read_halfword(paddr)?;
+        let xlen = self.register.csr.xlen;
+        if low & 0b11 != 0b11 {
+            let instruction = decode(low, xlen)?;
+            self.decode_cache.insert(paddr, instruction);
+            return Ok(instruction);
+        }
+        let paddr_high = self.translate(addr + 2, 2, Access::Execute)?;
+        let high = self.memory.read_halfword(paddr_high)?;
+        let instruction = decode(high << 16 | low, xlen)?;
+        if !crosses_page(paddr) {
+            self.decode_cache.insert(paddr, instruction);
+        }
+        Ok(instruction)
     }
 
     pub fn current_instruction(&self) -> anyhow::Result<(usize, Instruction)> {
         if self.waits_for_interrupt {
-            Ok(self
+            Ok(*self
                 .last_instruction()
-                .expect("How did you start waiting for an interrupt without executing WFI first?")
-                .clone())
+                .expect("How did you start waiting for an interrupt without executing WFI first?"))
         } else {
             let addr = self.register.pc as usize;
             let inst = self.instruction_at_addr(addr)?;
@@ -122,7 +313,10 @@ impl<T: AddrBus> CPU<T> {
                     addr += 4;
                 }
             } else {
-                instruction_list.push((addr, Err(self.memory.read_word(addr).unwrap_or(0))));
+                let word = self
+                    .peek_translate(addr, Access::Execute)
+                    .and_then(|paddr| self.memory.read_word(paddr).ok());
+                instruction_list.push((addr, Err(word.unwrap_or(0))));
                 addr += 4;
             }
         }
@@ -130,54 +324,210 @@ impl<T: AddrBus> CPU<T> {
     }
 
     pub fn last_instruction(&self) -> Option<&(usize, Instruction)> {
-        self.instruction_log.last().unwrap_or(&None).as_ref()
+        self.instruction_log.back()
+    }
+
+    /// The up to `n` last executed instructions, the oldest first
+    pub fn last_n_instructions(
+        &self,
+        n: usize,
+    ) -> impl ExactSizeIterator<Item = &(usize, Instruction)> {
+        self.instruction_log
+            .range(self.instruction_log.len().saturating_sub(n)..)
     }
 
-    pub fn last_n_instructions(&self, n: usize) -> &[Option<(usize, Instruction)>] {
-        if n > self.instruction_log.len() {
-            &self.instruction_log
+    /// Enters the trap handler, `epc` is the address the handler should return to.
+    /// Traps from below M-mode are handled in S-mode if they are delegated.
+    fn trap(&mut self, reason: register::MCAUSE, epc: u64, tval: u64) {
+        let delegation = if reason.is_interrupt() {
+            self.register.csr.mideleg
+        } else {
+            self.register.csr.medeleg
+        };
+        let csr = &mut self.register.csr;
+        if self.privilege < Privilege::Machine && delegation & (1 << reason.code()) != 0 {
+            csr.mstatus_set_spie(csr.mstatus_get_sie());
+            csr.mstatus_set_sie(false);
+            csr.mstatus_set_spp(self.privilege);
+            self.privilege = Privilege::Supervisor;
+            csr.sepc = epc;
+            csr.scause = reason.to_xlen(csr.xlen);
+            csr.stval = tval;
+            self.register.pc = register::trap_vector(csr.stvec, reason);
         } else {
-            &self.instruction_log[self.instruction_log.len() - n..]
+            csr.mstatus_set_mpie(csr.mstatus_get_mie());
+            csr.mstatus_set_mie(false);
+            csr.mstatus_set_mpp(self.privilege);
+            self.privilege = Privilege::Machine;
+            csr.mepc = epc;
+            csr.mcause = reason.to_xlen(csr.xlen);
+            csr.mtval = tval;
+            self.register.pc = register::trap_vector(csr.mtvec, reason);
         }
+        self.waits_for_interrupt = false;
     }
 
-    fn exception(&mut self, reason: register::MCAUSE) {
-        self.register
-            .csr
-            .mstatus_set_mpie(self.register.csr.mstatus_get_mie());
-        self.register.csr.mstatus_set_mie(false);
-        self.register.csr.mepc = self.register.pc;
-        self.register.csr.mcause = reason as u32;
-        self.register.pc = self.register.csr.mtvec;
-        self.waits_for_interrupt = false;
+    /// Takes the trap for the exception in `err`, the instruction at `epc` raised it.
+    /// Other errors are not recoverable and passed on.
+    fn trap_exception(&mut self, err: anyhow::Error, epc: usize) -> anyhow::Result<()> {
+        let exception = err.downcast::<Exception>()?;
+        self.trap(exception.cause, epc as u64, exception.tval);
+        self.last_exception = Some(exception);
+        Ok(())
     }
 
     /// Returns true if an interrupt has occured
     pub fn check_interrupts(&mut self) -> bool {
-        // Interrupts are implicitly enabled when stalling the cpu due to WFI
-        // Or directly enabled via MIE
-        if self.waits_for_interrupt || self.register.csr.mstatus_get_mie() {
-            if let Some(_reason) = self.memory.pending_interrupt() {
-                self.exception(register::MCAUSE::MachineExternalInterrupt);
-                return true;
+        let csr = &mut self.register.csr;
+        csr.mip =
+            (csr.mip & register::MIP_SOFTWARE_MASK) | self.memory.pending_interrupts(self.hart_id);
+        let pending = csr.mip & csr.mie;
+        if pending == 0 {
+            return false;
+        }
+        // A pending and enabled interrupt always ends the WFI stall,
+        // but the trap is only taken if interrupts are globally enabled.
+        let waited_for_interrupt = self.waits_for_interrupt;
+        self.waits_for_interrupt = false;
+        // Interrupts for a higher privilege are always enabled, for the same one only with xIE
+        let machine_enabled = self.privilege < Privilege::Machine || csr.mstatus_get_mie();
+        let supervisor_enabled = self.privilege < Privilege::Supervisor
+            || (self.privilege == Privilege::Supervisor && csr.mstatus_get_sie());
+        let mut enabled = 0;
+        if machine_enabled {
+            enabled |= pending & !csr.mideleg;
+        }
+        if supervisor_enabled {
+            enabled |= pending & csr.mideleg;
+        }
+        if enabled == 0 {
+            return waited_for_interrupt;
+        }
+        // Interrupts destined for M-mode are taken before the ones for S-mode
+        let (_bit, reason) = register::INTERRUPT_PRIORITY
+            .into_iter()
+            .filter(|(bit, _reason)| enabled & bit != 0)
+            .min_by_key(|(bit, _reason)| csr.mideleg & bit != 0)
+            .expect("Pending interrupt without known priority");
+        self.trap(reason, self.register.pc, 0);
+        true
+    }
+
+    /// Lets the given amount of host time pass without executing instructions.
+    /// Used while the CPU waits for an interrupt, so that timers keep running.
+    pub fn idle(&mut self, elapsed: Duration) {
+        let cycles = u128::from(self.memory.clock_frequency()) * elapsed.as_micros() / 1_000_000;
+        let cycles = u64::try_from(cycles).unwrap_or(u64::MAX);
+        self.memory.tick(cycles);
+        if let Some(reference) = &mut self.reference {
+            reference.memory.replay_input(self.memory.take_input());
+            reference.memory.tick(cycles);
+        }
+    }
+
+    /// Lets the clock run for `steps` steps of the scheduled hart.
+    fn advance_clock(&mut self, steps: usize) {
+        self.round_steps += steps;
+        let cycles = self.round_steps / self.hart_count();
+        self.round_steps %= self.hart_count();
+        if cycles > 0 {
+            self.memory.tick(cycles as u64);
+        }
+    }
+
+    /// Schedules the next hart once the quantum of the current one is used up.
+    fn schedule(&mut self) {
+        if self.remaining_quantum == 0 {
+            self.select_hart((self.hart_id + 1) % self.hart_count());
+            self.remaining_quantum = self.quantum;
+        }
+    }
+
+    /// Executes the instruction at `pc`, exceptions are trapped.
+    /// Returns false after executing ebreak.
+    fn execute(&mut self) -> anyhow::Result<bool> {
+        let addr = self.register.pc as usize;
+        let inst = match self.fetch() {
+            Ok(inst) => inst,
+            Err(err) => {
+                self.trap_exception(err, addr)?;
+                return Ok(true);
             }
+        };
+        self.execute_fetched(addr, inst)
+    }
+
+    /// Executes the already fetched instruction at `addr`, exceptions are trapped.
+    /// Returns false after executing ebreak, unless it makes a semihosting call,
+    /// and once the program exits through a call to the host.
+    fn execute_fetched(&mut self, addr: usize, inst: Instruction) -> anyhow::Result<bool> {
+        let continue_exec = self.execute_unlogged(addr, inst)?;
+        self.log_instruction(addr, inst);
+        Ok(continue_exec)
+    }
+
+    /// Like `execute_fetched`, but the instruction is neither counted nor logged.
+    fn execute_unlogged(&mut self, addr: usize, inst: Instruction) -> anyhow::Result<bool> {
+        Ok(
+            if matches!(inst, Instruction::ECALL()) && self.emulates_syscalls() {
+                // The system call is made instead of taking the trap
+                self.syscall(addr)?
+            } else {
+                if let Err(err) = self.exec(&inst, true, true) {
+                    self.trap_exception(err, addr)?;
+                }
+                if !matches!(inst, Instruction::EBREAK()) {
+                    true
+                } else if self.is_semihosting_call(addr) {
+                    self.semihosting_call(addr)?
+                } else {
+                    false
+                }
+            },
+        )
+    }
+
+    /// Counts the executed instruction at `addr` and appends it to the log.
+    fn log_instruction(&mut self, addr: usize, inst: Instruction) {
+        self.executed_instructions += 1;
+        if self.instruction_log.len() == LOG_LENGTH {
+            self.instruction_log.pop_front();
         }
-        false
+        self.instruction_log.push_back((addr, inst));
     }
 
     /// Returns true for all instructions except when executing ebreak.
     /// ebreak is used to signal the termination of the programm.
     pub fn step(&mut self) -> anyhow::Result<bool> {
+        self.schedule();
+        self.check_reset();
+        self.remaining_quantum -= 1;
         self.check_interrupts();
-        // Stall when waiting for interrupts
+        // The clock keeps running, even when stalled
+        self.advance_clock(1);
+        // Stall when waiting for interrupts, the rest of the quantum is left to the other harts.
+        // The stall lasts as long as the quantum would have, as the other harts run meanwhile.
         if self.waits_for_interrupt {
+            self.advance_clock(self.remaining_quantum);
+            self.remaining_quantum = 0;
             Ok(true)
         } else {
-            let (addr, inst) = self.current_instruction()?;
-            self.exec(&inst, true, true)?;
-            self.instruction_log.rotate_left(1);
-            self.instruction_log[LOG_LENGTH - 1] = Some((addr, inst.clone()));
-            Ok(!matches!(inst, Instruction::EBREAK()))
+            self.execute()
+        }
+    }
+
+    /// Executes up to `limit` instructions with the selected engine.
+    /// Returns false after executing ebreak, together with the number of steps taken.
+    pub fn run(&mut self, limit: usize) -> anyhow::Result<(bool, usize)> {
+        match self.engine {
+            Engine::Interpreter => Ok((self.step()?, 1)),
+            Engine::Block => {
+                let (continue_exec, steps) = self.step_block(limit)?;
+                if self.reference.is_some() {
+                    self.lockstep(steps)?;
+                }
+                Ok((continue_exec, steps))
+            }
         }
     }
 }
@@ -197,14 +547,30 @@ fn cpu_executor<T: AddrBus>(
     receiver: &Receiver<CpuJob>,
 ) {
     let mut autostep = false;
+    let mut last_idle = Instant::now();
     loop {
-        let cpu_waits_for_interrupt = { cpu.lock().unwrap().waits_for_interrupt };
+        let cpu_waits_for_interrupt = { cpu.lock().unwrap().all_harts_wait() };
         let job = if autostep && !cpu_waits_for_interrupt {
             match receiver.try_recv() {
                 Ok(job) => job,
                 Err(std::sync::mpsc::TryRecvError::Empty) => CpuJob::Step(307),
                 Err(_e) => return,
             }
+        } else if autostep {
+            // While waiting for an interrupt, the time still has to pass for the timers
+            match receiver.recv_timeout(IDLE_INTERVAL) {
+                Ok(job) => job,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    let mut cpu = cpu.lock().unwrap();
+                    cpu.idle(last_idle.elapsed());
+                    last_idle = Instant::now();
+                    if cpu.check_all_interrupts() {
+                        sender.send(Event::CpuStepComplete(true)).unwrap();
+                    }
+                    continue;
+                }
+                Err(_e) => return,
+            }
         } else {
             // If we wait for interrupt, we wait
             // If we don't wait for interrupt, we wait anyway for the next CpuJob
@@ -232,7 +598,7 @@ fn cpu_executor<T: AddrBus>(
             CpuJob::CheckInterrupts => {
                 {
                     let mut cpu = cpu.lock().unwrap();
-                    if cpu.check_interrupts() {
+                    if cpu.check_all_interrupts() {
                         sender.send(Event::CpuStepComplete(true)).unwrap();
                     }
                 }
@@ -241,11 +607,14 @@ fn cpu_executor<T: AddrBus>(
         };
 
         let mut continue_exec = true;
+        last_idle = Instant::now();
         {
             let mut cpu = cpu.lock().unwrap();
-            for _ in 0..steps {
-                match cpu.step() {
-                    Ok(con_exe) => {
+            let mut done = 0;
+            while done < steps {
+                match cpu.run(steps - done) {
+                    Ok((con_exe, taken)) => {
+                        done += taken;
                         if !con_exe {
                             continue_exec = false;
                             break;
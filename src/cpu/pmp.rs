@@ -0,0 +1,123 @@
+//! The Physical Memory Protection unit is scoped for this file.
+//! It holds the `pmpcfg` and `pmpaddr` CSRs and decides if an access is permitted.
+use super::memory::Access;
+use super::register::Privilege;
+
+const ENTRIES: usize = 16;
+
+const CFG_R: u8 = 1 << 0;
+const CFG_W: u8 = 1 << 1;
+const CFG_X: u8 = 1 << 2;
+const CFG_A_SHIFT: u8 = 3;
+const CFG_L: u8 = 1 << 7;
+// Bits 5 and 6 are reserved and read as zero
+const CFG_MASK: u8 = 0b1001_1111;
+
+#[derive(PartialEq)]
+enum AddressMatching {
+    Off,
+    TopOfRange,
+    NaturallyAlignedFour,
+    NaturallyAlignedPowerOfTwo,
+}
+
+#[derive(Default)]
+pub struct Pmp {
+    cfg: [u8; ENTRIES],
+    addr: [u32; ENTRIES],
+}
+
+impl Pmp {
+    fn matching(&self, index: usize) -> AddressMatching {
+        match (self.cfg[index] >> CFG_A_SHIFT) & 0b11 {
+            0b00 => AddressMatching::Off,
+            0b01 => AddressMatching::TopOfRange,
+            0b10 => AddressMatching::NaturallyAlignedFour,
+            0b11 => AddressMatching::NaturallyAlignedPowerOfTwo,
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_locked(&self, index: usize) -> bool {
+        self.cfg[index] & CFG_L != 0
+    }
+
+    /// Reads `pmpcfgN`, each register holds the configuration of four entries.
+    pub fn read_cfg(&self, register: usize) -> u32 {
+        let cfg = &self.cfg[register * 4..register * 4 + 4];
+        u32::from_le_bytes([cfg[0], cfg[1], cfg[2], cfg[3]])
+    }
+
+    pub fn write_cfg(&mut self, register: usize, value: u32) {
+        for (byte, cfg) in value.to_le_bytes().into_iter().enumerate() {
+            let index = register * 4 + byte;
+            if !self.is_locked(index) {
+                self.cfg[index] = cfg & CFG_MASK;
+            }
+        }
+    }
+
+    pub fn read_addr(&self, index: usize) -> u32 {
+        self.addr[index]
+    }
+
+    pub fn write_addr(&mut self, index: usize, value: u32) {
+        // A locked TOR entry also locks the address of the entry below it
+        let locked_by_next = index + 1 < ENTRIES
+            && self.is_locked(index + 1)
+            && self.matching(index + 1) == AddressMatching::TopOfRange;
+        if !self.is_locked(index) && !locked_by_next {
+            self.addr[index] = value;
+        }
+    }
+
+    /// The byte range `[start, end)` covered by the entry, if it is active.
+    fn range(&self, index: usize) -> Option<(u64, u64)> {
+        let addr = u64::from(self.addr[index]);
+        match self.matching(index) {
+            AddressMatching::Off => None,
+            AddressMatching::TopOfRange => {
+                let bottom = if index == 0 {
+                    0
+                } else {
+                    u64::from(self.addr[index - 1])
+                };
+                Some((bottom << 2, addr << 2))
+            }
+            AddressMatching::NaturallyAlignedFour => Some((addr << 2, (addr << 2) + 4)),
+            AddressMatching::NaturallyAlignedPowerOfTwo => {
+                let trailing_ones = u64::from(self.addr[index].trailing_ones());
+                let base = (addr & !((1 << (trailing_ones + 1)) - 1)) << 2;
+                Some((base, base + (1 << (trailing_ones + 3))))
+            }
+        }
+    }
+
+    /// Returns true if the access of `size` bytes at `addr` is permitted.
+    pub fn check(&self, addr: usize, size: usize, access: Access, privilege: Privilege) -> bool {
+        let start = addr as u64;
+        let end = start + size as u64;
+        for index in 0..ENTRIES {
+            let Some((bottom, top)) = self.range(index) else {
+                continue;
+            };
+            if start >= bottom && end <= top {
+                if privilege == Privilege::Machine && !self.is_locked(index) {
+                    return true;
+                }
+                let required = match access {
+                    Access::Read => CFG_R,
+                    Access::Write => CFG_W,
+                    Access::Execute => CFG_X,
+                };
+                return self.cfg[index] & required != 0;
+            }
+            if start < top && end > bottom {
+                // Accesses that only partially match an entry always fail
+                return false;
+            }
+        }
+        // No entry matched, only M-mode has access by default
+        privilege == Privilege::Machine
+    }
+}
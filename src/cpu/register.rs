@@ -1,5 +1,6 @@
 //! Everything related to registers is scoped to this file.
 //! This includes naming, definitions, usage and pretty printing
+use super::pmp::Pmp;
 use crate::instructions::Rindex;
 
 pub fn index_to_name(register: Rindex) -> &'static str {
@@ -40,8 +41,48 @@ pub fn index_to_name(register: Rindex) -> &'static str {
     }
 }
 
+/// The native register width of the hart
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Xlen {
+    #[default]
+    Rv32,
+    Rv64,
+}
+
+impl Xlen {
+    pub fn bits(self) -> u32 {
+        match self {
+            Xlen::Rv32 => 32,
+            Xlen::Rv64 => 64,
+        }
+    }
+
+    /// Masks everything above XLEN, registers always hold zero-extended values.
+    pub fn truncate(self, value: u64) -> u64 {
+        match self {
+            Xlen::Rv32 => value & 0xFFFF_FFFF,
+            Xlen::Rv64 => value,
+        }
+    }
+
+    /// Interprets the register value as two's complement number of XLEN bits.
+    pub fn signed(self, value: u64) -> i64 {
+        match self {
+            Xlen::Rv32 => i64::from(value as i32),
+            Xlen::Rv64 => value as i64,
+        }
+    }
+
+    /// Shift amounts only use the lower 5 (RV32) or 6 (RV64) bits
+    pub fn shamt(self, value: u64) -> u32 {
+        (value & u64::from(self.bits() - 1)) as u32
+    }
+}
+
 #[derive(Default)]
 pub struct CSR {
+    /// Mirrors `misa.MXL`, the hart never changes it at runtime
+    pub xlen: Xlen,
     /* Machine Information Registers */
     pub mvendorid: u32,
     pub marchid: u32,
@@ -54,80 +95,151 @@ pub struct CSR {
     pub medeleg: u32,
     pub mideleg: u32,
     pub mie: u32,
-    pub mtvec: u32,
+    pub mtvec: u64,
     pub mcounteren: u32,
     pub mstatush: u32,
     /* Machine Trap Handling */
-    pub mscratch: u32,
-    pub mepc: u32,
-    pub mcause: u32,
-    pub mtval: u32,
+    pub mscratch: u64,
+    pub mepc: u64,
+    pub mcause: u64,
+    pub mtval: u64,
     pub mip: u32,
     pub mtinst: u32,
     pub mtval2: u32,
+    /* Machine Memory Protection */
+    pub pmp: Pmp,
+    /* Supervisor Trap Setup, sstatus, sie and sip are views of their machine counterparts */
+    pub stvec: u64,
+    pub scounteren: u32,
+    /* Supervisor Trap Handling */
+    pub sscratch: u64,
+    pub sepc: u64,
+    pub scause: u64,
+    pub stval: u64,
+    /* Supervisor Protection and Translation */
+    pub satp: u32,
 }
 
 #[allow(clippy::manual_range_patterns)]
 impl CSR {
-    pub fn read(&self, index: u32) -> u32 {
+    pub fn read(&self, index: u32) -> u64 {
         match index {
-            0xF11 => self.mvendorid,
-            0xF12 => self.marchid,
-            0xF13 => self.mimpid,
-            0xF14 => self.mhartid,
-            0xF15 => self.mconfigptr,
-            0x300 => self.mstatus,
-            0x301 => self.misa,
-            0x302 => self.medeleg,
-            0x303 => self.mideleg,
-            0x304 => self.mie,
+            0x100 => u64::from(self.mstatus & SSTATUS_MASK) | self.xl_fields(UXL_SHIFT),
+            0x104 => u64::from(self.mie & self.mideleg),
+            0x105 => self.stvec,
+            0x106 => u64::from(self.scounteren),
+            0x140 => self.sscratch,
+            0x141 => self.sepc,
+            0x142 => self.scause,
+            0x143 => self.stval,
+            0x144 => u64::from(self.mip & self.mideleg),
+            0x180 => u64::from(self.satp),
+            0xF11 => u64::from(self.mvendorid),
+            0xF12 => u64::from(self.marchid),
+            0xF13 => u64::from(self.mimpid),
+            0xF14 => u64::from(self.mhartid),
+            0xF15 => u64::from(self.mconfigptr),
+            0x300 => {
+                u64::from(self.mstatus) | self.xl_fields(UXL_SHIFT) | self.xl_fields(SXL_SHIFT)
+            }
+            0x301 => u64::from(self.misa),
+            0x302 => u64::from(self.medeleg),
+            0x303 => u64::from(self.mideleg),
+            0x304 => u64::from(self.mie),
             0x305 => self.mtvec,
-            0x306 => self.mcounteren,
-            0x310 => self.mstatush,
+            0x306 => u64::from(self.mcounteren),
+            0x310 => u64::from(self.mstatush),
             0x340 => self.mscratch,
             0x341 => self.mepc,
             0x342 => self.mcause,
             0x343 => self.mtval,
-            0x344 => self.mip,
-            0x34A => self.mtinst,
-            0x34B => self.mtval2,
+            0x344 => u64::from(self.mip),
+            0x34A => u64::from(self.mtinst),
+            0x34B => u64::from(self.mtval2),
+            // On RV64 the even pmpcfg registers hold the configuration of eight entries
+            0x3A0 | 0x3A2 if self.xlen == Xlen::Rv64 => {
+                let register = (index - 0x3A0) as usize;
+                u64::from(self.pmp.read_cfg(register))
+                    | u64::from(self.pmp.read_cfg(register + 1)) << 32
+            }
+            0x3A0..=0x3A3 => u64::from(self.pmp.read_cfg((index - 0x3A0) as usize)),
+            0x3B0..=0x3BF => u64::from(self.pmp.read_addr((index - 0x3B0) as usize)),
             _ => {
                 todo!();
             }
         }
     }
 
-    pub fn write(&mut self, index: u32, value: u32) {
+    /// On RV64 the `UXL` and `SXL` fields of `mstatus` report the fixed XLEN of 64 bit
+    fn xl_fields(&self, shift: u32) -> u64 {
+        match self.xlen {
+            Xlen::Rv32 => 0,
+            Xlen::Rv64 => 0b10 << shift,
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub fn write(&mut self, index: u32, value: u64) {
+        // Only the XLEN wide registers keep the upper half on RV64
+        let narrow = value as u32;
         match index {
             0xF11 | 0xF12 | 0xF13 | 0xF14 | 0xF15 => {
                 panic!("Attempt to write to read-only CSR!");
             }
+            0x100 => {
+                self.mstatus = (self.mstatus & !SSTATUS_MASK) | (narrow & SSTATUS_MASK);
+            }
+            0x104 => {
+                self.mie = (self.mie & !self.mideleg) | (narrow & self.mideleg);
+            }
+            0x105 => {
+                self.stvec = legalize_tvec(self.xlen.truncate(value));
+            }
+            0x106 => {
+                println!("Ingoring write of {value:X} into scounteren");
+                self.scounteren = 0;
+            }
+            0x140 => {
+                self.sscratch = self.xlen.truncate(value);
+            }
+            0x141 => {
+                self.sepc = self.xlen.truncate(value);
+            }
+            0x142 => {
+                self.scause = self.xlen.truncate(value);
+            }
+            0x143 => {
+                self.stval = self.xlen.truncate(value);
+            }
+            0x144 => {
+                // Only the supervisor software interrupt can be raised from S-mode
+                let writable = MIP_SSIP & self.mideleg;
+                self.mip = (self.mip & !writable) | (narrow & writable);
+            }
+            0x180 => {
+                // Only Bare is implemented for RV64, writes of other modes have no effect
+                if self.xlen == Xlen::Rv32 || value >> 60 == 0 {
+                    self.satp = narrow;
+                }
+            }
             0x300 => {
-                self.mstatus = value;
+                self.mstatus = narrow;
             }
             0x301 => {
                 /* WARL / zero indicates misa is not implemented */
                 self.misa = 0;
             }
             0x302 => {
-                println!("Ingoring write of {value:X} into medeleg");
-                self.medeleg = 0;
+                self.medeleg = narrow & MEDELEG_MASK;
             }
             0x303 => {
-                println!("Ingoring write of {value:X} into mideleg");
-                self.mideleg = 0;
+                self.mideleg = narrow & MIDELEG_MASK;
             }
             0x304 => {
-                self.mie = 0;
+                self.mie = narrow & MIP_MASK;
             }
             0x305 => {
-                if !value.is_multiple_of(4) {
-                    assert!(
-                        value.is_multiple_of(4),
-                        "mtvec value not 4-byte aligned or mode other than Direct selected"
-                    );
-                }
-                self.mtvec = value;
+                self.mtvec = legalize_tvec(self.xlen.truncate(value));
             }
             0x306 => {
                 println!("Ingoring write of {value:X} into mcounteren");
@@ -138,21 +250,20 @@ impl CSR {
                 self.mstatush = 0;
             }
             0x340 => {
-                self.mscratch = value;
+                self.mscratch = self.xlen.truncate(value);
             }
             0x341 => {
-                self.mepc = value;
+                self.mepc = self.xlen.truncate(value);
             }
             0x342 => {
-                self.mcause = value;
+                self.mcause = self.xlen.truncate(value);
             }
             0x343 => {
-                println!("Ingoring write of {value:X} into mtval");
-                self.mtval = 0;
+                self.mtval = self.xlen.truncate(value);
             }
             0x344 => {
-                println!("Ingoring write of {value:X} into mip");
-                self.mip = 0;
+                // The machine level bits are read-only and driven by the interrupt sources
+                self.mip = (self.mip & !MIP_SOFTWARE_MASK) | (narrow & MIP_SOFTWARE_MASK);
             }
             0x34A => {
                 println!("Ingoring write of {value:X} into mtinst");
@@ -162,6 +273,17 @@ impl CSR {
                 println!("Ingoring write of {value:X} into mtval2");
                 self.mtval2 = 0;
             }
+            0x3A0 | 0x3A2 if self.xlen == Xlen::Rv64 => {
+                let register = (index - 0x3A0) as usize;
+                self.pmp.write_cfg(register, narrow);
+                self.pmp.write_cfg(register + 1, (value >> 32) as u32);
+            }
+            0x3A0..=0x3A3 => {
+                self.pmp.write_cfg((index - 0x3A0) as usize, narrow);
+            }
+            0x3B0..=0x3BF => {
+                self.pmp.write_addr((index - 0x3B0) as usize, narrow);
+            }
             _ => {
                 todo!();
             }
@@ -188,59 +310,250 @@ impl CSR {
         self.mstatus & (1 << 3) > 0
     }
 
-    pub fn _mstatus_get_mpie(&self) -> bool {
+    pub fn mstatus_get_mpie(&self) -> bool {
         self.mstatus & (1 << 7) > 0
     }
+
+    pub fn mstatus_set_sie(&mut self, value: bool) {
+        if value {
+            self.mstatus |= 1 << 1;
+        } else {
+            self.mstatus &= !(1 << 1);
+        }
+    }
+
+    pub fn mstatus_get_sie(&self) -> bool {
+        self.mstatus & (1 << 1) > 0
+    }
+
+    pub fn mstatus_set_spie(&mut self, value: bool) {
+        if value {
+            self.mstatus |= 1 << 5;
+        } else {
+            self.mstatus &= !(1 << 5);
+        }
+    }
+
+    pub fn mstatus_get_spie(&self) -> bool {
+        self.mstatus & (1 << 5) > 0
+    }
+
+    pub fn mstatus_get_spp(&self) -> Privilege {
+        Privilege::from_bits((self.mstatus >> 8) & 0b1)
+    }
+
+    /// SPP is only one bit wide, it can not hold M-mode
+    pub fn mstatus_set_spp(&mut self, value: Privilege) {
+        self.mstatus &= !(1 << 8);
+        self.mstatus |= ((value as u32) & 0b1) << 8;
+    }
+
+    pub fn mstatus_get_sum(&self) -> bool {
+        self.mstatus & (1 << 18) > 0
+    }
+
+    pub fn mstatus_get_mxr(&self) -> bool {
+        self.mstatus & (1 << 19) > 0
+    }
+
+    pub fn mstatus_get_mpp(&self) -> Privilege {
+        Privilege::from_bits((self.mstatus >> 11) & 0b11)
+    }
+
+    pub fn mstatus_set_mpp(&mut self, value: Privilege) {
+        self.mstatus &= !(0b11 << 11);
+        self.mstatus |= (value as u32) << 11;
+    }
+
+    pub fn mstatus_get_mprv(&self) -> bool {
+        self.mstatus & (1 << 17) > 0
+    }
+
+    pub fn mstatus_set_mprv(&mut self, value: bool) {
+        if value {
+            self.mstatus |= 1 << 17;
+        } else {
+            self.mstatus &= !(1 << 17);
+        }
+    }
+
+    pub fn satp_is_sv32(&self) -> bool {
+        self.xlen == Xlen::Rv32 && self.satp & (1 << 31) != 0
+    }
+
+    /// The physical address of the root page table
+    pub fn satp_root(&self) -> u64 {
+        u64::from(self.satp & 0x003F_FFFF) << 12
+    }
+}
+
+const TVEC_MODE_VECTORED: u64 = 1;
+
+const UXL_SHIFT: u32 = 32;
+const SXL_SHIFT: u32 = 34;
+
+/// WARL: Only Direct (0) and Vectored (1) are valid modes, fall back to Direct
+fn legalize_tvec(value: u64) -> u64 {
+    if value & 0b11 > TVEC_MODE_VECTORED {
+        println!("Unsupported trap vector mode in {value:X}, using Direct mode");
+        value & !0b11
+    } else {
+        value
+    }
+}
+
+/// Returns the address of the trap handler for the given `mtvec` or `stvec` value.
+/// Only interrupts are dispatched via the vector table, exceptions always use the base.
+pub fn trap_vector(tvec: u64, reason: MCAUSE) -> u64 {
+    let base = tvec & !0b11;
+    if reason.is_interrupt() && tvec & 0b11 == TVEC_MODE_VECTORED {
+        base + 4 * u64::from(reason.code())
+    } else {
+        base
+    }
+}
+
+// The bits of mstatus that are visible via sstatus: SIE, SPIE, SPP, SUM and MXR
+const SSTATUS_MASK: u32 = (1 << 1) | (1 << 5) | (1 << 8) | (1 << 18) | (1 << 19);
+
+// All exceptions can be delegated, except for an ecall from M-mode
+const MEDELEG_MASK: u32 = 0xB3FF;
+
+// Interrupt Pending / Enable bits
+// The same bit positions are used in `mip` and `mie`.
+pub const MIP_SSIP: u32 = 1 << 1;
+pub const MIP_MSIP: u32 = 1 << 3;
+pub const MIP_STIP: u32 = 1 << 5;
+pub const MIP_MTIP: u32 = 1 << 7;
+pub const MIP_SEIP: u32 = 1 << 9;
+pub const MIP_MEIP: u32 = 1 << 11;
+const MIP_MASK: u32 = MIP_SSIP | MIP_MSIP | MIP_STIP | MIP_MTIP | MIP_SEIP | MIP_MEIP;
+// The supervisor level bits in `mip` are written by software, e.g. to forward the timer
+pub const MIP_SOFTWARE_MASK: u32 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+const MIDELEG_MASK: u32 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+
+/// The interrupts in decreasing priority, as defined by the privileged spec.
+pub const INTERRUPT_PRIORITY: [(u32, MCAUSE); 6] = [
+    (MIP_MEIP, MCAUSE::MachineExternalInterrupt),
+    (MIP_MSIP, MCAUSE::MachineSoftwareInterrupt),
+    (MIP_MTIP, MCAUSE::MachineTimerInterrupt),
+    (MIP_SEIP, MCAUSE::SupervisorExternalInterrupt),
+    (MIP_SSIP, MCAUSE::SupervisorSoftwareInterrupt),
+    (MIP_STIP, MCAUSE::SupervisorTimerInterrupt),
+];
+
+/// The privilege level the hart is currently running in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl Privilege {
+    /// Decodes the two bit encoding used in e.g. `mstatus.MPP` or in CSR addresses.
+    /// Levels that are not implemented fall back to User.
+    pub fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b11 => Privilege::Machine,
+            0b01 => Privilege::Supervisor,
+            _ => Privilege::User,
+        }
+    }
 }
 
 // Machine Cause Register
 // The Interrupt bit (msb, the 31th) is set if the trap was caused by an interrupt.
+#[derive(Clone, Copy, Debug)]
 #[repr(u32)]
 pub enum MCAUSE {
-    _MachineSoftwareInterrupt = 0x8000_0000 + 3,
+    SupervisorSoftwareInterrupt = 0x8000_0000 + 1,
+    SupervisorTimerInterrupt = 0x8000_0000 + 5,
+    SupervisorExternalInterrupt = 0x8000_0000 + 9,
+    MachineSoftwareInterrupt = 0x8000_0000 + 3,
+    MachineTimerInterrupt = 0x8000_0000 + 7,
     MachineExternalInterrupt = 0x8000_0000 + 11,
     _CounterOverflowInterrupt = 0x8000_0000 + 13,
     _InstructionAddressMisaligned = 0,
-    _InstructionAccessFault = 1,
-    _IllegalInstruction = 2,
+    InstructionAccessFault = 1,
+    IllegalInstruction = 2,
     _Breakpoint = 3,
     _LoadAddressMisaligned = 4,
-    _LoadAccessFault = 5,
+    LoadAccessFault = 5,
     _StoreAddressMisaligned = 6,
-    _StoreAccessFault = 7,
-    _EcallFromUser = 8,
-    _EcallFromMachine = 11,
-    _InstructionPageFault = 12,
-    _LoadPageFault = 13,
-    _StorePageFault = 15,
+    StoreAccessFault = 7,
+    EcallFromUser = 8,
+    EcallFromSupervisor = 9,
+    EcallFromMachine = 11,
+    InstructionPageFault = 12,
+    LoadPageFault = 13,
+    StorePageFault = 15,
     _SoftwareCheck = 18,
     _HardwareError = 19,
 }
 
+impl MCAUSE {
+    pub fn is_interrupt(self) -> bool {
+        (self as u32) & 0x8000_0000 != 0
+    }
+
+    /// The cause number without the interrupt bit
+    pub fn code(self) -> u32 {
+        (self as u32) & !0x8000_0000
+    }
+
+    /// The value of `mcause`, the interrupt bit is always the msb of XLEN
+    pub fn to_xlen(self, xlen: Xlen) -> u64 {
+        let interrupt = u64::from(self.is_interrupt()) << (xlen.bits() - 1);
+        interrupt | u64::from(self.code())
+    }
+}
+
 #[derive(Default)]
 pub struct Register {
-    regs: [u32; 32],
+    regs: [u64; 32],
     pub csr: CSR,
-    pub pc: u32,
+    pub pc: u64,
+    /// The RV32E base ISA only has the registers x0-x15
+    pub rve: bool,
 }
 
 impl Register {
-    pub fn read(&self, index: Rindex) -> u32 {
+    /// The number of integer registers
+    pub fn count(&self) -> usize {
+        if self.rve {
+            16
+        } else {
+            32
+        }
+    }
+
+    pub fn read(&self, index: Rindex) -> u64 {
         self.regs[index]
     }
 
-    pub fn write(&mut self, index: Rindex, value: u32) {
+    /// Values wider than XLEN are truncated
+    pub fn write(&mut self, index: Rindex, value: u64) {
         if index > 0 {
-            self.regs[index] = value;
+            self.regs[index] = self.csr.xlen.truncate(value);
         }
     }
 
     pub fn to_string(&self, index: Rindex) -> String {
-        format!(
-            "{:>4}: 0x{:08X} / {:>11}",
-            index_to_name(index),
-            self.regs[index],
-            self.regs[index] as i32
-        )
+        let value = self.regs[index];
+        match self.csr.xlen {
+            Xlen::Rv32 => format!(
+                "{:>4}: 0x{:08X} / {:>11}",
+                index_to_name(index),
+                value,
+                value as i32
+            ),
+            Xlen::Rv64 => format!(
+                "{:>4}: 0x{:016X} / {:>20}",
+                index_to_name(index),
+                value,
+                value as i64
+            ),
+        }
     }
 }
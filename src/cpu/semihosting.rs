@@ -0,0 +1,177 @@
+//! RISC-V semihosting is scoped for this file.
+//! A call is an `ebreak` between `slli zero, zero, 0x1f` and `srai zero, zero, 7`, none of them compressed.
+//! `a0` selects the operation and `a1` holds its parameter or points to a block of XLEN wide fields,
+//! the result is returned in `a0`. Execution continues after the sequence, unless the program exits.
+//! `:tt` is the console, other files are opened in the sandbox of the host.
+use std::fs::OpenOptions;
+
+use super::host::{Call, Outcome, Sandbox};
+use super::memory::Access;
+use super::{AddrBus, Xlen, CPU};
+
+const SLLI_ZERO: u32 = 0x01F0_1013;
+const EBREAK: u32 = 0x0010_0073;
+const SRAI_ZERO: u32 = 0x4070_5013;
+
+const SYS_OPEN: u64 = 0x01;
+const SYS_CLOSE: u64 = 0x02;
+const SYS_WRITEC: u64 = 0x03;
+const SYS_WRITE0: u64 = 0x04;
+const SYS_WRITE: u64 = 0x05;
+const SYS_READ: u64 = 0x06;
+const SYS_CLOCK: u64 = 0x10;
+const SYS_GETCMDLINE: u64 = 0x15;
+const SYS_EXIT: u64 = 0x18;
+
+/// The reason passed to `SYS_EXIT` by a program that ran to completion
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x2_0026;
+
+/// -1 in `a0`, it is truncated to XLEN
+const FAILURE: u64 = u64::MAX;
+
+/// Opens `name` with the `mode` of `SYS_OPEN`, 0 to 11 stand for `r`, `rb`, `r+`, `r+b`, `w`, ... `a+b` of `fopen`.
+fn open(sandbox: &mut Sandbox, name: &[u8], mode: u64) -> Option<u64> {
+    if name == b":tt" {
+        return Some(sandbox.open_console(mode < 4));
+    }
+    let update = mode & 0b10 != 0;
+    let mut options = OpenOptions::new();
+    match mode >> 2 {
+        0 => options.read(true).write(update),
+        1 => options.write(true).create(true).truncate(true).read(update),
+        2 => options.append(true).create(true).read(update),
+        _ => return None,
+    };
+    sandbox.open(name, &options).ok()
+}
+
+impl<T: AddrBus> CPU<T> {
+    /// Only code in executable memory is inspected, reading a peripheral could have side effects.
+    fn peek_code_word(&self, addr: usize) -> Option<u32> {
+        let paddr = self.peek_translate(addr, Access::Execute)?;
+        if !self.memory.region(paddr)?.permits(Access::Execute) {
+            return None;
+        }
+        self.memory.read_word(paddr).ok()
+    }
+
+    /// True if the `ebreak` at `addr` is part of the semihosting sequence.
+    pub(super) fn is_semihosting_call(&self, addr: usize) -> bool {
+        self.host.as_ref().is_some_and(|host| host.semihosting)
+            && addr >= 4
+            && [(addr - 4, SLLI_ZERO), (addr, EBREAK), (addr + 4, SRAI_ZERO)]
+                .into_iter()
+                .all(|(addr, word)| self.peek_code_word(addr) == Some(word))
+    }
+
+    /// Makes the call of the `ebreak` at `addr`, returns false if the program exited.
+    /// Exceptions while accessing the parameters are trapped like the ones of the `ebreak`.
+    pub(super) fn semihosting_call(&mut self, addr: usize) -> anyhow::Result<bool> {
+        self.call_host(addr, addr - 4, Self::semihosting_operation)
+    }
+
+    fn semihosting_operation(
+        &mut self,
+        sandbox: &mut Sandbox,
+        call: &mut Call,
+    ) -> anyhow::Result<u64> {
+        let parameter = self.register.read(11) as usize;
+        let result = match self.register.read(10) {
+            SYS_OPEN => {
+                let (name, mode, length) = (
+                    self.field(parameter, 0)?,
+                    self.field(parameter, 1)?,
+                    self.field(parameter, 2)?,
+                );
+                let name = self.read_memory(name as usize, length as usize)?;
+                open(sandbox, &name, mode).unwrap_or(FAILURE)
+            }
+            SYS_CLOSE => {
+                if sandbox.close(self.field(parameter, 0)?) {
+                    0
+                } else {
+                    FAILURE
+                }
+            }
+            SYS_WRITEC => {
+                sandbox.print(&[self.load_byte(parameter)? as u8]);
+                0
+            }
+            SYS_WRITE0 => {
+                let text = self.read_string(parameter)?;
+                sandbox.print(&text);
+                0
+            }
+            SYS_WRITE => {
+                let (handle, buffer, length) = (
+                    self.field(parameter, 0)?,
+                    self.field(parameter, 1)?,
+                    self.field(parameter, 2)?,
+                );
+                let data = self.read_memory(buffer as usize, length as usize)?;
+                // The result is the number of bytes that were not written
+                sandbox
+                    .write(handle, &data)
+                    .map_or(length, |written| length - written as u64)
+            }
+            SYS_READ => {
+                let (handle, buffer, length) = (
+                    self.field(parameter, 0)?,
+                    self.field(parameter, 1)?,
+                    self.field(parameter, 2)?,
+                );
+                match sandbox.read(handle, length as usize) {
+                    Ok(Some(data)) => {
+                        self.write_memory(call, buffer as usize, &data)?;
+                        // The result is the number of bytes that were not read, all of them at the end
+                        length - data.len() as u64
+                    }
+                    Ok(None) => {
+                        call.outcome = Outcome::Retry;
+                        0
+                    }
+                    Err(_) => FAILURE,
+                }
+            }
+            SYS_CLOCK => (sandbox.start.elapsed().as_millis() / 10) as u64,
+            SYS_GETCMDLINE => {
+                let (buffer, size) = (self.field(parameter, 0)?, self.field(parameter, 1)?);
+                let mut line = sandbox.command_line.as_bytes().to_vec();
+                if line.len() as u64 >= size {
+                    FAILURE
+                } else {
+                    let length = line.len() as u64;
+                    line.push(0);
+                    self.write_memory(call, buffer as usize, &line)?;
+                    let width = (self.register.csr.xlen.bits() / 8) as usize;
+                    self.write_xlen(call, parameter + width, &[length])?;
+                    0
+                }
+            }
+            SYS_EXIT => {
+                // On RV64, the parameter points to the reason and a subcode
+                let (reason, subcode) = match self.register.csr.xlen {
+                    Xlen::Rv32 => (parameter as u64, 0),
+                    Xlen::Rv64 => (self.field(parameter, 0)?, self.field(parameter, 1)?),
+                };
+                let code = if reason == ADP_STOPPED_APPLICATION_EXIT {
+                    subcode as i32
+                } else {
+                    1
+                };
+                call.outcome = Outcome::Exit(code);
+                0
+            }
+            _ => FAILURE,
+        };
+        Ok(result)
+    }
+
+    /// The field `index` of the parameter block at `block`, the fields are XLEN wide.
+    fn field(&mut self, block: usize, index: usize) -> anyhow::Result<u64> {
+        match self.register.csr.xlen {
+            Xlen::Rv32 => Ok(u64::from(self.load_word(block + 4 * index)?)),
+            Xlen::Rv64 => self.load_doubleword(block + 8 * index),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! The emulation of Linux system calls, like `qemu-user` or the proxy kernel do, is scoped for this file.
+//! `ecall` passes the number of the call in `a7` and its arguments in `a0` to `a5`,
+//! the result or the negated error number is returned in `a0`.
+//! The calls newlib needs for `printf` and `malloc` are served by the host, others fail with `ENOSYS`.
+//! The file descriptors 0 to 2 are the console, files are opened in the sandbox of the host.
+use std::fs::{Metadata, OpenOptions};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::host::{Call, Outcome, Sandbox};
+use super::{AddrBus, Exception, CPU};
+
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_GETTIMEOFDAY: u64 = 169;
+const SYS_BRK: u64 = 214;
+
+const O_ACCMODE: u64 = 0b11;
+const O_WRONLY: u64 = 1;
+const O_RDWR: u64 = 2;
+const O_CREAT: u64 = 0o100;
+const O_EXCL: u64 = 0o200;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+
+const EBADF: i64 = 9;
+const EACCES: i64 = 13;
+const EFAULT: i64 = 14;
+const EINVAL: i64 = 22;
+const ENOSYS: i64 = 38;
+
+/// The descriptor of the first file the program opens, the ones below are the console
+const FIRST_FILE: u64 = 3;
+
+/// A character device, the console is a terminal
+const S_IFCHR: u64 = 0o020_000;
+const CONSOLE_MODE: u64 = S_IFCHR | 0o620;
+
+/// The size of `struct stat` of the RISC-V Linux ABI, the same for RV32 and RV64
+const STAT_SIZE: usize = 128;
+
+/// The error numbers of the host are the ones of Linux.
+fn errno(err: &io::Error) -> i64 {
+    match (err.raw_os_error(), err.kind()) {
+        (Some(code), _) => -i64::from(code),
+        (None, io::ErrorKind::PermissionDenied) => -EACCES,
+        (None, io::ErrorKind::InvalidInput) => -EBADF,
+        (None, _) => -EINVAL,
+    }
+}
+
+/// The options of `open` for the Linux `flags`, the file mode is left to the host.
+fn open_options(flags: u64) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options
+        .read(flags & O_ACCMODE != O_WRONLY)
+        .write(flags & O_ACCMODE == O_WRONLY || flags & O_ACCMODE == O_RDWR)
+        .append(flags & O_APPEND != 0)
+        .truncate(flags & O_TRUNC != 0);
+    if flags & O_CREAT != 0 {
+        if flags & O_EXCL != 0 {
+            options.create_new(true);
+        } else {
+            options.create(true);
+        }
+    }
+    options
+}
+
+/// `struct stat` with the fields `st_dev` to `st_ctim`, the console has no file behind it.
+fn stat(metadata: Option<&Metadata>) -> Vec<u8> {
+    let mut stat = Vec::with_capacity(STAT_SIZE);
+    let Some(metadata) = metadata else {
+        stat.extend_from_slice(&[0; 16]);
+        stat.extend_from_slice(&(CONSOLE_MODE as u32).to_le_bytes());
+        stat.extend_from_slice(&1_u32.to_le_bytes());
+        stat.resize(STAT_SIZE, 0);
+        return stat;
+    };
+    stat.extend_from_slice(&metadata.dev().to_le_bytes());
+    stat.extend_from_slice(&metadata.ino().to_le_bytes());
+    stat.extend_from_slice(&metadata.mode().to_le_bytes());
+    stat.extend_from_slice(&(metadata.nlink() as u32).to_le_bytes());
+    stat.extend_from_slice(&metadata.uid().to_le_bytes());
+    stat.extend_from_slice(&metadata.gid().to_le_bytes());
+    stat.extend_from_slice(&metadata.rdev().to_le_bytes());
+    stat.extend_from_slice(&[0; 8]);
+    stat.extend_from_slice(&metadata.size().to_le_bytes());
+    stat.extend_from_slice(&(metadata.blksize() as u32).to_le_bytes());
+    stat.extend_from_slice(&[0; 4]);
+    stat.extend_from_slice(&metadata.blocks().to_le_bytes());
+    for (seconds, nanoseconds) in [
+        (metadata.atime(), metadata.atime_nsec()),
+        (metadata.mtime(), metadata.mtime_nsec()),
+        (metadata.ctime(), metadata.ctime_nsec()),
+    ] {
+        stat.extend_from_slice(&seconds.to_le_bytes());
+        stat.extend_from_slice(&nanoseconds.to_le_bytes());
+    }
+    stat.resize(STAT_SIZE, 0);
+    stat
+}
+
+impl<T: AddrBus> CPU<T> {
+    pub(super) fn emulates_syscalls(&self) -> bool {
+        self.host.as_ref().is_some_and(|host| host.syscalls)
+    }
+
+    /// Makes the system call of the `ecall` at `addr`, returns false if the program exited.
+    pub(super) fn syscall(&mut self, addr: usize) -> anyhow::Result<bool> {
+        self.register.pc = addr as u64 + 4;
+        self.call_host(addr, addr, Self::syscall_operation)
+    }
+
+    /// Like Linux, a buffer that can not be accessed fails the call with `EFAULT`.
+    fn syscall_operation(&mut self, sandbox: &mut Sandbox, call: &mut Call) -> anyhow::Result<u64> {
+        let result = match self.syscall_result(sandbox, call) {
+            Ok(result) => result,
+            Err(err) => {
+                err.downcast::<Exception>()?;
+                -EFAULT
+            }
+        };
+        Ok(result as u64)
+    }
+
+    fn syscall_result(&mut self, sandbox: &mut Sandbox, call: &mut Call) -> anyhow::Result<i64> {
+        let argument = |index: u32| self.register.read(10 + index as usize);
+        let (first, second, third) = (argument(0), argument(1), argument(2));
+        let result = match self.register.read(17) {
+            SYS_OPENAT => {
+                // The directory is ignored, relative paths start in the sandbox
+                let name = self.read_string(second as usize)?;
+                match sandbox.open(&name, &open_options(third)) {
+                    Ok(handle) => (handle - 1 + FIRST_FILE) as i64,
+                    Err(err) => errno(&err),
+                }
+            }
+            SYS_CLOSE => {
+                if first < FIRST_FILE || sandbox.close(first + 1 - FIRST_FILE) {
+                    0
+                } else {
+                    -EBADF
+                }
+            }
+            SYS_READ => {
+                let read = match first {
+                    0 => Ok(sandbox.read_console(third as usize)),
+                    fd if fd >= FIRST_FILE => sandbox.read(fd + 1 - FIRST_FILE, third as usize),
+                    _ => Err(io::ErrorKind::InvalidInput.into()),
+                };
+                match read {
+                    Ok(Some(data)) => {
+                        self.write_memory(call, second as usize, &data)?;
+                        data.len() as i64
+                    }
+                    Ok(None) => {
+                        call.outcome = Outcome::Retry;
+                        0
+                    }
+                    Err(err) => errno(&err),
+                }
+            }
+            SYS_WRITE => {
+                let data = self.read_memory(second as usize, third as usize)?;
+                let written = match first {
+                    1 | 2 => {
+                        sandbox.print(&data);
+                        Ok(data.len())
+                    }
+                    fd if fd >= FIRST_FILE => sandbox.write(fd + 1 - FIRST_FILE, &data),
+                    _ => Err(io::ErrorKind::InvalidInput.into()),
+                };
+                written.map_or_else(|err| errno(&err), |written| written as i64)
+            }
+            SYS_FSTAT => {
+                let metadata = if first < FIRST_FILE {
+                    Ok(None)
+                } else {
+                    sandbox.metadata(first + 1 - FIRST_FILE)
+                };
+                match metadata {
+                    Ok(metadata) => {
+                        self.write_memory(call, second as usize, &stat(metadata.as_ref()))?;
+                        0
+                    }
+                    Err(err) => errno(&err),
+                }
+            }
+            SYS_EXIT | SYS_EXIT_GROUP => {
+                call.outcome = Outcome::Exit(first as i32);
+                0
+            }
+            SYS_GETTIMEOFDAY => {
+                // `tv_sec` has 64 bits on RV32 as well, `tv_usec` is padded to them
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                let mut time = now.as_secs().to_le_bytes().to_vec();
+                time.extend_from_slice(&u64::from(now.subsec_micros()).to_le_bytes());
+                self.write_memory(call, first as usize, &time)?;
+                0
+            }
+            SYS_BRK => {
+                // The heap ends in the RAM and does not grow into the stack
+                let (_, ram_limit) = self.memory.ram_range();
+                let limit = self.register.read(2).min(ram_limit as u64);
+                if first != 0 && self.memory.is_ram(first as usize) && first <= limit {
+                    self.program_break = first;
+                }
+                self.program_break as i64
+            }
+            _ => -ENOSYS,
+        };
+        Ok(result)
+    }
+}
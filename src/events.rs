@@ -16,4 +16,6 @@ pub enum Event {
     CpuStepComplete(bool),
     CpuPanic(Error),
     InterruptUart,
+    /// A file of the program changed on disk, see `--watch`
+    ProgramChanged,
 }
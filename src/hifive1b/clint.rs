@@ -0,0 +1,84 @@
+//! The Core-Local Interruptor (CLINT) provides the software and timer interrupt.
+//! `mtime` runs with the real time clock of the Hifive1b.
+//! Every hart has its own `msip` and `mtimecmp` register, `mtime` is shared.
+use crate::cpu::{MIP_MSIP, MIP_MTIP};
+use crate::periph::write_byte_of;
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+pub struct Clint {
+    msip: Vec<bool>,
+    mtimecmp: Vec<u64>,
+    mtime: u64,
+}
+
+impl Clint {
+    pub fn new(harts: usize) -> Self {
+        Self {
+            msip: vec![false; harts],
+            mtimecmp: vec![u64::MAX; harts],
+            mtime: 0,
+        }
+    }
+
+    /// The `mip` bits the CLINT drives for the given hart.
+    pub fn pending_interrupt_of(&self, hart: usize) -> Option<InterruptReason> {
+        let mut reason = 0;
+        if self.msip[hart] {
+            reason |= MIP_MSIP;
+        }
+        if self.mtime >= self.mtimecmp[hart] {
+            reason |= MIP_MTIP;
+        }
+        if reason == 0 {
+            None
+        } else {
+            Some(reason)
+        }
+    }
+}
+
+fn read_byte_of(value: u64, byte: usize) -> u8 {
+    (value >> (byte * 8)) as u8
+}
+
+impl MmapPeripheral for Clint {
+    #[allow(clippy::match_same_arms)]
+    fn read(&self, offset: usize) -> u8 {
+        match offset {
+            0x0000..=0x3FFF if offset.is_multiple_of(4) && offset / 4 < self.msip.len() => {
+                u8::from(self.msip[offset / 4])
+            }
+            0x4000..=0xBFF7 if (offset - 0x4000) / 8 < self.mtimecmp.len() => {
+                let hart = (offset - 0x4000) / 8;
+                read_byte_of(self.mtimecmp[hart], (offset - 0x4000) % 8)
+            }
+            0xBFF8..=0xBFFF => read_byte_of(self.mtime, offset - 0xBFF8),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        match offset {
+            0x0000..=0x3FFF if offset.is_multiple_of(4) && offset / 4 < self.msip.len() => {
+                self.msip[offset / 4] = (value & 0b1) != 0;
+            }
+            0x4000..=0xBFF7 if (offset - 0x4000) / 8 < self.mtimecmp.len() => {
+                let hart = (offset - 0x4000) / 8;
+                write_byte_of(&mut self.mtimecmp[hart], (offset - 0x4000) % 8, value);
+            }
+            0xBFF8..=0xBFFF => write_byte_of(&mut self.mtime, offset - 0xBFF8, value),
+            _ => (),
+        }
+    }
+
+    /// The interrupt reason of the CLINT are the `mip` bits it drives for hart 0.
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        self.pending_interrupt_of(0)
+    }
+
+    /// `cycles` of the real time clock
+    fn tick(&mut self, cycles: u64) {
+        self.mtime = self.mtime.wrapping_add(cycles);
+    }
+}
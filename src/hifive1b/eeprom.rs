@@ -0,0 +1,173 @@
+//! An I2C EEPROM of the 24Cxx family is scoped for this file.
+//! A write transaction starts with the address in the EEPROM, the following bytes are
+//! written to its page at the stop condition. Reads continue at the current address.
+//! If an image file is attached, it holds the content and every write is written back to it.
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::i2c::I2cDevice;
+
+pub struct Eeprom {
+    address: u8,
+    memory: Vec<u8>,
+    page_size: usize,
+    image: Option<File>,
+    /// The next address that is read or written
+    pointer: usize,
+    /// The bytes of the address that were received in the current write transaction
+    address_bytes: usize,
+    /// The page buffer holds the bytes written in the current transaction by their offset
+    /// in the page, they are programmed at the stop condition
+    buffer: Vec<Option<u8>>,
+    written: usize,
+    /// Writing back to the image failed, the error is taken by the bus
+    error: Option<anyhow::Error>,
+}
+
+impl Eeprom {
+    /// The 24C256 with 32 KiB in pages of 64 bytes
+    pub fn new_24c256(address: u8) -> Self {
+        Self::new(address, 32 * 1024, 64)
+    }
+
+    /// An erased EEPROM of `size` bytes, which has to be a power of two.
+    /// Sizes of up to 256 bytes take one address byte, larger ones two.
+    /// The sizes that select the block with the device address are not supported.
+    pub fn new(address: u8, size: usize, page_size: usize) -> Self {
+        Self {
+            address,
+            memory: vec![0xFF; size],
+            page_size,
+            image: None,
+            pointer: 0,
+            address_bytes: 0,
+            buffer: vec![None; page_size],
+            written: 0,
+            error: None,
+        }
+    }
+
+    /// Loads the image file, like the flash it is created if it does not exist yet.
+    /// Only a persistent image is written back, otherwise a missing image leaves the EEPROM erased.
+    pub fn attach_image(&mut self, path: &Path, persistent: bool) -> anyhow::Result<()> {
+        if !persistent && !path.exists() {
+            return Ok(());
+        }
+        let mut file = File::options()
+            .read(true)
+            .write(persistent)
+            .create(persistent)
+            .truncate(false)
+            .open(path)
+            .context(format!(
+                "Could not open the EEPROM image {}",
+                path.display()
+            ))?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).context(format!(
+            "Could not read the EEPROM image {}",
+            path.display()
+        ))?;
+        if content.len() > self.memory.len() {
+            anyhow::bail!(
+                "The EEPROM image {} is larger than the EEPROM of 0x{:X} bytes",
+                path.display(),
+                self.memory.len()
+            );
+        }
+        self.memory[..content.len()].copy_from_slice(&content);
+        if persistent {
+            // The image always holds the whole EEPROM, so that every page can be written back
+            file.seek(SeekFrom::Start(0))
+                .and_then(|_| file.write_all(&self.memory))
+                .context(format!(
+                    "Could not write the EEPROM image {}",
+                    path.display()
+                ))?;
+            self.image = Some(file);
+        }
+        Ok(())
+    }
+
+    fn address_length(&self) -> usize {
+        if self.memory.len() <= 256 {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn page(&self) -> usize {
+        self.pointer & !(self.page_size - 1)
+    }
+
+    /// Programs the page buffer, the address continues after the last written byte.
+    fn program(&mut self) {
+        let page = self.page();
+        for (offset, byte) in self.buffer.iter_mut().enumerate() {
+            if let Some(byte) = byte.take() {
+                self.memory[page + offset] = byte;
+            }
+        }
+        self.pointer = page + (self.pointer + self.written) % self.page_size;
+        self.written = 0;
+        if let Some(image) = &mut self.image {
+            let written = image
+                .seek(SeekFrom::Start(page as u64))
+                .and_then(|_| image.write_all(&self.memory[page..page + self.page_size]));
+            if let Err(err) = written {
+                self.error = Some(anyhow::anyhow!(
+                    "EEPROM: could not write back to the image: {err}"
+                ));
+            }
+        }
+    }
+}
+
+impl I2cDevice for Eeprom {
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn start(&mut self, read: bool) -> bool {
+        if !read {
+            self.address_bytes = 0;
+            self.written = 0;
+            self.buffer.fill(None);
+        }
+        true
+    }
+
+    /// Writing wraps around within the page of the start address,
+    /// later bytes replace the earlier ones.
+    fn write(&mut self, data: u8) -> bool {
+        if self.address_bytes < self.address_length() {
+            self.pointer = (self.pointer << 8 | usize::from(data)) % self.memory.len();
+            self.address_bytes += 1;
+        } else {
+            let offset = (self.pointer + self.written) % self.page_size;
+            self.buffer[offset] = Some(data);
+            self.written += 1;
+        }
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let data = self.memory[self.pointer];
+        self.pointer = (self.pointer + 1) % self.memory.len();
+        data
+    }
+
+    fn stop(&mut self) {
+        if self.written > 0 {
+            self.program();
+        }
+    }
+
+    fn take_error(&mut self) -> anyhow::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
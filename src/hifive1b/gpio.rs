@@ -0,0 +1,366 @@
+//! The general purpose I/O (GPIO) controller of the Hifive1b is scoped for this file.
+//! Every one of the 32 pins is either driven by the controller, by its I/O function,
+//! from outside or left floating. A floating pin reads as high if its pull-up is enabled.
+//! The pins can be driven from outside through `GpioPins`, e.g. to press a button.
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+const PINS: u32 = 32;
+
+/// The RGB LED of the Hifive1b, it lights up while its pin is driven low
+const LED_RED: u32 = 22;
+const LED_GREEN: u32 = 19;
+const LED_BLUE: u32 = 21;
+
+const INPUT_VAL: usize = 0x00;
+const INPUT_EN: usize = 0x04;
+const OUTPUT_EN: usize = 0x08;
+const OUTPUT_VAL: usize = 0x0C;
+const PUE: usize = 0x10;
+const DS: usize = 0x14;
+const RISE_IE: usize = 0x18;
+const RISE_IP: usize = 0x1C;
+const FALL_IE: usize = 0x20;
+const FALL_IP: usize = 0x24;
+const HIGH_IE: usize = 0x28;
+const HIGH_IP: usize = 0x2C;
+const LOW_IE: usize = 0x30;
+const LOW_IP: usize = 0x34;
+const IOF_EN: usize = 0x38;
+const IOF_SEL: usize = 0x3C;
+const OUT_XOR: usize = 0x40;
+const PASSTHRU_HIGH_IE: usize = 0x44;
+const PASSTHRU_LOW_IE: usize = 0x48;
+
+#[derive(Default)]
+struct PinState {
+    /// Published by the controller whenever the pins change
+    levels: AtomicU32,
+    outputs: AtomicU32,
+    iof: AtomicU32,
+    /// Set from outside: the driven pins in the upper, their levels in the lower half
+    driven: AtomicU64,
+}
+
+/// The pins as seen from outside of the chip, shared with the controller.
+#[derive(Clone, Default)]
+pub struct GpioPins(Arc<PinState>);
+
+impl GpioPins {
+    /// Drives the pin from outside, the controller still wins on its output pins.
+    pub fn drive(&self, pin: u32, high: bool) {
+        let _ = self
+            .0
+            .driven
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |driven| {
+                let levels = if high {
+                    driven | 1 << pin
+                } else {
+                    driven & !(1 << pin)
+                };
+                Some(levels | 1 << (pin + PINS))
+            });
+    }
+
+    /// Stops driving the pin from outside, it floats again.
+    pub fn release(&self, pin: u32) {
+        self.0
+            .driven
+            .fetch_and(!(1 << (pin + PINS) | 1 << pin), Ordering::Relaxed);
+    }
+
+    /// The pins driven from outside and their levels
+    pub fn driven(&self) -> (u32, u32) {
+        let driven = self.0.driven.load(Ordering::Relaxed);
+        ((driven >> PINS) as u32, driven as u32)
+    }
+
+    /// The pins driven from outside already have their level, before the controller notices them.
+    pub fn levels(&self) -> u32 {
+        let (driven, levels) = self.driven();
+        let driven = driven & !self.outputs();
+        (self.0.levels.load(Ordering::Relaxed) & !driven) | (levels & driven)
+    }
+
+    /// The pins driven by the controller
+    pub fn outputs(&self) -> u32 {
+        self.0.outputs.load(Ordering::Relaxed)
+    }
+
+    /// The pins handed to their I/O function, e.g. the UART
+    pub fn iof(&self) -> u32 {
+        self.0.iof.load(Ordering::Relaxed)
+    }
+
+    /// The red, green and blue part of the LED
+    pub fn led(&self) -> (bool, bool, bool) {
+        let lit = self.outputs() & !self.levels();
+        (
+            lit & (1 << LED_RED) != 0,
+            lit & (1 << LED_GREEN) != 0,
+            lit & (1 << LED_BLUE) != 0,
+        )
+    }
+
+    /// Executes one line of the socket protocol and returns the answer.
+    fn command(&self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let pin = |word: Option<&&str>| {
+            word.and_then(|word| word.parse::<u32>().ok())
+                .filter(|pin| *pin < PINS)
+        };
+        match (words.first().copied(), pin(words.get(1)), words.get(2)) {
+            (Some("set"), Some(pin), Some(&"0" | &"1")) => {
+                self.drive(pin, words[2] == "1");
+                String::from("ok")
+            }
+            (Some("release"), Some(pin), None) => {
+                self.release(pin);
+                String::from("ok")
+            }
+            (Some("get"), Some(pin), None) => format!("{}", self.levels() >> pin & 1),
+            (Some("get"), None, None) if words.len() == 1 => format!("0x{:08X}", self.levels()),
+            (Some("led"), None, None) if words.len() == 1 => {
+                let (red, green, blue) = self.led();
+                format!("{} {} {}", u8::from(red), u8::from(green), u8::from(blue))
+            }
+            _ => format!(
+                "error: unknown command `{line}`, use `set <pin> <0|1>`, `release <pin>`, `get [pin]` or `led`"
+            ),
+        }
+    }
+
+    fn serve(&self, socket: UnixStream) {
+        let Ok(mut writer) = socket.try_clone() else {
+            return;
+        };
+        for line in BufReader::new(socket).lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if writeln!(writer, "{}", self.command(&line)).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Controls the pins through a unix socket, with one command per line.
+    /// Every command is answered with one line.
+    pub fn map_to_unixsocket(&self, socket_path: PathBuf) {
+        let pins = self.clone();
+        let _handle = thread::Builder::new()
+            .name("GPIO Unixsocket".to_owned())
+            .spawn(move || {
+                let listener = UnixListener::bind(socket_path).unwrap();
+                for socket in listener.incoming().flatten() {
+                    pins.serve(socket);
+                }
+            })
+            .unwrap();
+    }
+}
+
+pub struct Gpio {
+    pins: GpioPins,
+    /// The last state driven from outside, to notice changes
+    driven: u64,
+    input_val: u32,
+    input_en: u32,
+    output_en: u32,
+    output_val: u32,
+    pue: u32,
+    ds: u32,
+    rise_ie: u32,
+    rise_ip: u32,
+    fall_ie: u32,
+    fall_ip: u32,
+    high_ie: u32,
+    high_ip: u32,
+    low_ie: u32,
+    low_ip: u32,
+    iof_en: u32,
+    iof_sel: u32,
+    out_xor: u32,
+    passthru_high_ie: u32,
+    passthru_low_ie: u32,
+}
+
+impl Gpio {
+    pub fn new() -> (GpioPins, Self) {
+        let pins = GpioPins::default();
+        let gpio = Self {
+            pins: pins.clone(),
+            driven: 0,
+            input_val: 0,
+            input_en: 0,
+            output_en: 0,
+            output_val: 0,
+            pue: 0,
+            ds: 0,
+            rise_ie: 0,
+            rise_ip: 0,
+            fall_ie: 0,
+            fall_ip: 0,
+            high_ie: 0,
+            high_ip: 0,
+            low_ie: 0,
+            low_ip: 0,
+            iof_en: 0,
+            iof_sel: 0,
+            out_xor: 0,
+            passthru_high_ie: 0,
+            passthru_low_ie: 0,
+        };
+        (pins, gpio)
+    }
+
+    /// Returns the registers to their reset values, the pins driven from outside are kept.
+    pub fn reset(&mut self) {
+        let (_pins, gpio) = Self::new();
+        *self = Self {
+            pins: self.pins.clone(),
+            driven: self.driven,
+            ..gpio
+        };
+        self.update();
+    }
+
+    /// Shares the pins of another controller, so that both see the same pins driven from outside.
+    pub fn share_pins(&mut self, pins: GpioPins) {
+        self.pins = pins;
+    }
+
+    /// The interrupt line of every pin, they are wired to the PLIC one by one
+    pub fn interrupt_lines(&self) -> u32 {
+        (self.rise_ip & self.rise_ie)
+            | (self.fall_ip & self.fall_ie)
+            | (self.high_ip & self.high_ie)
+            | (self.low_ip & self.low_ie)
+    }
+
+    /// The pins the controller drives, instead of their I/O function
+    fn outputs(&self) -> u32 {
+        self.output_en & !self.iof_en
+    }
+
+    fn levels(&self) -> u32 {
+        let outputs = self.outputs();
+        let driven = (self.driven >> PINS) as u32 & !outputs;
+        let floating = !outputs & !driven;
+        ((self.output_val ^ self.out_xor) & outputs)
+            | (self.driven as u32 & driven)
+            | (self.pue & floating)
+    }
+
+    /// Samples the pins and latches the edges and levels of the enabled inputs.
+    fn update(&mut self) {
+        let levels = self.levels();
+        let input = levels & self.input_en;
+        self.rise_ip |= input & !self.input_val;
+        self.fall_ip |= !input & self.input_val & self.input_en;
+        self.high_ip |= input;
+        self.low_ip |= !input & self.input_en;
+        self.input_val = input;
+
+        let state = &self.pins.0;
+        state.levels.store(levels, Ordering::Relaxed);
+        state.outputs.store(self.outputs(), Ordering::Relaxed);
+        state.iof.store(self.iof_en, Ordering::Relaxed);
+    }
+
+    fn register(&mut self, offset: usize) -> Option<&mut u32> {
+        match offset {
+            INPUT_EN => Some(&mut self.input_en),
+            OUTPUT_EN => Some(&mut self.output_en),
+            OUTPUT_VAL => Some(&mut self.output_val),
+            PUE => Some(&mut self.pue),
+            DS => Some(&mut self.ds),
+            RISE_IE => Some(&mut self.rise_ie),
+            FALL_IE => Some(&mut self.fall_ie),
+            HIGH_IE => Some(&mut self.high_ie),
+            LOW_IE => Some(&mut self.low_ie),
+            IOF_EN => Some(&mut self.iof_en),
+            IOF_SEL => Some(&mut self.iof_sel),
+            OUT_XOR => Some(&mut self.out_xor),
+            PASSTHRU_HIGH_IE => Some(&mut self.passthru_high_ie),
+            PASSTHRU_LOW_IE => Some(&mut self.passthru_low_ie),
+            _ => None,
+        }
+    }
+}
+
+impl MmapPeripheral for Gpio {
+    fn read(&self, offset: usize) -> u8 {
+        let byte = offset % 4;
+        let value = match offset - byte {
+            INPUT_VAL => self.input_val,
+            INPUT_EN => self.input_en,
+            OUTPUT_EN => self.output_en,
+            OUTPUT_VAL => self.output_val,
+            PUE => self.pue,
+            DS => self.ds,
+            RISE_IE => self.rise_ie,
+            RISE_IP => self.rise_ip,
+            FALL_IE => self.fall_ie,
+            FALL_IP => self.fall_ip,
+            HIGH_IE => self.high_ie,
+            HIGH_IP => self.high_ip,
+            LOW_IE => self.low_ie,
+            LOW_IP => self.low_ip,
+            IOF_EN => self.iof_en,
+            IOF_SEL => self.iof_sel,
+            OUT_XOR => self.out_xor,
+            PASSTHRU_HIGH_IE => self.passthru_high_ie,
+            PASSTHRU_LOW_IE => self.passthru_low_ie,
+            _ => 0,
+        };
+        (value >> (byte * 8)) as u8
+    }
+
+    /// The pending bits are cleared by writing ones, a level that persists is latched again.
+    fn write(&mut self, offset: usize, value: u8) {
+        let byte = offset % 4;
+        let bits = u32::from(value) << (byte * 8);
+        match offset - byte {
+            RISE_IP => self.rise_ip &= !bits,
+            FALL_IP => self.fall_ip &= !bits,
+            HIGH_IP => self.high_ip &= !bits,
+            LOW_IP => self.low_ip &= !bits,
+            register => {
+                if let Some(register) = self.register(register) {
+                    *register &= !(0xFF << (byte * 8));
+                    *register |= bits;
+                }
+            }
+        }
+        self.update();
+    }
+
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        let lines = self.interrupt_lines();
+        if lines == 0 {
+            None
+        } else {
+            Some(lines)
+        }
+    }
+
+    /// Picks up the pins driven from outside
+    fn tick(&mut self, _cycles: u64) {
+        let driven = self.pins.0.driven.load(Ordering::Relaxed);
+        if driven != self.driven {
+            self.driven = driven;
+            self.update();
+        }
+    }
+}
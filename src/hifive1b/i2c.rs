@@ -0,0 +1,197 @@
+//! The I2C controller of the Hifive1b is scoped for this file, it follows the I2C master core of `OpenCores`.
+//! Every command written to `cr` completes instantly, the devices on the bus are virtual.
+//! A device is addressed by the first byte written after a start condition.
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+const PRERLO: usize = 0x00;
+const PRERHI: usize = 0x04;
+const CTR: usize = 0x08;
+/// `txr` when written, `rxr` when read
+const TXR_RXR: usize = 0x0C;
+/// `cr` when written, `sr` when read
+const CR_SR: usize = 0x10;
+
+const CTR_EN: u32 = 1 << 7;
+const CTR_IEN: u32 = 1 << 6;
+
+const CR_STA: u32 = 1 << 7;
+const CR_STO: u32 = 1 << 6;
+const CR_RD: u32 = 1 << 5;
+const CR_WR: u32 = 1 << 4;
+const CR_IACK: u32 = 1 << 0;
+
+/// Set if the device did not acknowledge the last byte
+const SR_RXACK: u32 = 1 << 7;
+const SR_BUSY: u32 = 1 << 6;
+const SR_IF: u32 = 1 << 0;
+
+/// A device on the bus, it answers to its 7 bit address.
+pub trait I2cDevice: Send {
+    fn address(&self) -> u8;
+
+    /// The device got addressed after a start condition, returns true to acknowledge.
+    fn start(&mut self, _read: bool) -> bool {
+        true
+    }
+
+    /// Receives a byte from the controller, returns true to acknowledge it.
+    fn write(&mut self, data: u8) -> bool;
+
+    /// Sends a byte to the controller.
+    fn read(&mut self) -> u8;
+
+    /// The stop condition ends the transaction.
+    fn stop(&mut self) {}
+
+    /// Returns the error the host ran into for the device, e.g. while writing back its image.
+    fn take_error(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct I2c {
+    devices: Vec<Box<dyn I2cDevice>>,
+    prescale: u32,
+    ctr: u32,
+    txr: u32,
+    rxr: u32,
+    sr: u32,
+    /// The byte written after a start condition is the address
+    addressing: bool,
+    /// The index of the device addressed after the last start condition
+    selected: Option<usize>,
+}
+
+impl I2c {
+    pub fn new() -> Self {
+        Self {
+            devices: Vec::new(),
+            prescale: 0xFFFF,
+            ctr: 0,
+            txr: 0,
+            rxr: 0,
+            sr: 0,
+            addressing: false,
+            selected: None,
+        }
+    }
+
+    /// Connects the device to the bus, it replaces a device with the same address.
+    pub fn attach(&mut self, device: Box<dyn I2cDevice>) {
+        self.devices
+            .retain(|other| other.address() != device.address());
+        self.devices.push(device);
+    }
+
+    /// Returns the registers to their reset values and releases the bus, the devices are kept.
+    pub fn reset(&mut self) {
+        self.stop();
+        let devices = std::mem::take(&mut self.devices);
+        *self = Self {
+            devices,
+            ..Self::new()
+        };
+    }
+
+    /// The first error of a device, it ends the emulation like any other error of the memory.
+    pub fn take_error(&mut self) -> anyhow::Result<()> {
+        self.devices
+            .iter_mut()
+            .try_for_each(|device| device.take_error())
+    }
+
+    fn stop(&mut self) {
+        if let Some(device) = self.selected.take() {
+            self.devices[device].stop();
+        }
+        self.sr &= !SR_BUSY;
+    }
+
+    /// Nobody acknowledges a byte written to an address without device.
+    fn write_byte(&mut self, data: u8) -> bool {
+        if std::mem::take(&mut self.addressing) {
+            let address = data >> 1;
+            self.selected = self
+                .devices
+                .iter()
+                .position(|device| device.address() == address);
+            return match self.selected {
+                Some(device) => self.devices[device].start(data & 1 != 0),
+                None => false,
+            };
+        }
+        match self.selected {
+            Some(device) => self.devices[device].write(data),
+            None => false,
+        }
+    }
+
+    /// The commands of `cr` are executed in the order start, write or read and stop.
+    fn command(&mut self, cr: u32) {
+        if cr & CR_IACK != 0 {
+            self.sr &= !SR_IF;
+        }
+        if self.ctr & CTR_EN == 0 || cr & (CR_STA | CR_STO | CR_RD | CR_WR) == 0 {
+            return;
+        }
+        if cr & CR_STA != 0 {
+            self.sr |= SR_BUSY;
+            self.addressing = true;
+        }
+        if cr & CR_WR != 0 {
+            let acknowledged = self.write_byte(self.txr as u8);
+            if acknowledged {
+                self.sr &= !SR_RXACK;
+            } else {
+                self.sr |= SR_RXACK;
+            }
+        }
+        if cr & CR_RD != 0 {
+            // Without a device, the data line stays high
+            self.rxr = u32::from(
+                self.selected
+                    .map_or(0xFF, |device| self.devices[device].read()),
+            );
+        }
+        if cr & CR_STO != 0 {
+            self.stop();
+        }
+        self.sr |= SR_IF;
+    }
+}
+
+impl MmapPeripheral for I2c {
+    /// The registers are 8 bits wide, one in every word.
+    fn read(&self, offset: usize) -> u8 {
+        let value = match offset {
+            PRERLO => self.prescale,
+            PRERHI => self.prescale >> 8,
+            CTR => self.ctr,
+            TXR_RXR => self.rxr,
+            CR_SR => self.sr,
+            _ => 0,
+        };
+        value as u8
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        let value = u32::from(value);
+        match offset {
+            PRERLO => self.prescale = (self.prescale & 0xFF00) | value,
+            PRERHI => self.prescale = (self.prescale & 0x00FF) | value << 8,
+            CTR => self.ctr = value & (CTR_EN | CTR_IEN),
+            TXR_RXR => self.txr = value,
+            CR_SR => self.command(value),
+            _ => (),
+        }
+    }
+
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        if self.ctr & CTR_IEN != 0 && self.sr & SR_IF != 0 {
+            Some(SR_IF)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! An LM75B temperature sensor on the I2C bus is scoped for this file.
+//! The first byte of a write transaction selects the register, reads start at it.
+//! The registers are read MSB first, a read past the last byte of a register repeats it.
+//! The temperature does not change on its own, it is set from outside. The OS output is not modeled.
+use super::i2c::I2cDevice;
+
+const TEMP: u8 = 0;
+const CONF: u8 = 1;
+const THYST: u8 = 2;
+const TOS: u8 = 3;
+
+/// The temperature has 11 bits, in steps of 0.125 °C
+const TEMP_MASK: u16 = 0xFFE0;
+/// The limits have 9 bits, in steps of 0.5 °C
+const LIMIT_MASK: u16 = 0xFF80;
+
+pub struct Lm75 {
+    address: u8,
+    /// The temperature registers hold the degrees Celsius as signed fixed point with 8 fractional bits
+    temp: u16,
+    conf: u8,
+    thyst: u16,
+    tos: u16,
+    pointer: u8,
+    /// The byte of the register that is accessed next, MSB first
+    byte: usize,
+    /// The first byte written after a start condition is the pointer
+    addressing: bool,
+}
+
+impl Lm75 {
+    pub fn new(address: u8, celsius: f64) -> Self {
+        let mut sensor = Self {
+            address,
+            temp: 0,
+            conf: 0,
+            thyst: 75 << 8,
+            tos: 80 << 8,
+            pointer: TEMP,
+            byte: 0,
+            addressing: false,
+        };
+        sensor.set_temperature(celsius);
+        sensor
+    }
+
+    /// The temperature is clamped to the range of the sensor, -55 °C to 125 °C.
+    pub fn set_temperature(&mut self, celsius: f64) {
+        let fixed = (celsius.clamp(-55.0, 125.0) * 256.0).round() as i16;
+        self.temp = fixed as u16 & TEMP_MASK;
+    }
+
+    fn register(&self) -> (u16, usize) {
+        match self.pointer {
+            TEMP => (self.temp, 2),
+            CONF => (u16::from(self.conf) << 8, 1),
+            THYST => (self.thyst, 2),
+            _ => (self.tos, 2),
+        }
+    }
+}
+
+impl I2cDevice for Lm75 {
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn start(&mut self, read: bool) -> bool {
+        self.addressing = !read;
+        self.byte = 0;
+        true
+    }
+
+    /// The temperature register is read-only, writes to it are ignored.
+    fn write(&mut self, data: u8) -> bool {
+        if std::mem::take(&mut self.addressing) {
+            self.pointer = data & 0b11;
+            return true;
+        }
+        let (_, length) = self.register();
+        let shift = 8 * (1 - self.byte % length);
+        let mask = 0xFF << shift;
+        let data = u16::from(data) << shift;
+        match self.pointer {
+            CONF => self.conf = (data >> 8) as u8 & 0x1F,
+            THYST => self.thyst = (self.thyst & !mask | data) & LIMIT_MASK,
+            TOS => self.tos = (self.tos & !mask | data) & LIMIT_MASK,
+            _ => (),
+        }
+        self.byte += 1;
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let (value, length) = self.register();
+        let data = (value >> (8 * (1 - self.byte % length))) as u8;
+        self.byte += 1;
+        data
+    }
+}
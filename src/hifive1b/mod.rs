@@ -1,34 +1,123 @@
+use std::path::Path;
 use std::sync::mpsc;
 
-use crate::cpu::AddrBus;
+use crate::cpu::{AddrBus, InputFrame, Region, MIP_MEIP};
 use crate::events;
 use crate::periph::MmapPeripheral;
 use crate::utils::IOChannel;
 
+use clint::Clint;
+use eeprom::Eeprom;
+use gpio::Gpio;
+pub use gpio::GpioPins;
+use i2c::I2c;
+use lm75::Lm75;
+use nor_flash::NorFlash;
+use plic::Plic;
+use prci::Prci;
+use pwm::Pwm;
+pub use pwm::PwmDuty;
+use rtc::Rtc;
+use sd_card::SdCard;
+use sparse_memory::SparseMemory;
+use spi::{NoDevice, Spi, SpiDevice};
 use uart::Uart;
+use watchdog::Watchdog;
 
+mod clint;
+mod eeprom;
+mod gpio;
+mod i2c;
+mod lm75;
+mod nor_flash;
+mod plic;
+mod prci;
+mod pwm;
+mod rtc;
+mod sd_card;
+mod sparse_memory;
+mod spi;
 mod uart;
+mod watchdog;
+
+/// The real time clock (lfclk) of the always-on domain, it drives `mtime`, the watchdog and the RTC
+const RTC_FREQUENCY: u64 = 32_768;
+
+/// The 7 bit addresses of the devices on the I2C bus
+const EEPROM_ADDRESS: u8 = 0x50;
+const TEMPERATURE_SENSOR_ADDRESS: u8 = 0x48;
+/// The temperature the sensor reports unless it is set
+const DEFAULT_TEMPERATURE: f64 = 25.0;
+
+/// A reservation covers the naturally aligned doubleword of its address.
+const RESERVATION_GRANULE: usize = 8;
+
+/// The flat RAM of a user program that makes Linux system calls, it ends below the CLINT
+const USER_RAM_BASE: usize = 0x1_0000;
+const USER_RAM_LIMIT: usize = 0x0200_0000;
 
 pub struct Hifive1b {
     pub uart0channel: Option<IOChannel>,
     pub uart1channel: Option<IOChannel>,
+    pub gpio_pins: GpioPins,
+    pub pwm_duty: PwmDuty,
     pub memory: Option<Memory>,
 }
 
 impl Hifive1b {
-    pub fn new(interrupts: mpsc::Sender<events::Event>) -> Self {
+    pub fn new(interrupts: mpsc::Sender<events::Event>, harts: usize) -> Self {
         let (uart0channel, uart0) = Uart::default(interrupts.clone());
         let (uart1channel, uart1) = Uart::default(interrupts);
-        let memory = Memory::new(uart0, uart1);
+        let (gpio_pins, gpio) = Gpio::new();
+        let pwm_duty = PwmDuty::default();
+        let pwm_controllers = [
+            Pwm::new(0, 8, pwm_duty.clone()),
+            Pwm::new(1, 16, pwm_duty.clone()),
+            Pwm::new(2, 16, pwm_duty.clone()),
+        ];
+        let memory = Memory::new(uart0, uart1, gpio, pwm_controllers, harts);
         Self {
             uart0channel: Some(uart0channel),
             uart1channel: Some(uart1channel),
+            gpio_pins,
+            pwm_duty,
             memory: Some(memory),
         }
     }
 }
 
 pub struct Memory {
+    pub clint_base: usize,
+    pub clint: Clint,
+    pub clint_limit: usize,
+    pub plic_base: usize,
+    pub plic: Plic,
+    pub plic_limit: usize,
+    pub watchdog_base: usize,
+    pub watchdog: Watchdog,
+    pub watchdog_limit: usize,
+    pub rtc_base: usize,
+    pub rtc: Rtc,
+    pub rtc_limit: usize,
+    pub prci_base: usize,
+    pub prci: Prci,
+    pub prci_limit: usize,
+    /// The emulator executes one instruction per cycle of the core clock selected by the PRCI
+    core_frequency: u64,
+    /// Core cycles that did not yet add up to a full cycle of the real time clock
+    rtc_remainder: u64,
+    pub gpio_base: usize,
+    pub gpio: Gpio,
+    pub gpio_limit: usize,
+    pub pwm0_base: usize,
+    pub pwm0: Pwm,
+    pub pwm0_limit: usize,
+    pub pwm1_base: usize,
+    pub pwm1: Pwm,
+    pub pwm1_limit: usize,
+    pub pwm2_base: usize,
+    pub pwm2: Pwm,
+    pub pwm2_limit: usize,
     pub uart0_base: usize,
     pub uart0: Uart,
     pub uart0_limit: usize,
@@ -37,16 +126,91 @@ pub struct Memory {
     pub uart1_limit: usize,
     pub ram_base: usize,
     pub ram_limit: usize,
-    pub ram: Vec<u8>,
+    pub ram: SparseMemory,
+    /// The flash is read through the memory-mapped window of SPI0 at `rom_base`
     pub rom_base: usize,
     pub rom_limit: usize,
-    pub rom: Vec<u8>,
-    pub reservation: Option<(usize, u32)>,
+    pub spi0_base: usize,
+    pub spi0: Spi<NorFlash>,
+    pub spi0_limit: usize,
+    pub spi1_base: usize,
+    pub spi1: Spi<Box<dyn SpiDevice>>,
+    pub spi1_limit: usize,
+    pub spi2_base: usize,
+    pub spi2: Spi<Box<dyn SpiDevice>>,
+    pub spi2_limit: usize,
+    pub i2c0_base: usize,
+    pub i2c0: I2c,
+    pub i2c0_limit: usize,
+    pub reservations: Vec<Option<(usize, u64)>>,
+    /// The memory map, RAM and flash are first as they are accessed the most
+    pub regions: Vec<Region>,
 }
 
 impl Memory {
-    pub fn new(uart0: Uart, uart1: Uart) -> Self {
+    pub fn new(
+        uart0: Uart,
+        uart1: Uart,
+        gpio: Gpio,
+        pwm_controllers: [Pwm; 3],
+        harts: usize,
+    ) -> Self {
+        let [pwm0, pwm1, pwm2] = pwm_controllers;
+        let regions = vec![
+            Region::new("RAM", 0x8000_0000, 0x8000_8000, "rwx"),
+            Region::new("Flash", 0x2000_0000, 0x4000_0000, "r-x"),
+            Region::new("CLINT", 0x0200_0000, 0x0200_C000, "rw-"),
+            Region::new("PLIC", 0x0C00_0000, 0x1000_0000, "rw-"),
+            Region::new("WDT", 0x1000_0000, 0x1000_0040, "rw-"),
+            Region::new("RTC", 0x1000_0040, 0x1000_0081, "rw-"),
+            Region::new("PRCI", 0x1000_8000, 0x1000_8010, "rw-"),
+            Region::new("GPIO", 0x1001_2000, 0x1001_3000, "rw-"),
+            Region::new("UART0", 0x1001_3000, 0x1001_301C, "rw-"),
+            Region::new("SPI0", 0x1001_4000, 0x1001_5000, "rw-"),
+            Region::new("PWM0", 0x1001_5000, 0x1001_6000, "rw-"),
+            Region::new("I2C0", 0x1001_6000, 0x1001_7000, "rw-"),
+            Region::new("UART1", 0x1002_3000, 0x1002_301C, "rw-"),
+            Region::new("SPI1", 0x1002_4000, 0x1002_5000, "rw-"),
+            Region::new("PWM1", 0x1002_5000, 0x1002_6000, "rw-"),
+            Region::new("SPI2", 0x1003_4000, 0x1003_5000, "rw-"),
+            Region::new("PWM2", 0x1003_5000, 0x1003_6000, "rw-"),
+        ];
+        let mut i2c0 = I2c::new();
+        i2c0.attach(Box::new(Eeprom::new_24c256(EEPROM_ADDRESS)));
+        i2c0.attach(Box::new(Lm75::new(
+            TEMPERATURE_SENSOR_ADDRESS,
+            DEFAULT_TEMPERATURE,
+        )));
         Self {
+            clint_base: 0x0200_0000,
+            clint: Clint::new(harts),
+            clint_limit: 0x0200_C000,
+            plic_base: 0x0C00_0000,
+            plic: Plic::new(harts),
+            plic_limit: 0x1000_0000,
+            watchdog_base: 0x1000_0000,
+            watchdog: Watchdog::new(),
+            watchdog_limit: 0x1000_0040,
+            rtc_base: 0x1000_0040,
+            rtc: Rtc::new(),
+            rtc_limit: 0x1000_0081,
+            prci_base: 0x1000_8000,
+            prci: Prci::new(),
+            prci_limit: 0x1000_8010,
+            core_frequency: Prci::new().core_frequency(),
+            rtc_remainder: 0,
+            gpio_base: 0x1001_2000,
+            gpio,
+            gpio_limit: 0x1001_3000,
+            pwm0_base: 0x1001_5000,
+            pwm0,
+            pwm0_limit: 0x1001_6000,
+            pwm1_base: 0x1002_5000,
+            pwm1,
+            pwm1_limit: 0x1002_6000,
+            pwm2_base: 0x1003_5000,
+            pwm2,
+            pwm2_limit: 0x1003_6000,
             uart0_base: 0x1001_3000,
             uart0,
             uart0_limit: 0x1001_301C,
@@ -55,12 +219,93 @@ impl Memory {
             uart1_limit: 0x1002_301C,
             rom_base: 0x2000_0000,
             rom_limit: 0x4000_0000,
-            rom: vec![0; 0x2000_0000],
+            spi0_base: 0x1001_4000,
+            spi0: Spi::new(NorFlash::new(0x2000_0000)),
+            spi0_limit: 0x1001_5000,
+            spi1_base: 0x1002_4000,
+            spi1: Spi::without_flash(Box::new(NoDevice), 4),
+            spi1_limit: 0x1002_5000,
+            spi2_base: 0x1003_4000,
+            spi2: Spi::without_flash(Box::new(NoDevice), 1),
+            spi2_limit: 0x1003_5000,
+            i2c0_base: 0x1001_6000,
+            i2c0,
+            i2c0_limit: 0x1001_7000,
             ram_base: 0x8000_0000,
             ram_limit: 0x8000_8000,
-            ram: vec![0; 0x8000],
-            reservation: None,
+            ram: SparseMemory::new(0x8000, 0),
+            reservations: vec![None; harts],
+            regions,
+        }
+    }
+
+    fn is_clint(&self, addr: usize) -> bool {
+        self.clint_base <= addr && addr < self.clint_limit
+    }
+
+    fn is_plic(&self, addr: usize) -> bool {
+        self.plic_base <= addr && addr < self.plic_limit
+    }
+
+    fn is_watchdog(&self, addr: usize) -> bool {
+        self.watchdog_base <= addr && addr < self.watchdog_limit
+    }
+
+    fn is_rtc(&self, addr: usize) -> bool {
+        self.rtc_base <= addr && addr < self.rtc_limit
+    }
+
+    fn is_prci(&self, addr: usize) -> bool {
+        self.prci_base <= addr && addr < self.prci_limit
+    }
+
+    fn is_gpio(&self, addr: usize) -> bool {
+        self.gpio_base <= addr && addr < self.gpio_limit
+    }
+
+    fn is_pwm0(&self, addr: usize) -> bool {
+        self.pwm0_base <= addr && addr < self.pwm0_limit
+    }
+
+    fn is_pwm1(&self, addr: usize) -> bool {
+        self.pwm1_base <= addr && addr < self.pwm1_limit
+    }
+
+    fn is_pwm2(&self, addr: usize) -> bool {
+        self.pwm2_base <= addr && addr < self.pwm2_limit
+    }
+
+    /// The interrupt lines of the peripherals, bit `n` is the line of PLIC source `n`
+    fn interrupt_lines(&self) -> u64 {
+        let mut lines = u64::from(self.gpio.interrupt_lines()) << plic::GPIO0
+            | u64::from(self.pwm0.interrupt_lines()) << plic::PWM0
+            | u64::from(self.pwm1.interrupt_lines()) << plic::PWM1
+            | u64::from(self.pwm2.interrupt_lines()) << plic::PWM2;
+        if self.watchdog.pending_interrupt().is_some() {
+            lines |= 1 << plic::WDOG;
+        }
+        if self.rtc.pending_interrupt().is_some() {
+            lines |= 1 << plic::RTC;
+        }
+        if self.uart0.pending_interrupt().is_some() {
+            lines |= 1 << plic::UART0;
         }
+        if self.uart1.pending_interrupt().is_some() {
+            lines |= 1 << plic::UART1;
+        }
+        if self.spi0.pending_interrupt().is_some() {
+            lines |= 1 << plic::SPI0;
+        }
+        if self.spi1.pending_interrupt().is_some() {
+            lines |= 1 << plic::SPI1;
+        }
+        if self.spi2.pending_interrupt().is_some() {
+            lines |= 1 << plic::SPI2;
+        }
+        if self.i2c0.pending_interrupt().is_some() {
+            lines |= 1 << plic::I2C0;
+        }
+        lines
     }
 
     fn is_uart0(&self, addr: usize) -> bool {
@@ -70,42 +315,223 @@ impl Memory {
     fn is_uart1(&self, addr: usize) -> bool {
         self.uart1_base <= addr && addr < self.uart1_limit
     }
+
+    fn is_spi0(&self, addr: usize) -> bool {
+        self.spi0_base <= addr && addr < self.spi0_limit
+    }
+
+    fn is_spi1(&self, addr: usize) -> bool {
+        self.spi1_base <= addr && addr < self.spi1_limit
+    }
+
+    fn is_spi2(&self, addr: usize) -> bool {
+        self.spi2_base <= addr && addr < self.spi2_limit
+    }
+
+    fn is_i2c0(&self, addr: usize) -> bool {
+        self.i2c0_base <= addr && addr < self.i2c0_limit
+    }
+
+    /// If set, the frames of the UARTs take as long as with their configured baud rate.
+    pub fn set_uart_pacing(&mut self, pacing: bool) {
+        self.uart0.pacing = pacing;
+        self.uart1.pacing = pacing;
+    }
+
+    /// Loads the flash from the image file.
+    /// If `persistent`, program and erase operations are written back to it.
+    pub fn attach_flash_image(&mut self, path: &Path, persistent: bool) -> anyhow::Result<()> {
+        self.spi0.device.attach_image(path, persistent)
+    }
+
+    /// Inserts an SD card backed by the disk image into the slot on chip select 0 of SPI1.
+    /// If `persistent`, the blocks written by the firmware are written to the image.
+    pub fn attach_sd_card(&mut self, path: &Path, persistent: bool) -> anyhow::Result<()> {
+        self.spi1.device = Box::new(SdCard::open(path, persistent)?);
+        Ok(())
+    }
+
+    /// Replaces the EEPROM on the I2C bus with one loaded from the image file.
+    /// If `persistent`, writes of the firmware are written back to it.
+    pub fn attach_eeprom_image(&mut self, path: &Path, persistent: bool) -> anyhow::Result<()> {
+        let mut eeprom = Eeprom::new_24c256(EEPROM_ADDRESS);
+        eeprom.attach_image(path, persistent)?;
+        self.i2c0.attach(Box::new(eeprom));
+        Ok(())
+    }
+
+    /// Replaces the RAM with a flat one for a user program, like the one of `qemu-user` or `pk`.
+    /// Programs linked by the default linker script of newlib start at 0x10000, the heap and
+    /// the stack take the rest, up to the CLINT.
+    pub fn map_user_ram(&mut self) {
+        self.ram_base = USER_RAM_BASE;
+        self.ram_limit = USER_RAM_LIMIT;
+        self.ram = SparseMemory::new(USER_RAM_LIMIT - USER_RAM_BASE, 0);
+        self.regions[0] = Region::new("RAM", USER_RAM_BASE, USER_RAM_LIMIT, "rwx");
+    }
+
+    /// The temperature in degrees Celsius that the sensor on the I2C bus reports.
+    pub fn set_temperature(&mut self, celsius: f64) {
+        self.i2c0
+            .attach(Box::new(Lm75::new(TEMPERATURE_SENSOR_ADDRESS, celsius)));
+    }
+
+    /// The RAM or flash bytes `[addr, addr + size)`, used as fast path for wide accesses.
+    /// Accesses that cross a page take the slow path.
+    fn memory_slice(&self, addr: usize, size: usize) -> Option<&[u8]> {
+        if self.is_ram(addr) {
+            self.ram.slice(addr - self.ram_base, size)
+        } else if self.is_rom(addr) && self.spi0.xip_enabled() {
+            self.spi0.device.array.slice(addr - self.rom_base, size)
+        } else {
+            None
+        }
+    }
+
+    /// The flash is not writable, only RAM has a fast path for wide stores.
+    fn ram_slice_mut(&mut self, addr: usize, size: usize) -> Option<&mut [u8]> {
+        if self.is_ram(addr) {
+            self.ram.slice_mut(addr - self.ram_base, size)
+        } else {
+            None
+        }
+    }
 }
 
 impl AddrBus for Memory {
-    fn set_reservation(&mut self, addr: usize, value: u32) {
-        self.reservation = Some((addr, value));
+    fn set_reservation(&mut self, hart: usize, addr: usize, value: u64) {
+        self.reservations[hart] = Some((addr, value));
+    }
+
+    fn get_reservation(&mut self, hart: usize) -> Option<(usize, u64)> {
+        self.reservations[hart]
+    }
+
+    fn del_reservation(&mut self, hart: usize) {
+        self.reservations[hart] = None;
     }
 
-    fn get_reservation(&mut self) -> Option<(usize, u32)> {
-        self.reservation
+    fn invalidate_reservations(&mut self, hart: usize, addr: usize, size: usize) {
+        for (other, reservation) in self.reservations.iter_mut().enumerate() {
+            if let Some((reserved, _value)) = *reservation {
+                let granule = reserved & !(RESERVATION_GRANULE - 1);
+                if other != hart && addr < granule + RESERVATION_GRANULE && addr + size > granule {
+                    *reservation = None;
+                }
+            }
+        }
     }
 
-    fn del_reservation(&mut self) {
-        self.reservation = None;
+    fn pending_interrupts(&self, hart: usize) -> u32 {
+        self.clint.pending_interrupt_of(hart).unwrap_or(0)
+            | self.plic.pending_interrupt_of(hart).unwrap_or(0) & MIP_MEIP
     }
 
-    fn pending_interrupt(&self) -> Option<u32> {
-        let i0 = self.uart0.pending_interrupt();
-        if i0.is_some() {
-            return i0;
+    fn tick(&mut self, cycles: u64) {
+        // Most cycles of the core do not complete a cycle of the real time clock
+        let total = self.rtc_remainder + cycles.saturating_mul(RTC_FREQUENCY);
+        if total < self.core_frequency {
+            self.rtc_remainder = total;
+        } else {
+            let rtc_cycles = total / self.core_frequency;
+            self.rtc_remainder = total % self.core_frequency;
+            self.clint.tick(rtc_cycles);
+            self.watchdog.tick(rtc_cycles);
+            self.rtc.tick(rtc_cycles);
         }
-        let i1 = self.uart1.pending_interrupt();
-        if i1.is_some() {
-            return i1;
+        self.uart0.tick(cycles);
+        self.uart1.tick(cycles);
+        self.gpio.tick(cycles);
+        self.pwm0.tick(cycles);
+        self.pwm1.tick(cycles);
+        self.pwm2.tick(cycles);
+        let lines = self.interrupt_lines();
+        self.plic.update(lines);
+    }
+
+    fn take_reset_request(&mut self) -> bool {
+        self.watchdog.take_reset_request()
+    }
+
+    /// The content of RAM and flash is kept, like the pins driven from outside
+    /// and the state of the flash chip, which is not reset with the chip.
+    fn reset(&mut self) {
+        self.clint = Clint::new(self.reservations.len());
+        self.plic = Plic::new(self.reservations.len());
+        self.watchdog = Watchdog::new();
+        self.rtc = Rtc::new();
+        self.prci = Prci::new();
+        self.core_frequency = self.prci.core_frequency();
+        self.gpio.reset();
+        self.pwm0.reset();
+        self.pwm1.reset();
+        self.pwm2.reset();
+        self.uart0.reset();
+        self.uart1.reset();
+        self.spi0.reset();
+        self.spi1.reset();
+        self.spi2.reset();
+        self.i2c0.reset();
+        self.reservations.fill(None);
+    }
+
+    fn record_input(&mut self) {
+        self.uart0.record_input();
+        self.uart1.record_input();
+    }
+
+    /// UART0 is peripheral 0, UART1 peripheral 1.
+    fn take_input(&mut self) -> Vec<InputFrame> {
+        [self.uart0.take_input(), self.uart1.take_input()]
+            .into_iter()
+            .enumerate()
+            .flat_map(|(peripheral, frames)| {
+                frames.into_iter().map(move |(attempt, data)| InputFrame {
+                    peripheral,
+                    attempt,
+                    data,
+                })
+            })
+            .collect()
+    }
+
+    fn replay_input(&mut self, input: Vec<InputFrame>) {
+        for (peripheral, uart) in [&mut self.uart0, &mut self.uart1].into_iter().enumerate() {
+            uart.replay_input(
+                input
+                    .iter()
+                    .filter(|frame| frame.peripheral == peripheral)
+                    .map(|frame| (frame.attempt, frame.data))
+                    .collect(),
+            );
         }
-        None
+    }
+
+    fn clock_frequency(&self) -> u64 {
+        self.core_frequency
+    }
+
+    fn region(&self, addr: usize) -> Option<Region> {
+        self.regions
+            .iter()
+            .find(|region| region.base <= addr && addr < region.limit)
+            .copied()
     }
 
     fn is_ram(&self, addr: usize) -> bool {
         self.ram_base <= addr && addr < self.ram_limit
     }
 
+    fn ram_range(&self) -> (usize, usize) {
+        (self.ram_base, self.ram_limit)
+    }
+
     fn load_ram_at(&mut self, offset: usize, data: &[u8]) {
-        let ram = &mut self.ram[offset..];
-        for (x, i) in data.iter().enumerate() {
-            ram[x] = *i;
-        }
+        self.ram.load(offset, data);
+    }
+
+    fn clear_ram(&mut self) {
+        self.ram.erase(0, self.ram.size());
     }
 
     fn is_rom(&self, addr: usize) -> bool {
@@ -113,33 +539,51 @@ impl AddrBus for Memory {
     }
 
     fn load_rom_at(&mut self, offset: usize, data: &[u8]) {
-        let rom = &mut self.rom[offset..];
-        for (x, i) in data.iter().enumerate() {
-            rom[x] = *i;
-        }
+        self.spi0.device.array.load(offset, data);
     }
 
-    fn load_at(&mut self, addr: usize, data: &[u8]) {
-        if self.is_ram(addr) {
-            let offset = addr - self.ram_base;
-            self.load_ram_at(offset, data);
-            return;
-        } else if self.is_rom(addr) {
-            let offset = addr - self.rom_base;
-            self.load_rom_at(offset, data);
-            return;
+    fn take_modified_range(&mut self) -> Option<(usize, usize)> {
+        self.spi0
+            .device
+            .take_modified()
+            .map(|(offset, size)| (self.rom_base + offset, size))
+    }
+
+    fn load_at(&mut self, addr: usize, data: &[u8]) -> anyhow::Result<()> {
+        let end = addr + data.len();
+        if self.is_ram(addr) && end <= self.ram_limit {
+            self.load_ram_at(addr - self.ram_base, data);
+            Ok(())
+        } else if self.is_rom(addr) && end <= self.rom_limit {
+            self.load_rom_at(addr - self.rom_base, data);
+            Ok(())
+        } else {
+            let region = self
+                .region(addr)
+                .map_or("unmapped memory", |region| region.name);
+            Err(anyhow::anyhow!(
+                "Can't load 0x{:X} bytes at 0x{addr:08X} in {region}, only RAM and flash can be loaded",
+                data.len()
+            ))
         }
-        panic!("Can't load at this address");
     }
 
     fn read_byte(&self, addr: usize) -> anyhow::Result<u32> {
         if self.is_ram(addr) {
-            let index = addr - self.ram_base;
-            return Ok(u32::from(self.ram[index]));
+            return Ok(u32::from(self.ram.read_byte(addr - self.ram_base)));
         }
         if self.is_rom(addr) {
-            let index = addr - self.rom_base;
-            return Ok(u32::from(self.rom[index]));
+            if !self.spi0.xip_enabled() {
+                return Err(anyhow::anyhow!(
+                    "Flash: attempted read at address 0x{addr:08X} while it is not mapped, fctrl.en of SPI0 is cleared"
+                ));
+            }
+            return Ok(u32::from(
+                self.spi0.device.array.read_byte(addr - self.rom_base),
+            ));
+        }
+        if self.is_clint(addr) {
+            return Ok(u32::from(self.clint.read(addr - self.clint_base)));
         }
         if self.is_uart0(addr) {
             return Ok(u32::from(self.uart0.read(addr - self.uart0_base)));
@@ -147,45 +591,88 @@ impl AddrBus for Memory {
         if self.is_uart1(addr) {
             return Ok(u32::from(self.uart1.read(addr - self.uart1_base)));
         }
+        if self.is_spi0(addr) {
+            return Ok(u32::from(self.spi0.read(addr - self.spi0_base)));
+        }
+        if self.is_plic(addr) {
+            return Ok(u32::from(self.plic.read(addr - self.plic_base)));
+        }
+        if self.is_pwm0(addr) {
+            return Ok(u32::from(self.pwm0.read(addr - self.pwm0_base)));
+        }
+        if self.is_pwm1(addr) {
+            return Ok(u32::from(self.pwm1.read(addr - self.pwm1_base)));
+        }
+        if self.is_pwm2(addr) {
+            return Ok(u32::from(self.pwm2.read(addr - self.pwm2_base)));
+        }
+        if self.is_spi1(addr) {
+            return Ok(u32::from(self.spi1.read(addr - self.spi1_base)));
+        }
+        if self.is_spi2(addr) {
+            return Ok(u32::from(self.spi2.read(addr - self.spi2_base)));
+        }
+        if self.is_i2c0(addr) {
+            return Ok(u32::from(self.i2c0.read(addr - self.i2c0_base)));
+        }
+        if self.is_gpio(addr) {
+            return Ok(u32::from(self.gpio.read(addr - self.gpio_base)));
+        }
+        if self.is_watchdog(addr) {
+            return Ok(u32::from(self.watchdog.read(addr - self.watchdog_base)));
+        }
+        if self.is_rtc(addr) {
+            return Ok(u32::from(self.rtc.read(addr - self.rtc_base)));
+        }
+        if self.is_prci(addr) {
+            return Ok(u32::from(self.prci.read(addr - self.prci_base)));
+        }
+        Err(anyhow::anyhow!(
+            "Memory: attempted read outside memory map at address: 0x{addr:08X}"
+        ))
+    }
 
-        // FIXME: Temporal hack to get RIOT happy in-time for the 1.0 release
-        #[allow(clippy::match_same_arms)]
-        match addr {
-            // PLIC
-            0x0C20_0004 => {
-                if self.uart0.pending_interrupt().is_some() {
-                    Ok(0x03)
-                } else if self.uart1.pending_interrupt().is_some() {
-                    Ok(0x04)
-                } else {
-                    Ok(0x00)
-                }
-            }
-            0x0C00_0000..=0x0FFF_FFFF => Ok(0x00),
-            // WDT
-            0x1000_0000..=0x1000_003F => Ok(0x00),
-            // RTC
-            0x1000_0040..=0x1000_0080 => Ok(0x00),
-            // PRCI
-            0x1000_8000..=0x1000_800F => {
-                // RIOT uses hfrosccfg, hfxosccfg, pllcfg, plloutdiv, procmoncfg
-                Ok(0xFF)
-            }
-            // GPIO
-            0x1001_2000..=0x1001_2FFF => Ok(0xFF),
-            // timer?
-            0x0200_BFF8..=0x0200_BFFF => Ok(0),
-            0x0200_4000..=0x0200_4003 => Ok(0),
-            _ => Err(anyhow::anyhow!(
-                "Memory: attempted read outside memory map at address: 0x{addr:08X}"
-            )),
+    fn read_halfword(&self, addr: usize) -> anyhow::Result<u32> {
+        if let Some(bytes) = self.memory_slice(addr, 2) {
+            return Ok(u32::from(u16::from_le_bytes([bytes[0], bytes[1]])));
         }
+        Ok((self.read_byte(addr + 1)? << 8) + self.read_byte(addr)?)
+    }
+
+    fn read_word(&self, addr: usize) -> anyhow::Result<u32> {
+        if let Some(bytes) = self.memory_slice(addr, 4) {
+            return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        }
+        Ok((self.read_halfword(addr + 2)? << 16) + self.read_halfword(addr)?)
+    }
+
+    fn write_halfword(&mut self, addr: usize, value: u32) -> anyhow::Result<()> {
+        if let Some(bytes) = self.ram_slice_mut(addr, 2) {
+            bytes.copy_from_slice(&(value as u16).to_le_bytes());
+            return Ok(());
+        }
+        self.write_byte(addr, value)?;
+        self.write_byte(addr + 1, value >> 8)
+    }
+
+    fn write_word(&mut self, addr: usize, value: u32) -> anyhow::Result<()> {
+        if let Some(bytes) = self.ram_slice_mut(addr, 4) {
+            bytes.copy_from_slice(&value.to_le_bytes());
+            return Ok(());
+        }
+        self.write_halfword(addr, value)?;
+        self.write_halfword(addr + 2, value >> 16)
     }
 
     fn write_byte(&mut self, addr: usize, value: u32) -> anyhow::Result<()> {
         if self.is_ram(addr) {
-            let index = addr - self.ram_base;
-            self.ram[index] = (value & 0xFF) as u8;
+            self.ram
+                .write_byte(addr - self.ram_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_clint(addr) {
+            self.clint
+                .write(addr - self.clint_base, (value & 0xFF) as u8);
             return Ok(());
         }
         if self.is_uart0(addr) {
@@ -198,29 +685,59 @@ impl AddrBus for Memory {
                 .write(addr - self.uart1_base, (value & 0xFF) as u8);
             return Ok(());
         }
-
-        // FIXME: Temporal hack to get RIOT happy in-time for the 1.0 release
-        #[allow(clippy::match_same_arms)]
-        match addr {
-            // PLIC
-            0x0C00_0000..=0x0FFF_FFFF => Ok(()),
-            // WDT
-            0x1000_0000..=0x1000_003F => Ok(()),
-            // RTT
-            0x1000_0040..=0x1000_0080 => Ok(()),
-            // PRCI
-            0x1000_8000..=0x1000_800F => {
-                // RIOT uses hfrosccfg, hfxosccfg, pllcfg, plloutdiv, procmoncfg
-                Ok(())
-            }
-            // GPIO
-            0x1001_2000..=0x1001_2FFF => Ok(()),
-            // timer?
-            0x0200_BFF8..=0x0200_BFFF => Ok(()),
-            0x0200_4000..=0x0200_4007 => Ok(()),
-            _ => Err(anyhow::anyhow!(
-                "Memory: attempted write outside writable memory map at address: 0x{addr:08X}"
-            )),
+        if self.is_spi0(addr) {
+            self.spi0.write(addr - self.spi0_base, (value & 0xFF) as u8);
+            return self.spi0.device.take_error();
+        }
+        if self.is_plic(addr) {
+            self.plic.write(addr - self.plic_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_pwm0(addr) {
+            self.pwm0.write(addr - self.pwm0_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_pwm1(addr) {
+            self.pwm1.write(addr - self.pwm1_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_pwm2(addr) {
+            self.pwm2.write(addr - self.pwm2_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_spi1(addr) {
+            self.spi1.write(addr - self.spi1_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_spi2(addr) {
+            self.spi2.write(addr - self.spi2_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_i2c0(addr) {
+            self.i2c0.write(addr - self.i2c0_base, (value & 0xFF) as u8);
+            return self.i2c0.take_error();
+        }
+        if self.is_gpio(addr) {
+            self.gpio.write(addr - self.gpio_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_watchdog(addr) {
+            self.watchdog
+                .write(addr - self.watchdog_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_rtc(addr) {
+            self.rtc.write(addr - self.rtc_base, (value & 0xFF) as u8);
+            return Ok(());
+        }
+        if self.is_prci(addr) {
+            self.prci.write(addr - self.prci_base, (value & 0xFF) as u8);
+            // The time passes with the new core clock from now on
+            self.core_frequency = self.prci.core_frequency();
+            return Ok(());
         }
+        Err(anyhow::anyhow!(
+            "Memory: attempted write outside writable memory map at address: 0x{addr:08X}"
+        ))
     }
 }
@@ -0,0 +1,252 @@
+//! The NOR flash on SPI0 of the Hifive1b is scoped for this file.
+//! It understands the common JEDEC commands to identify, read, program and erase the flash.
+//! Erasing sets the bytes to `0xFF`, programming can only clear bits.
+//! If an image file is attached, it holds the initial content and every program
+//! or erase operation is written back to it. Beyond the end of the image, the flash is erased.
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::sparse_memory::SparseMemory;
+use super::spi::SpiDevice;
+
+/// The ISSI IS25LP032D of the Hifive1b: manufacturer, memory type and capacity (4 MiB)
+const JEDEC_ID: [u8; 3] = [0x9D, 0x60, 0x16];
+
+const PAGE_SIZE: usize = 256;
+const SECTOR_SIZE: usize = 4 * 1024;
+const BLOCK_SIZE: usize = 64 * 1024;
+
+const WRITE_STATUS: u8 = 0x01;
+const PAGE_PROGRAM: u8 = 0x02;
+const READ: u8 = 0x03;
+const WRITE_DISABLE: u8 = 0x04;
+const READ_STATUS: u8 = 0x05;
+const WRITE_ENABLE: u8 = 0x06;
+const FAST_READ: u8 = 0x0B;
+const SECTOR_ERASE: u8 = 0x20;
+const CHIP_ERASE: u8 = 0xC7;
+const CHIP_ERASE_ALTERNATIVE: u8 = 0x60;
+const READ_ID: u8 = 0x9F;
+const RELEASE_POWER_DOWN: u8 = 0xAB;
+const BLOCK_ERASE: u8 = 0xD8;
+
+/// `WEL` in the status register, set by write enable and cleared by every program or erase
+const STATUS_WEL: u8 = 1 << 1;
+
+pub struct NorFlash {
+    /// The content, read directly by the memory-mapped window
+    pub array: SparseMemory,
+    image: Option<File>,
+    status: u8,
+    /// The command of the current transaction and the frames received after it
+    command: Option<u8>,
+    frames: usize,
+    address: usize,
+    program: Vec<u8>,
+    /// The range changed by program or erase operations, since it was last taken
+    modified: Option<(usize, usize)>,
+    /// Writing back to the image failed, the error is taken by the bus
+    error: Option<anyhow::Error>,
+}
+
+impl NorFlash {
+    pub fn new(size: usize) -> Self {
+        Self {
+            array: SparseMemory::new(size, 0xFF),
+            image: None,
+            status: 0,
+            command: None,
+            frames: 0,
+            address: 0,
+            program: Vec::with_capacity(PAGE_SIZE),
+            modified: None,
+            error: None,
+        }
+    }
+
+    /// Loads the image file, it is created if it does not exist yet.
+    /// Only a persistent image is written back, otherwise a missing image leaves the flash erased.
+    pub fn attach_image(&mut self, path: &Path, persistent: bool) -> anyhow::Result<()> {
+        if !persistent && !path.exists() {
+            return Ok(());
+        }
+        let mut file = File::options()
+            .read(true)
+            .write(persistent)
+            .create(persistent)
+            .truncate(false)
+            .open(path)
+            .context(format!("Could not open the flash image {}", path.display()))?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)
+            .context(format!("Could not read the flash image {}", path.display()))?;
+        if content.len() > self.array.size() {
+            anyhow::bail!(
+                "The flash image {} is larger than the flash of 0x{:X} bytes",
+                path.display(),
+                self.array.size()
+            );
+        }
+        self.array.load(0, &content);
+        if persistent {
+            self.image = Some(file);
+        }
+        Ok(())
+    }
+
+    /// The range `[offset, offset + size)` changed by program or erase operations since the
+    /// last call, the CPU may have cached instructions from it.
+    pub fn take_modified(&mut self) -> Option<(usize, usize)> {
+        self.modified.take()
+    }
+
+    fn modify(&mut self, offset: usize, size: usize) {
+        let (start, end) = match self.modified {
+            Some((start, length)) => (start.min(offset), (start + length).max(offset + size)),
+            None => (offset, offset + size),
+        };
+        self.modified = Some((start, end - start));
+        self.persist(offset, size);
+    }
+
+    /// Writes the range back to the image, the gap to its current end is erased flash.
+    fn persist(&mut self, offset: usize, size: usize) {
+        let Some(image) = &mut self.image else {
+            return;
+        };
+        let mut content = vec![0xFF; size];
+        for (index, byte) in content.iter_mut().enumerate() {
+            *byte = self.array.read_byte(offset + index);
+        }
+        let written = image
+            .metadata()
+            .map(|metadata| metadata.len())
+            .and_then(|length| {
+                let length = usize::try_from(length).unwrap_or(usize::MAX);
+                if length < offset {
+                    image.seek(SeekFrom::End(0))?;
+                    image.write_all(&vec![0xFF; offset - length])?;
+                }
+                image.seek(SeekFrom::Start(offset as u64))?;
+                image.write_all(&content)
+            });
+        if let Err(err) = written {
+            self.error = Some(anyhow::anyhow!(
+                "Flash: could not write back to the image: {err}"
+            ));
+        }
+    }
+
+    fn erase(&mut self, size: usize) {
+        let offset = self.address & !(size - 1);
+        self.array.erase(offset, size);
+        self.modify(offset, size);
+    }
+
+    /// Programming wraps around within the page of the start address
+    fn commit_program(&mut self) {
+        let page = self.address & !(PAGE_SIZE - 1);
+        for (index, byte) in std::mem::take(&mut self.program).into_iter().enumerate() {
+            let offset = page + (self.address + index) % PAGE_SIZE;
+            let erased = self.array.read_byte(offset);
+            self.array.write_byte(offset, erased & byte);
+        }
+        self.modify(page, PAGE_SIZE);
+    }
+
+    /// The address is sent with the three frames after the command, most significant byte first.
+    /// Returns true once it is complete.
+    fn receive_address(&mut self, mosi: u8) -> bool {
+        if self.frames <= 4 {
+            self.address = (self.address << 8 | usize::from(mosi)) & 0xFF_FFFF;
+        }
+        self.frames > 4
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let byte = self.array.read_byte(self.address % self.array.size());
+        self.address += 1;
+        byte
+    }
+}
+
+impl SpiDevice for NorFlash {
+    fn select(&mut self) {
+        self.command = None;
+        self.frames = 0;
+        self.address = 0;
+        self.program.clear();
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        self.frames += 1;
+        let Some(command) = self.command else {
+            self.command = Some(mosi);
+            match mosi {
+                WRITE_ENABLE => self.status |= STATUS_WEL,
+                WRITE_DISABLE => self.status &= !STATUS_WEL,
+                _ => (),
+            }
+            return 0xFF;
+        };
+        let addressed = match command {
+            READ | FAST_READ | PAGE_PROGRAM | SECTOR_ERASE | BLOCK_ERASE => {
+                self.receive_address(mosi)
+            }
+            _ => false,
+        };
+        match command {
+            READ_STATUS => self.status,
+            READ_ID => JEDEC_ID[(self.frames - 2) % JEDEC_ID.len()],
+            // Only the block protection and quad enable bits are writable
+            WRITE_STATUS if self.frames == 2 && self.status & STATUS_WEL != 0 => {
+                self.status = mosi & 0b0111_1100;
+                0xFF
+            }
+            READ if addressed => self.read_data(),
+            // One dummy frame follows the address
+            FAST_READ if addressed && self.frames > 5 => self.read_data(),
+            PAGE_PROGRAM if addressed && self.program.len() < PAGE_SIZE => {
+                self.program.push(mosi);
+                0xFF
+            }
+            _ => 0xFF,
+        }
+    }
+
+    /// Program and erase operations are executed once the chip select is released.
+    /// They complete instantly, so the busy flag never has to be polled.
+    fn deselect(&mut self) {
+        let Some(command) = self.command.take() else {
+            return;
+        };
+        if self.status & STATUS_WEL == 0 {
+            return;
+        }
+        match command {
+            PAGE_PROGRAM if self.frames > 4 => self.commit_program(),
+            SECTOR_ERASE if self.frames == 4 => self.erase(SECTOR_SIZE),
+            BLOCK_ERASE if self.frames == 4 => self.erase(BLOCK_SIZE),
+            CHIP_ERASE | CHIP_ERASE_ALTERNATIVE => {
+                self.array.erase(0, self.array.size());
+                self.modified = Some((0, self.array.size()));
+                if let Some(image) = &self.image {
+                    if let Err(err) = image.set_len(0) {
+                        self.error =
+                            Some(anyhow::anyhow!("Flash: could not erase the image: {err}"));
+                    }
+                }
+            }
+            WRITE_STATUS | RELEASE_POWER_DOWN => (),
+            _ => return,
+        }
+        self.status &= !STATUS_WEL;
+    }
+
+    fn take_error(&mut self) -> anyhow::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
@@ -0,0 +1,196 @@
+//! The Platform-Level Interrupt Controller (PLIC) of the Hifive1b is scoped for this file.
+//! It collects the interrupt lines of the peripherals and raises the external interrupt of the harts.
+//! Every hart has a machine mode context of its own, laid out like on the multi-hart SiFive cores.
+//! The lines are level-triggered: a source becomes pending while its line is raised and
+//! stays pending until it is claimed. It is not pending again until the claim is completed.
+use std::cell::Cell;
+
+use crate::cpu::MIP_MEIP;
+use crate::periph::write_byte_of;
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+/// The interrupt sources of the FE310, source 0 means no interrupt
+pub const SOURCES: usize = 53;
+pub const WDOG: usize = 1;
+pub const RTC: usize = 2;
+pub const UART0: usize = 3;
+pub const UART1: usize = 4;
+pub const SPI0: usize = 5;
+pub const SPI1: usize = 6;
+pub const SPI2: usize = 7;
+/// The first of the 32 GPIO sources, one for every pin
+pub const GPIO0: usize = 8;
+/// The first of the 4 sources of every PWM controller, one for every comparator
+pub const PWM0: usize = 40;
+pub const PWM1: usize = 44;
+pub const PWM2: usize = 48;
+pub const I2C0: usize = 52;
+
+const PRIORITY: usize = 0x0000;
+const PENDING: usize = 0x1000;
+const ENABLE: usize = 0x2000;
+/// The enable bits of the contexts follow each other at this stride
+const ENABLE_STRIDE: usize = 0x80;
+const THRESHOLD: usize = 0x20_0000;
+const CLAIM: usize = 0x20_0004;
+/// The threshold and claim registers of the contexts follow each other at this stride
+const CONTEXT_STRIDE: usize = 0x1000;
+
+const PRIORITY_MASK: u32 = 0b111;
+
+/// The machine mode context of a hart
+#[derive(Clone, Copy, Default)]
+struct Context {
+    enable: u64,
+    threshold: u32,
+}
+
+/// A register of the context of a hart, the enable bits are addressed by the byte offset into them
+enum ContextRegister {
+    Enable(usize, usize),
+    Threshold(usize),
+    Claim(usize),
+}
+
+pub struct Plic {
+    priority: [u32; SOURCES],
+    /// Context `n` belongs to hart `n`
+    contexts: Vec<Context>,
+    /// A source is pending and claimed for all contexts, whichever claims it first gets it.
+    /// Claiming is done by reading the claim register
+    pending: Cell<u64>,
+    claimed: Cell<u64>,
+}
+
+impl Plic {
+    pub fn new(harts: usize) -> Self {
+        Self {
+            priority: [0; SOURCES],
+            contexts: vec![Context::default(); harts],
+            pending: Cell::new(0),
+            claimed: Cell::new(0),
+        }
+    }
+
+    /// Samples the interrupt lines, bit `n` is the line of source `n`.
+    pub fn update(&mut self, lines: u64) {
+        let lines = lines & !1;
+        self.pending
+            .set(self.pending.get() | (lines & !self.claimed.get()));
+    }
+
+    /// The pending source enabled for the context with the highest priority above its threshold.
+    /// On equal priority, the lower source wins.
+    fn highest_pending(&self, context: usize) -> Option<usize> {
+        let Context { enable, threshold } = self.contexts[context];
+        let candidates = self.pending.get() & enable;
+        if candidates == 0 {
+            return None;
+        }
+        (1..SOURCES)
+            .filter(|source| candidates & (1 << source) != 0)
+            .filter(|&source| self.priority[source] > threshold)
+            .min_by_key(|&source| (std::cmp::Reverse(self.priority[source]), source))
+    }
+
+    /// The external interrupt of `hart`, if a source is pending for its context.
+    pub fn pending_interrupt_of(&self, hart: usize) -> Option<InterruptReason> {
+        self.highest_pending(hart).map(|_source| MIP_MEIP)
+    }
+
+    fn claim(&self, context: usize) -> u32 {
+        let Some(source) = self.highest_pending(context) else {
+            return 0;
+        };
+        self.pending.set(self.pending.get() & !(1 << source));
+        self.claimed.set(self.claimed.get() | 1 << source);
+        source as u32
+    }
+
+    /// The context and register addressed by `register`, if it is one of a context.
+    fn context_register(&self, register: usize) -> Option<ContextRegister> {
+        let harts = self.contexts.len();
+        match register {
+            ENABLE..THRESHOLD if (register - ENABLE) / ENABLE_STRIDE < harts => {
+                let context = (register - ENABLE) / ENABLE_STRIDE;
+                let offset = (register - ENABLE) % ENABLE_STRIDE;
+                (offset < 8).then_some(ContextRegister::Enable(context, offset))
+            }
+            THRESHOLD.. if (register - THRESHOLD) / CONTEXT_STRIDE < harts => {
+                let context = (register - THRESHOLD) / CONTEXT_STRIDE;
+                match THRESHOLD + (register - THRESHOLD) % CONTEXT_STRIDE {
+                    THRESHOLD => Some(ContextRegister::Threshold(context)),
+                    CLAIM => Some(ContextRegister::Claim(context)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Completing a source that is not enabled for the context is ignored.
+    fn complete(&mut self, context: usize, source: usize) {
+        if source < SOURCES && self.contexts[context].enable & (1 << source) != 0 {
+            self.claimed.set(self.claimed.get() & !(1 << source));
+        }
+    }
+}
+
+impl MmapPeripheral for Plic {
+    fn read(&self, offset: usize) -> u8 {
+        let byte = offset % 4;
+        let register = offset - byte;
+        let value = match register {
+            PRIORITY..PENDING if (register - PRIORITY) / 4 < SOURCES => {
+                self.priority[(register - PRIORITY) / 4]
+            }
+            PENDING | 0x1004 => (self.pending.get() >> ((register - PENDING) * 8)) as u32,
+            _ => match self.context_register(register) {
+                Some(ContextRegister::Enable(context, offset)) => {
+                    (self.contexts[context].enable >> (offset * 8)) as u32
+                }
+                Some(ContextRegister::Threshold(context)) => self.contexts[context].threshold,
+                Some(ContextRegister::Claim(context)) if byte == 0 => self.claim(context),
+                _ => 0,
+            },
+        };
+        (value >> (byte * 8)) as u8
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        let byte = offset % 4;
+        let register = offset - byte;
+        match register {
+            PRIORITY..PENDING if (register - PRIORITY) / 4 < SOURCES => {
+                let priority = &mut self.priority[(register - PRIORITY) / 4];
+                write_byte_of(priority, byte, value);
+                *priority &= PRIORITY_MASK;
+                // Source 0 does not exist
+                self.priority[0] = 0;
+            }
+            _ => match self.context_register(register) {
+                Some(ContextRegister::Enable(context, offset)) => {
+                    let enable = &mut self.contexts[context].enable;
+                    let shift = offset * 8 + byte * 8;
+                    *enable &= !(0xFF << shift);
+                    *enable |= u64::from(value) << shift;
+                    *enable &= (1 << SOURCES) - 2;
+                }
+                Some(ContextRegister::Threshold(context)) => {
+                    let threshold = &mut self.contexts[context].threshold;
+                    write_byte_of(threshold, byte, value);
+                    *threshold &= PRIORITY_MASK;
+                }
+                Some(ContextRegister::Claim(context)) if byte == 0 => {
+                    self.complete(context, usize::from(value));
+                }
+                _ => (),
+            },
+        }
+    }
+
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        self.pending_interrupt_of(0)
+    }
+}
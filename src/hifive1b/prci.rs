@@ -0,0 +1,134 @@
+//! The power, reset, clock and interrupt (PRCI) block of the Hifive1b is scoped for this file.
+//! It selects the clock of the core: the internal ring oscillator (HFROSC) or the PLL,
+//! which is fed by the ring oscillator or the 16 MHz crystal oscillator (HFXOSC).
+//! The oscillators are ready and the PLL is locked as soon as they are enabled.
+use crate::periph::write_byte_of;
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+/// The ring oscillator runs at about 72 MHz, its trim is ignored
+const HFROSC_FREQUENCY: u64 = 72_000_000;
+const HFXOSC_FREQUENCY: u64 = 16_000_000;
+
+const HFROSCCFG: usize = 0x00;
+const HFXOSCCFG: usize = 0x04;
+const PLLCFG: usize = 0x08;
+const PLLOUTDIV: usize = 0x0C;
+
+const HFROSC_DIV: u32 = 0x3F;
+const HFROSC_TRIM: u32 = 0x1F << 16;
+const HFROSC_EN: u32 = 1 << 30;
+const HFXOSC_EN: u32 = 1 << 30;
+/// The ready bit of both oscillators
+const OSC_RDY: u32 = 1 << 31;
+
+const PLL_R: u32 = 0x7;
+const PLL_F: u32 = 0x3F << 4;
+const PLL_Q: u32 = 0x3 << 10;
+const PLL_SEL: u32 = 1 << 16;
+const PLL_REFSEL: u32 = 1 << 17;
+const PLL_BYPASS: u32 = 1 << 18;
+const PLL_LOCK: u32 = 1 << 31;
+
+const PLLOUT_DIV: u32 = 0x3F;
+const PLLOUT_DIV_BY_1: u32 = 1 << 8;
+
+pub struct Prci {
+    hfrosccfg: u32,
+    hfxosccfg: u32,
+    pllcfg: u32,
+    plloutdiv: u32,
+}
+
+impl Prci {
+    /// After reset, the core runs with the ring oscillator divided by 5
+    pub fn new() -> Self {
+        Self {
+            hfrosccfg: HFROSC_EN | 16 << 16 | 4,
+            hfxosccfg: HFXOSC_EN,
+            pllcfg: PLL_BYPASS | PLL_REFSEL | 0x3 << 10 | 0x1F << 4 | 0x1,
+            plloutdiv: PLLOUT_DIV_BY_1,
+        }
+    }
+
+    fn hfrosc_frequency(&self) -> u64 {
+        HFROSC_FREQUENCY / u64::from((self.hfrosccfg & HFROSC_DIV) + 1)
+    }
+
+    /// The PLL multiplies the reference by `2 * (f + 1) / (r + 1)` and divides it by `2^q`.
+    /// A value of zero for `q` is reserved, it is treated like one.
+    fn pll_frequency(&self) -> u64 {
+        let reference = if self.pllcfg & PLL_REFSEL == 0 {
+            self.hfrosc_frequency()
+        } else {
+            HFXOSC_FREQUENCY
+        };
+        if self.pllcfg & PLL_BYPASS != 0 {
+            return reference;
+        }
+        let r = u64::from(self.pllcfg & PLL_R) + 1;
+        let f = 2 * (u64::from((self.pllcfg & PLL_F) >> 4) + 1);
+        let q = ((self.pllcfg & PLL_Q) >> 10).max(1);
+        (reference / r * f) >> q
+    }
+
+    /// The frequency of the core clock (hfclk) in Hz
+    pub fn core_frequency(&self) -> u64 {
+        if self.pllcfg & PLL_SEL == 0 {
+            return self.hfrosc_frequency();
+        }
+        let frequency = self.pll_frequency();
+        if self.plloutdiv & PLLOUT_DIV_BY_1 == 0 {
+            frequency / (2 * u64::from((self.plloutdiv & PLLOUT_DIV) + 1))
+        } else {
+            frequency
+        }
+    }
+
+    fn pll_locked(&self) -> bool {
+        let reference_enabled = if self.pllcfg & PLL_REFSEL == 0 {
+            self.hfrosccfg & HFROSC_EN != 0
+        } else {
+            self.hfxosccfg & HFXOSC_EN != 0
+        };
+        reference_enabled && self.pllcfg & PLL_BYPASS == 0
+    }
+}
+
+impl MmapPeripheral for Prci {
+    fn read(&self, offset: usize) -> u8 {
+        let byte = offset % 4;
+        let value = match offset - byte {
+            HFROSCCFG if self.hfrosccfg & HFROSC_EN != 0 => self.hfrosccfg | OSC_RDY,
+            HFROSCCFG => self.hfrosccfg,
+            HFXOSCCFG if self.hfxosccfg & HFXOSC_EN != 0 => self.hfxosccfg | OSC_RDY,
+            HFXOSCCFG => self.hfxosccfg,
+            PLLCFG if self.pll_locked() => self.pllcfg | PLL_LOCK,
+            PLLCFG => self.pllcfg,
+            PLLOUTDIV => self.plloutdiv,
+            _ => 0,
+        };
+        (value >> (byte * 8)) as u8
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        let byte = offset % 4;
+        // Only the implemented fields keep what was written
+        let (target, mask) = match offset - byte {
+            HFROSCCFG => (&mut self.hfrosccfg, HFROSC_EN | HFROSC_TRIM | HFROSC_DIV),
+            HFXOSCCFG => (&mut self.hfxosccfg, HFXOSC_EN),
+            PLLCFG => (
+                &mut self.pllcfg,
+                PLL_BYPASS | PLL_REFSEL | PLL_SEL | PLL_Q | PLL_F | PLL_R,
+            ),
+            PLLOUTDIV => (&mut self.plloutdiv, PLLOUT_DIV_BY_1 | PLLOUT_DIV),
+            _ => return,
+        };
+        write_byte_of(target, byte, value);
+        *target &= mask;
+    }
+
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        None
+    }
+}
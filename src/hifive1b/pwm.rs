@@ -0,0 +1,292 @@
+//! The pulse width modulation (PWM) controllers of the Hifive1b are scoped for this file.
+//! The counter advances with the core clock, `pwms` is the counter scaled down by `pwmscale`.
+//! Every comparator raises its `pwmcmpXip` bit while `pwms` is at least `pwmcmpX`,
+//! the bit is also its output and its interrupt line.
+//! The duty cycles of the outputs are published through `PwmDuty`, e.g. for the TUI.
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::periph::write_byte_of;
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+const PWMCFG: usize = 0x00;
+const PWMCOUNT: usize = 0x08;
+const PWMS: usize = 0x10;
+const PWMCMP0: usize = 0x20;
+const PWMCMP3: usize = 0x2C;
+
+pub const CONTROLLERS: usize = 3;
+pub const CHANNELS: usize = 4;
+
+const CFG_SCALE: u32 = 0xF;
+/// The `pwmcmpXip` bits can only be cleared by the firmware
+const CFG_STICKY: u32 = 1 << 8;
+/// The counter is reset when `pwms` reaches `pwmcmp0`
+const CFG_ZEROCMP: u32 = 1 << 9;
+/// The `pwmcmpXip` bits stay set until `pwms` wraps
+const CFG_DEGLITCH: u32 = 1 << 10;
+const CFG_ENALWAYS: u32 = 1 << 12;
+/// The counter runs until it is reset, then this bit is cleared
+const CFG_ENONESHOT: u32 = 1 << 13;
+const CFG_CENTER: u32 = 16;
+const CFG_GANG: u32 = 24;
+const CFG_IP: u32 = 28;
+const CFG_MASK: u32 = CFG_SCALE
+    | CFG_STICKY
+    | CFG_ZEROCMP
+    | CFG_DEGLITCH
+    | CFG_ENALWAYS
+    | CFG_ENONESHOT
+    | 0xF << CFG_CENTER
+    | 0xF << CFG_GANG
+    | 0xF << CFG_IP;
+
+/// The counter has 15 bits more than the comparators
+const COUNT_EXTRA_BITS: u32 = 15;
+
+/// The duty cycles of all outputs, published by the controllers and shared with the TUI.
+#[derive(Clone, Default)]
+pub struct PwmDuty(Arc<[AtomicU32; CONTROLLERS * CHANNELS]>);
+
+impl PwmDuty {
+    /// The share of time the output of the comparator is high, between 0 and 1
+    pub fn duty(&self, controller: usize, channel: usize) -> f32 {
+        f32::from_bits(self.0[controller * CHANNELS + channel].load(Ordering::Relaxed))
+    }
+
+    fn publish(&self, controller: usize, channel: usize, duty: f32) {
+        self.0[controller * CHANNELS + channel].store(duty.to_bits(), Ordering::Relaxed);
+    }
+}
+
+pub struct Pwm {
+    /// The number of the controller, its outputs are published with it
+    index: usize,
+    /// PWM0 has 8 bit comparators, PWM1 and PWM2 16 bit
+    cmp_width: u32,
+    cfg: u32,
+    count: u64,
+    cmp: [u32; CHANNELS],
+    duty: PwmDuty,
+}
+
+impl Pwm {
+    pub fn new(index: usize, cmp_width: u32, duty: PwmDuty) -> Self {
+        let pwm = Self {
+            index,
+            cmp_width,
+            cfg: 0,
+            count: 0,
+            cmp: [0; CHANNELS],
+            duty,
+        };
+        pwm.publish_duty();
+        pwm
+    }
+
+    /// Returns the registers to their reset values, the counter stops.
+    pub fn reset(&mut self) {
+        self.cfg = 0;
+        self.count = 0;
+        self.cmp = [0; CHANNELS];
+        self.publish_duty();
+    }
+
+    /// The interrupt lines of the comparators, bit `n` is the line of `pwmcmpN`
+    pub fn interrupt_lines(&self) -> u32 {
+        self.cfg >> CFG_IP
+    }
+
+    fn running(&self) -> bool {
+        self.cfg & (CFG_ENALWAYS | CFG_ENONESHOT) != 0
+    }
+
+    fn scale(&self) -> u32 {
+        self.cfg & CFG_SCALE
+    }
+
+    fn cmp_mask(&self) -> u32 {
+        (1 << self.cmp_width) - 1
+    }
+
+    fn pwms(&self) -> u32 {
+        (self.count >> self.scale()) as u32 & self.cmp_mask()
+    }
+
+    /// The values of `pwms` in one period, with `pwmzerocmp` it ends before `pwmcmp0`.
+    fn period(&self) -> u32 {
+        if self.cfg & CFG_ZEROCMP != 0 {
+            self.cmp[0].max(1)
+        } else {
+            1 << self.cmp_width
+        }
+    }
+
+    /// The counter values in one period of `pwms`
+    fn period_counts(&self) -> u64 {
+        u64::from(self.period()) << self.scale()
+    }
+
+    /// The ranges `start..end` of `pwms` in one period in which the comparator is set.
+    /// In center mode, the comparator sees `pwms` mirrored in the upper half, so it counts up and down.
+    fn active(&self, channel: usize) -> [(u32, u32); 2] {
+        let cmp = self.cmp[channel];
+        let period = self.period();
+        if self.cfg & (1 << (CFG_CENTER + channel as u32)) == 0 {
+            return [(cmp, period), (0, 0)];
+        }
+        let half = 1 << (self.cmp_width - 1);
+        [
+            (cmp, period.min(half)),
+            (half, period.min((1 << self.cmp_width) - cmp)),
+        ]
+    }
+
+    /// The number of `pwms` values in the ranges of both lists, empty ranges have no values
+    fn overlap(first: &[(u32, u32)], second: &[(u32, u32)]) -> u32 {
+        let mut overlap = 0;
+        for (start, end) in first {
+            for (other_start, other_end) in second {
+                overlap += end.min(other_end).saturating_sub(*start.max(other_start));
+            }
+        }
+        overlap
+    }
+
+    /// A ganged output is only high while the next comparator is not set.
+    fn ganged(&self, channel: usize) -> bool {
+        self.cfg & (1 << (CFG_GANG + channel as u32)) != 0
+    }
+
+    fn publish_duty(&self) {
+        for channel in 0..CHANNELS {
+            let next = (channel + 1) % CHANNELS;
+            let duty = if self.running() {
+                let active = self.active(channel);
+                let mut high = Self::overlap(&active, &[(0, u32::MAX)]);
+                if self.ganged(channel) {
+                    high -= Self::overlap(&active, &self.active(next));
+                }
+                (f64::from(high) / f64::from(self.period())) as f32
+            } else {
+                let ip = self.interrupt_lines();
+                let high = ip >> channel & 1 != 0 && !(self.ganged(channel) && ip >> next & 1 != 0);
+                f32::from(u8::from(high))
+            };
+            self.duty.publish(self.index, channel, duty);
+        }
+    }
+
+    /// Updates the `pwmcmpXip` bits after the counter passed the positions
+    /// `first..=last` of the current period of `pwms`.
+    fn compare(&mut self, first: u64, last: u64, wrapped: bool) {
+        let first = (first >> self.scale()) as u32;
+        let last = (last >> self.scale()) as u32;
+        let current = self.pwms();
+        for channel in 0..CHANNELS {
+            let active = self.active(channel);
+            let level = Self::overlap(&active, &[(current, current + 1)]) > 0;
+            // With `pwmzerocmp`, the counter is reset as `pwms` reaches `pwmcmp0`
+            let passed = (channel == 0 && wrapped && self.cfg & CFG_ZEROCMP != 0)
+                || Self::overlap(&active, &[(first, last + 1)]) > 0;
+            let bit = 1 << (CFG_IP + channel as u32);
+            let ip = if self.cfg & CFG_STICKY != 0 {
+                self.cfg & bit != 0 || passed
+            } else if self.cfg & CFG_DEGLITCH != 0 {
+                (self.cfg & bit != 0 && !wrapped) || passed
+            } else {
+                level
+            };
+            if ip {
+                self.cfg |= bit;
+            } else {
+                self.cfg &= !bit;
+            }
+        }
+    }
+}
+
+impl MmapPeripheral for Pwm {
+    fn read(&self, offset: usize) -> u8 {
+        let byte = offset % 4;
+        let value = match offset - byte {
+            PWMCFG => self.cfg,
+            PWMCOUNT => self.count as u32,
+            PWMS => self.pwms(),
+            register @ PWMCMP0..=PWMCMP3 => self.cmp[(register - PWMCMP0) / 4],
+            _ => 0,
+        };
+        (value >> (byte * 8)) as u8
+    }
+
+    /// `pwms` follows the counter and can not be written.
+    fn write(&mut self, offset: usize, value: u8) {
+        let byte = offset % 4;
+        match offset - byte {
+            PWMCFG => {
+                write_byte_of(&mut self.cfg, byte, value);
+                self.cfg &= CFG_MASK;
+            }
+            PWMCOUNT => {
+                let mut count = self.count as u32;
+                write_byte_of(&mut count, byte, value);
+                self.count = u64::from(count) & ((1 << (self.cmp_width + COUNT_EXTRA_BITS)) - 1);
+            }
+            register @ PWMCMP0..=PWMCMP3 => {
+                let mask = self.cmp_mask();
+                let cmp = &mut self.cmp[(register - PWMCMP0) / 4];
+                write_byte_of(cmp, byte, value);
+                *cmp &= mask;
+            }
+            _ => return,
+        }
+        self.publish_duty();
+    }
+
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        match self.interrupt_lines() {
+            0 => None,
+            lines => Some(lines),
+        }
+    }
+
+    /// A counter that only runs once stops at the reset, the remaining cycles are not counted.
+    fn tick(&mut self, cycles: u64) {
+        if !self.running() {
+            return;
+        }
+        let limit = if self.cfg & CFG_ZEROCMP != 0 {
+            self.period_counts()
+        } else {
+            1 << (self.cmp_width + COUNT_EXTRA_BITS)
+        };
+        let mut cycles = cycles;
+        if self.cfg & CFG_ENALWAYS == 0 {
+            cycles = cycles.min(limit - self.count % limit);
+        }
+        let period = self.period_counts();
+        let position = self.count % period;
+        let wrapped = position + cycles >= period;
+        let reset = self.count % limit + cycles >= limit;
+        self.count = (self.count + cycles) % limit;
+
+        if cycles >= period {
+            self.compare(0, period - 1, wrapped);
+        } else if wrapped {
+            // The positions before the wrap only count for sticky bits
+            let last = (position + cycles) % period;
+            if self.cfg & CFG_STICKY != 0 {
+                self.compare(position + 1, period - 1, wrapped);
+            }
+            self.compare(0, last, wrapped);
+        } else {
+            self.compare(position + 1, position + cycles, wrapped);
+        }
+
+        if reset && self.cfg & CFG_ENALWAYS == 0 {
+            self.cfg &= !CFG_ENONESHOT;
+            self.publish_duty();
+        }
+    }
+}
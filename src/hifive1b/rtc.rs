@@ -0,0 +1,93 @@
+//! The real time clock (RTC) of the always-on (AON) domain of the Hifive1b is scoped for this file.
+//! Its 48 bit counter runs with the real time clock while enabled. The interrupt is pending
+//! as long as the scaled count `rtcs` is at least `rtccmp0`, writing the compare value clears it.
+use crate::periph::write_byte_of;
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+/// The offsets are relative to the RTC, which starts at 0x40 in the AON domain
+const RTCCFG: usize = 0x00;
+const RTCCOUNTLO: usize = 0x08;
+const RTCCOUNTHI: usize = 0x0C;
+const RTCS: usize = 0x10;
+const RTCCMP0: usize = 0x20;
+
+const CFG_SCALE: u32 = 0xF;
+const CFG_ENALWAYS: u32 = 1 << 12;
+const CFG_IP0: u32 = 1 << 28;
+
+const COUNT_MASK: u64 = 0xFFFF_FFFF_FFFF;
+
+pub struct Rtc {
+    cfg: u32,
+    count: u64,
+    cmp0: u32,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            cfg: 0,
+            count: 0,
+            cmp0: u32::MAX,
+        }
+    }
+
+    /// 32 bits of the counter, starting at bit `rtcscale`
+    fn rtcs(&self) -> u32 {
+        (self.count >> (self.cfg & CFG_SCALE)) as u32
+    }
+
+    fn ip(&self) -> bool {
+        self.rtcs() >= self.cmp0
+    }
+}
+
+impl MmapPeripheral for Rtc {
+    fn read(&self, offset: usize) -> u8 {
+        let byte = offset % 4;
+        let value = match offset - byte {
+            RTCCFG if self.ip() => self.cfg | CFG_IP0,
+            RTCCFG => self.cfg,
+            RTCCOUNTLO => self.count as u32,
+            RTCCOUNTHI => (self.count >> 32) as u32,
+            RTCS => self.rtcs(),
+            RTCCMP0 => self.cmp0,
+            _ => 0,
+        };
+        (value >> (byte * 8)) as u8
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        let byte = offset % 4;
+        match offset - byte {
+            RTCCFG => {
+                write_byte_of(&mut self.cfg, byte, value);
+                self.cfg &= CFG_ENALWAYS | CFG_SCALE;
+            }
+            RTCCOUNTLO | RTCCOUNTHI => {
+                let shift = (offset - RTCCOUNTLO) * 8;
+                self.count &= !(0xFF << shift);
+                self.count |= u64::from(value) << shift;
+                self.count &= COUNT_MASK;
+            }
+            RTCCMP0 => write_byte_of(&mut self.cmp0, byte, value),
+            _ => (),
+        }
+    }
+
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        if self.ip() {
+            Some(CFG_IP0)
+        } else {
+            None
+        }
+    }
+
+    /// `cycles` of the real time clock
+    fn tick(&mut self, cycles: u64) {
+        if self.cfg & CFG_ENALWAYS != 0 {
+            self.count = (self.count + cycles) & COUNT_MASK;
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! An SD card in SPI mode is scoped for this file.
+//! It is a high capacity card (SDHC), addressed in blocks of 512 bytes and backed by a disk image.
+//! Commands are answered after one byte, data blocks follow with their start token and CRC.
+//! The card is ready right after the first ACMD41 and does not check the CRC of commands.
+//! Without a persistent image, the written blocks are kept in memory and the image is unchanged.
+//! If the image can not be read or written, the card answers with an error token, like a failing card.
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::spi::SpiDevice;
+
+const BLOCK_SIZE: usize = 512;
+/// The capacity of an SDHC card is a multiple of 512 KiB
+const CAPACITY_UNIT: u64 = 512 * 1024;
+
+const GO_IDLE_STATE: u8 = 0;
+const SEND_IF_COND: u8 = 8;
+const SEND_CSD: u8 = 9;
+const SEND_CID: u8 = 10;
+const STOP_TRANSMISSION: u8 = 12;
+const SEND_STATUS: u8 = 13;
+const SET_BLOCKLEN: u8 = 16;
+const READ_SINGLE_BLOCK: u8 = 17;
+const READ_MULTIPLE_BLOCK: u8 = 18;
+const WRITE_BLOCK: u8 = 24;
+const WRITE_MULTIPLE_BLOCK: u8 = 25;
+const APP_CMD: u8 = 55;
+const READ_OCR: u8 = 58;
+const CRC_ON_OFF: u8 = 59;
+const SET_WR_BLK_ERASE_COUNT: u8 = 23;
+const SD_SEND_OP_COND: u8 = 41;
+
+const R1_IDLE: u8 = 1 << 0;
+const R1_ILLEGAL_COMMAND: u8 = 1 << 2;
+const R1_ADDRESS_ERROR: u8 = 1 << 5;
+const R1_PARAMETER_ERROR: u8 = 1 << 6;
+
+const TOKEN_START_BLOCK: u8 = 0xFE;
+const TOKEN_START_MULTIPLE: u8 = 0xFC;
+const TOKEN_STOP_TRAN: u8 = 0xFD;
+/// The data error token of a read beyond the capacity
+const TOKEN_OUT_OF_RANGE: u8 = 0x08;
+/// The data error token of a read that failed, e.g. because the image could not be read
+const TOKEN_ERROR: u8 = 0x01;
+const DATA_ACCEPTED: u8 = 0x05;
+const DATA_WRITE_ERROR: u8 = 0x0D;
+/// The card pulls the data line low while it is busy
+const BUSY: u8 = 0x00;
+
+/// Power up done, high capacity and 2.7 V to 3.6 V
+const OCR: u32 = 0xC0FF_8000;
+const OCR_BUSY_CCS: u32 = 0xC000_0000;
+
+/// The card identification: manufacturer, "TR", "TRSD0", revision 1.0, serial number 1, 2024-01
+const CID: [u8; 15] = [
+    0x00, b'T', b'R', b'T', b'R', b'S', b'D', b'0', 0x10, 0x00, 0x00, 0x00, 0x01, 0x01, 0x81,
+];
+
+enum Mode {
+    Command,
+    /// Blocks are sent until the host sends `STOP_TRANSMISSION`
+    ReadMultiple(u64),
+    /// The host sends the start token of the next block
+    WriteToken {
+        block: u64,
+        multiple: bool,
+    },
+    WriteData {
+        block: u64,
+        multiple: bool,
+    },
+}
+
+pub struct SdCard {
+    image: File,
+    persistent: bool,
+    blocks: u64,
+    /// The blocks written to a card without persistent image
+    written: HashMap<u64, Vec<u8>>,
+    idle: bool,
+    /// The next command is an application specific command
+    app_command: bool,
+    mode: Mode,
+    /// The bytes of the command that is received
+    command: Vec<u8>,
+    /// The bytes of the block that is written, including its CRC
+    data: Vec<u8>,
+    /// The bytes the card sends with the next frames
+    response: VecDeque<u8>,
+}
+
+impl SdCard {
+    /// Opens the disk image, its size is rounded down to a multiple of 512 KiB.
+    /// Only a persistent image is written to.
+    pub fn open(path: &Path, persistent: bool) -> anyhow::Result<Self> {
+        let image = File::options()
+            .read(true)
+            .write(persistent)
+            .open(path)
+            .context(format!(
+                "Could not open the SD card image {}",
+                path.display()
+            ))?;
+        let size = image
+            .metadata()
+            .context(format!(
+                "Could not read the SD card image {}",
+                path.display()
+            ))?
+            .len();
+        if size < CAPACITY_UNIT {
+            anyhow::bail!(
+                "The SD card image {} is smaller than the minimum of 512 KiB",
+                path.display()
+            );
+        }
+        Ok(Self {
+            image,
+            persistent,
+            blocks: size / CAPACITY_UNIT * CAPACITY_UNIT / BLOCK_SIZE as u64,
+            written: HashMap::new(),
+            idle: true,
+            app_command: false,
+            mode: Mode::Command,
+            command: Vec::with_capacity(6),
+            data: Vec::with_capacity(BLOCK_SIZE + 2),
+            response: VecDeque::new(),
+        })
+    }
+
+    fn read_block(&mut self, block: u64) -> std::io::Result<Vec<u8>> {
+        if let Some(data) = self.written.get(&block) {
+            return Ok(data.clone());
+        }
+        let mut data = vec![0; BLOCK_SIZE];
+        self.image
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))?;
+        self.image.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&mut self, block: u64, data: Vec<u8>) -> std::io::Result<()> {
+        if !self.persistent {
+            self.written.insert(block, data);
+            return Ok(());
+        }
+        self.image
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))?;
+        self.image.write_all(&data)
+    }
+
+    /// Queues a data block with its start token, after one byte of access time.
+    fn send_data(&mut self, data: &[u8]) {
+        self.response.push_back(0xFF);
+        self.response.push_back(TOKEN_START_BLOCK);
+        self.response.extend(data);
+        self.response.extend(crc16(data).to_be_bytes());
+    }
+
+    /// Queues the block or, if it could not be read, the data error token instead.
+    /// Returns true if the block was read.
+    fn send_block(&mut self, block: u64) -> bool {
+        if let Ok(data) = self.read_block(block) {
+            self.send_data(&data);
+            true
+        } else {
+            self.response.extend([0xFF, TOKEN_ERROR]);
+            false
+        }
+    }
+
+    /// The card specific data of version 2.0, the capacity is `(c_size + 1) * 512 KiB`.
+    fn csd(&self) -> [u8; 16] {
+        let c_size = (self.blocks * BLOCK_SIZE as u64 / CAPACITY_UNIT - 1) as u32;
+        let mut csd = [
+            0x40,
+            0x0E,
+            0x00,
+            0x32,
+            0x5B,
+            0x59,
+            0x00,
+            (c_size >> 16) as u8 & 0x3F,
+            (c_size >> 8) as u8,
+            c_size as u8,
+            0x7F,
+            0x80,
+            0x0A,
+            0x40,
+            0x00,
+            0x00,
+        ];
+        csd[15] = crc7(&csd[..15]) << 1 | 1;
+        csd
+    }
+
+    fn execute(&mut self) {
+        let index = self.command[0] & 0x3F;
+        let argument = u32::from_be_bytes([
+            self.command[1],
+            self.command[2],
+            self.command[3],
+            self.command[4],
+        ]);
+        self.command.clear();
+        let app_command = std::mem::take(&mut self.app_command);
+        self.response.clear();
+        // One byte passes before the response
+        self.response.push_back(0xFF);
+
+        let r1 = if self.idle { R1_IDLE } else { 0 };
+        match (app_command, index) {
+            (_, GO_IDLE_STATE) => {
+                self.idle = true;
+                self.mode = Mode::Command;
+                self.response.push_back(R1_IDLE);
+            }
+            (_, SEND_IF_COND) => {
+                self.response.push_back(r1);
+                self.response
+                    .extend([0x00, 0x00, (argument >> 8) as u8 & 0x0F, argument as u8]);
+            }
+            (_, APP_CMD) => {
+                self.app_command = true;
+                self.response.push_back(r1);
+            }
+            (true, SD_SEND_OP_COND) => {
+                self.idle = false;
+                self.response.push_back(0);
+            }
+            (_, READ_OCR) => {
+                let ocr = if self.idle { OCR & !OCR_BUSY_CCS } else { OCR };
+                self.response.push_back(r1);
+                self.response.extend(ocr.to_be_bytes());
+            }
+            (_, CRC_ON_OFF) => self.response.push_back(r1),
+            (_, STOP_TRANSMISSION) => {
+                self.mode = Mode::Command;
+                // The byte after the command is a stuff byte, the card is busy after the response
+                self.response.push_back(r1);
+                self.response.push_back(BUSY);
+            }
+            _ if self.idle => self.response.push_back(R1_IDLE | R1_ILLEGAL_COMMAND),
+            (_, SEND_CSD) => {
+                self.response.push_back(0);
+                let csd = self.csd();
+                self.send_data(&csd);
+            }
+            (_, SEND_CID) => {
+                self.response.push_back(0);
+                let mut cid = [0; 16];
+                cid[..15].copy_from_slice(&CID);
+                cid[15] = crc7(&CID) << 1 | 1;
+                self.send_data(&cid);
+            }
+            (_, SEND_STATUS) => self.response.extend([0, 0]),
+            (_, SET_BLOCKLEN) if argument as usize == BLOCK_SIZE => self.response.push_back(0),
+            (_, SET_BLOCKLEN) => self.response.push_back(R1_PARAMETER_ERROR),
+            (true, SET_WR_BLK_ERASE_COUNT) => self.response.push_back(0),
+            (_, READ_SINGLE_BLOCK | READ_MULTIPLE_BLOCK | WRITE_BLOCK | WRITE_MULTIPLE_BLOCK)
+                if u64::from(argument) >= self.blocks =>
+            {
+                self.response.push_back(R1_ADDRESS_ERROR);
+            }
+            (_, READ_SINGLE_BLOCK) => {
+                self.response.push_back(0);
+                self.send_block(u64::from(argument));
+            }
+            (_, READ_MULTIPLE_BLOCK) => {
+                self.response.push_back(0);
+                self.mode = Mode::ReadMultiple(u64::from(argument));
+            }
+            (_, WRITE_BLOCK | WRITE_MULTIPLE_BLOCK) => {
+                self.response.push_back(0);
+                self.mode = Mode::WriteToken {
+                    block: u64::from(argument),
+                    multiple: index == WRITE_MULTIPLE_BLOCK,
+                };
+            }
+            _ => self.response.push_back(r1 | R1_ILLEGAL_COMMAND),
+        }
+    }
+
+    /// Commands start with the bits `01`, the frames in between are ignored.
+    fn receive_command(&mut self, mosi: u8) {
+        if self.command.is_empty() && mosi & 0xC0 != 0x40 {
+            return;
+        }
+        self.command.push(mosi);
+        if self.command.len() == 6 {
+            self.execute();
+        }
+    }
+
+    fn receive_data(&mut self, mosi: u8, block: u64, multiple: bool) {
+        self.data.push(mosi);
+        if self.data.len() < BLOCK_SIZE + 2 {
+            return;
+        }
+        let mut data = std::mem::take(&mut self.data);
+        data.truncate(BLOCK_SIZE);
+        if self.write_block(block, data).is_err() {
+            // The host stops the transfer after a rejected block
+            self.response.extend([DATA_WRITE_ERROR, BUSY]);
+            self.mode = Mode::Command;
+            return;
+        }
+        self.response.extend([DATA_ACCEPTED, BUSY]);
+        self.mode = if multiple {
+            Mode::WriteToken {
+                block: block + 1,
+                multiple,
+            }
+        } else {
+            Mode::Command
+        };
+    }
+}
+
+impl SpiDevice for SdCard {
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let miso = self.response.pop_front().unwrap_or(0xFF);
+        match self.mode {
+            Mode::Command => self.receive_command(mosi),
+            Mode::ReadMultiple(block) => {
+                self.receive_command(mosi);
+                if let Mode::ReadMultiple(_) = self.mode {
+                    if self.response.is_empty() {
+                        if block < self.blocks {
+                            self.mode = if self.send_block(block) {
+                                Mode::ReadMultiple(block + 1)
+                            } else {
+                                Mode::Command
+                            };
+                        } else {
+                            self.response.push_back(TOKEN_OUT_OF_RANGE);
+                            self.mode = Mode::Command;
+                        }
+                    }
+                }
+            }
+            Mode::WriteToken { block, multiple } => match mosi {
+                TOKEN_START_BLOCK if !multiple => self.mode = Mode::WriteData { block, multiple },
+                TOKEN_START_MULTIPLE if multiple => self.mode = Mode::WriteData { block, multiple },
+                TOKEN_STOP_TRAN if multiple => {
+                    self.response.push_back(BUSY);
+                    self.mode = Mode::Command;
+                }
+                _ => (),
+            },
+            Mode::WriteData { block, multiple } => self.receive_data(mosi, block, multiple),
+        }
+        miso
+    }
+
+    /// A command that is not received completely is dropped.
+    fn deselect(&mut self) {
+        self.command.clear();
+    }
+}
+
+/// The CRC7 of commands and registers, with the polynomial x^7 + x^3 + 1
+fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        for bit in (0..8).rev() {
+            let feedback = ((byte >> bit) & 1) ^ (crc >> 6);
+            crc = (crc << 1) & 0x7F;
+            if feedback != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// The CRC16 of data blocks, CCITT with the polynomial x^16 + x^12 + x^5 + 1
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
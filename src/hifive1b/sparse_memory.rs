@@ -0,0 +1,101 @@
+//! The sparse memory backing RAM and flash is scoped for this file.
+//! Pages are allocated on their first write, memory that was never written reads as blank.
+
+const PAGE_SIZE: usize = 4096;
+
+type Page = Box<[u8; PAGE_SIZE]>;
+
+pub struct SparseMemory {
+    size: usize,
+    pages: Vec<Option<Page>>,
+    /// Shared by all pages that were never written
+    blank: Page,
+}
+
+impl SparseMemory {
+    /// All bytes start out as `blank`, e.g. zero for RAM or `0xFF` for erased flash.
+    pub fn new(size: usize, blank: u8) -> Self {
+        Self {
+            size,
+            pages: vec![None; size.div_ceil(PAGE_SIZE)],
+            blank: Box::new([blank; PAGE_SIZE]),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn page(&self, offset: usize) -> &[u8; PAGE_SIZE] {
+        self.pages[offset / PAGE_SIZE]
+            .as_deref()
+            .unwrap_or(&self.blank)
+    }
+
+    fn page_mut(&mut self, offset: usize) -> &mut [u8; PAGE_SIZE] {
+        let blank = &self.blank;
+        self.pages[offset / PAGE_SIZE].get_or_insert_with(|| blank.clone())
+    }
+
+    pub fn read_byte(&self, offset: usize) -> u8 {
+        self.page(offset)[offset % PAGE_SIZE]
+    }
+
+    pub fn write_byte(&mut self, offset: usize, value: u8) {
+        self.page_mut(offset)[offset % PAGE_SIZE] = value;
+    }
+
+    /// The bytes `[offset, offset + size)`, if they are within one page.
+    /// Used as fast path for wide accesses.
+    pub fn slice(&self, offset: usize, size: usize) -> Option<&[u8]> {
+        let start = offset % PAGE_SIZE;
+        if start + size > PAGE_SIZE || offset + size > self.size {
+            return None;
+        }
+        Some(&self.page(offset)[start..start + size])
+    }
+
+    pub fn slice_mut(&mut self, offset: usize, size: usize) -> Option<&mut [u8]> {
+        let start = offset % PAGE_SIZE;
+        if start + size > PAGE_SIZE || offset + size > self.size {
+            return None;
+        }
+        Some(&mut self.page_mut(offset)[start..start + size])
+    }
+
+    /// Resets the bytes `[offset, offset + size)` to blank, whole pages are released.
+    pub fn erase(&mut self, offset: usize, size: usize) {
+        let end = (offset + size).min(self.size);
+        let mut offset = offset;
+        while offset < end {
+            let start = offset % PAGE_SIZE;
+            let length = (end - offset).min(PAGE_SIZE - start);
+            if length == PAGE_SIZE {
+                self.pages[offset / PAGE_SIZE] = None;
+            } else if self.pages[offset / PAGE_SIZE].is_some() {
+                let blank = self.blank[0];
+                self.page_mut(offset)[start..start + length].fill(blank);
+            }
+            offset += length;
+        }
+    }
+
+    /// Copies `data` to `offset`, it has to fit into the memory.
+    pub fn load(&mut self, offset: usize, data: &[u8]) {
+        assert!(
+            offset + data.len() <= self.size,
+            "Can't load 0x{:X} bytes at offset 0x{offset:X} into 0x{:X} bytes",
+            data.len(),
+            self.size
+        );
+        let mut offset = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let start = offset % PAGE_SIZE;
+            let length = data.len().min(PAGE_SIZE - start);
+            self.page_mut(offset)[start..start + length].copy_from_slice(&data[..length]);
+            offset += length;
+            data = &data[length..];
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! The serial peripheral interface (SPI) controller of the Hifive1b is scoped for this file.
+//! Frames are exchanged with the attached device as soon as they are written to `txdata`.
+//! The device is connected to chip select 0, with another `csid` no device answers.
+//! The controller of SPI0 also maps the flash into memory (XIP), enabled by `fctrl`.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+const SCKDIV: usize = 0x00;
+const SCKMODE: usize = 0x04;
+const CSID: usize = 0x10;
+const CSDEF: usize = 0x14;
+const CSMODE: usize = 0x18;
+const DELAY0: usize = 0x28;
+const DELAY1: usize = 0x2C;
+const FMT: usize = 0x40;
+const TXDATA: usize = 0x48;
+const RXDATA: usize = 0x4C;
+const TXMARK: usize = 0x50;
+const RXMARK: usize = 0x54;
+const FCTRL: usize = 0x60;
+const FFMT: usize = 0x64;
+const IE: usize = 0x70;
+const IP: usize = 0x74;
+
+const CSMODE_AUTO: u32 = 0;
+const CSMODE_HOLD: u32 = 2;
+const CSMODE_OFF: u32 = 3;
+
+/// `fmt.dir`, if set the received frames are not written to the RX FIFO
+const FMT_DIR_TX: u32 = 1 << 3;
+
+const FIFO_DEPTH: usize = 8;
+
+const IP_TXWM: u32 = 1 << 0;
+const IP_RXWM: u32 = 1 << 1;
+
+/// A device on the bus, it is selected while the chip select of the controller is asserted.
+pub trait SpiDevice: Send {
+    /// The chip select got asserted, a new transaction starts.
+    fn select(&mut self) {}
+
+    /// Exchanges one frame, the device answers with a frame at the same time.
+    fn transfer(&mut self, mosi: u8) -> u8;
+
+    /// The chip select got deasserted, the transaction ends.
+    fn deselect(&mut self) {}
+
+    /// Returns the error the host ran into for the device, e.g. while writing back its image.
+    /// It ends the emulation like any other error of the memory.
+    fn take_error(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Devices can be plugged into a controller at runtime.
+impl<D: SpiDevice + ?Sized> SpiDevice for Box<D> {
+    fn select(&mut self) {
+        (**self).select();
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        (**self).transfer(mosi)
+    }
+
+    fn deselect(&mut self) {
+        (**self).deselect();
+    }
+
+    fn take_error(&mut self) -> anyhow::Result<()> {
+        (**self).take_error()
+    }
+}
+
+/// The bus without a device, the data line is pulled up.
+pub struct NoDevice;
+
+impl SpiDevice for NoDevice {
+    fn transfer(&mut self, _mosi: u8) -> u8 {
+        0xFF
+    }
+}
+
+pub struct Spi<D: SpiDevice> {
+    pub device: D,
+    sckdiv: u32,
+    sckmode: u32,
+    csid: u32,
+    csdef: u32,
+    csmode: u32,
+    delay0: u32,
+    delay1: u32,
+    fmt: u32,
+    txmark: u32,
+    rxmark: u32,
+    fctrl: u32,
+    ffmt: u32,
+    ie: u32,
+    /// The number of chip selects and if the flash is mapped into memory differ between the controllers
+    chip_selects: u32,
+    xip: bool,
+    /// The device is selected until the chip select is released
+    selected: bool,
+    /// Reading `rxdata` pops a frame
+    rx_fifo: RefCell<VecDeque<u8>>,
+}
+
+impl<D: SpiDevice> Spi<D> {
+    /// SPI0, which has one chip select and boots with the flash mapped into memory
+    pub fn new(device: D) -> Self {
+        Self::with_chip_selects(device, 1, true)
+    }
+
+    /// SPI1 has 4 chip selects and SPI2 one, both without the memory-mapped flash.
+    pub fn without_flash(device: D, chip_selects: u32) -> Self {
+        Self::with_chip_selects(device, chip_selects, false)
+    }
+
+    fn with_chip_selects(device: D, chip_selects: u32, xip: bool) -> Self {
+        let mut spi = Self {
+            device,
+            sckdiv: 0,
+            sckmode: 0,
+            csid: 0,
+            csdef: 0,
+            csmode: 0,
+            delay0: 0,
+            delay1: 0,
+            fmt: 0,
+            txmark: 0,
+            rxmark: 0,
+            fctrl: 0,
+            ffmt: 0,
+            ie: 0,
+            chip_selects,
+            xip,
+            selected: false,
+            rx_fifo: RefCell::new(VecDeque::with_capacity(FIFO_DEPTH)),
+        };
+        spi.reset();
+        spi
+    }
+
+    /// Returns the registers to their reset values and ends the transaction.
+    pub fn reset(&mut self) {
+        self.release();
+        self.rx_fifo.get_mut().clear();
+        self.sckdiv = 0x3;
+        self.sckmode = 0;
+        self.csid = 0;
+        self.csdef = (1 << self.chip_selects) - 1;
+        self.csmode = CSMODE_AUTO;
+        self.delay0 = 0x0001_0001;
+        self.delay1 = 0x0000_0001;
+        self.fmt = 0x0008_0008;
+        self.txmark = 0;
+        self.rxmark = 0;
+        if self.xip {
+            self.fctrl = 0x1;
+            self.ffmt = 0x0003_0007;
+        }
+        self.ie = 0;
+    }
+
+    /// If set, the flash can be read through the memory-mapped window.
+    pub fn xip_enabled(&self) -> bool {
+        self.fctrl & 0b1 != 0
+    }
+
+    fn ip(&self) -> u32 {
+        let mut ip = 0;
+        // The TX FIFO is always empty, as frames are sent immediately
+        if self.txmark > 0 {
+            ip |= IP_TXWM;
+        }
+        if self.rx_fifo.borrow().len() > self.rxmark as usize {
+            ip |= IP_RXWM;
+        }
+        ip
+    }
+
+    fn release(&mut self) {
+        if self.selected {
+            self.device.deselect();
+            self.selected = false;
+        }
+    }
+
+    fn transmit(&mut self, mosi: u8) {
+        let miso = if self.csmode == CSMODE_OFF || self.csid != 0 {
+            // Without a selected device, the data line is pulled up
+            0xFF
+        } else {
+            if !self.selected {
+                self.device.select();
+                self.selected = true;
+            }
+            self.device.transfer(mosi)
+        };
+        if self.fmt & FMT_DIR_TX == 0 {
+            let mut rx_fifo = self.rx_fifo.borrow_mut();
+            // Frames that do not fit anymore are dropped
+            if rx_fifo.len() < FIFO_DEPTH {
+                rx_fifo.push_back(miso);
+            }
+        }
+        if self.csmode == CSMODE_AUTO {
+            self.release();
+        }
+    }
+
+    fn register(&mut self, offset: usize) -> Option<&mut u32> {
+        match offset {
+            SCKDIV => Some(&mut self.sckdiv),
+            SCKMODE => Some(&mut self.sckmode),
+            CSID => Some(&mut self.csid),
+            CSDEF => Some(&mut self.csdef),
+            CSMODE => Some(&mut self.csmode),
+            DELAY0 => Some(&mut self.delay0),
+            DELAY1 => Some(&mut self.delay1),
+            FMT => Some(&mut self.fmt),
+            TXMARK => Some(&mut self.txmark),
+            RXMARK => Some(&mut self.rxmark),
+            FCTRL if self.xip => Some(&mut self.fctrl),
+            FFMT if self.xip => Some(&mut self.ffmt),
+            IE => Some(&mut self.ie),
+            _ => None,
+        }
+    }
+}
+
+impl<D: SpiDevice> MmapPeripheral for Spi<D> {
+    fn read(&self, offset: usize) -> u8 {
+        let byte = offset % 4;
+        let value = match offset - byte {
+            SCKDIV => self.sckdiv,
+            SCKMODE => self.sckmode,
+            CSID => self.csid,
+            CSDEF => self.csdef,
+            CSMODE => self.csmode,
+            DELAY0 => self.delay0,
+            DELAY1 => self.delay1,
+            FMT => self.fmt,
+            RXDATA => {
+                let mut rx_fifo = self.rx_fifo.borrow_mut();
+                match byte {
+                    0 => u32::from(rx_fifo.pop_front().unwrap_or(0)),
+                    3 if rx_fifo.is_empty() => 1 << 31,
+                    _ => 0,
+                }
+            }
+            TXMARK => self.txmark,
+            RXMARK => self.rxmark,
+            FCTRL => self.fctrl,
+            FFMT => self.ffmt,
+            IE => self.ie,
+            IP => self.ip(),
+            // The TX FIFO of `txdata` is never full
+            _ => 0,
+        };
+        (value >> (byte * 8)) as u8
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        let byte = offset % 4;
+        match offset - byte {
+            TXDATA if byte == 0 => self.transmit(value),
+            register => {
+                if let Some(register) = self.register(register) {
+                    *register &= !(0xFF << (byte * 8));
+                    *register |= u32::from(value) << (byte * 8);
+                }
+            }
+        }
+        // Leaving the hold mode or selecting another chip ends the transaction
+        let register = offset - byte;
+        if (register == CSMODE && self.csmode != CSMODE_HOLD)
+            || (register == CSID && self.csid != 0)
+        {
+            self.release();
+        }
+    }
+
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        let pending = self.ip() & self.ie;
+        if pending == 0 {
+            None
+        } else {
+            Some(pending)
+        }
+    }
+}
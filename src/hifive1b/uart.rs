@@ -1,192 +1,311 @@
+//! The UART of the Hifive1b is scoped for this file.
+//! Both directions have a FIFO of 8 frames. The watermark interrupts are pending as long as
+//! the TX FIFO holds less than `txcnt` frames or the RX FIFO more than `rxcnt` frames.
+//! Without pacing, the frames are exchanged with the backend as fast as possible.
+//! With pacing, every frame takes as long as it would with the baud rate set by `div`.
+//! In lockstep, the UART of the reference replays the frames the checked UART received.
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::sync::mpsc;
+
 use crate::events;
+use crate::periph::write_byte_of;
 use crate::periph::InterruptReason;
 use crate::periph::MmapPeripheral;
 use crate::utils::IOChannel;
 use crate::utils::PeekableChannel;
-use std::sync::mpsc;
 
-#[allow(clippy::struct_excessive_bools)]
+const TXDATA: usize = 0x00;
+const RXDATA: usize = 0x04;
+const TXCTRL: usize = 0x08;
+const RXCTRL: usize = 0x0C;
+const IE: usize = 0x10;
+const IP: usize = 0x14;
+const DIV: usize = 0x18;
+
+const FIFO_DEPTH: usize = 8;
+
+const CTRL_EN: u32 = 1 << 0;
+const TXCTRL_NSTOP: u32 = 1 << 1;
+/// The watermark `txcnt` or `rxcnt` in the control registers
+const CTRL_CNT: u32 = 0b111 << 16;
+
+const IP_TXWM: u32 = 1 << 0;
+const IP_RXWM: u32 = 1 << 1;
+
+/// `txdata.full` and `rxdata.empty`
+const DATA_FLAG: u32 = 1 << 31;
+
+/// The reset value of `div` is not specified, this is 115200 baud with a 16 MHz clock
+const DIV_RESET: u32 = 138;
+const DIV_MASK: u32 = 0xFFFF;
+
+/// Without pacing, the backend is checked for received frames at this interval
+const POLL_INTERVAL: u64 = 1024;
+
+/// Where the received frames come from. Every frame is tagged with the number of the attempt
+/// to receive that took it, the reference receives it in the same attempt as the checked UART.
+enum Input {
+    Backend,
+    /// The frames are taken from the backend and kept for the reference
+    Recorded(Vec<(u64, u8)>),
+    Replayed(VecDeque<(u64, u8)>),
+}
+
 pub struct Uart {
-    tx_fifo_full: bool,
-    tx_enable: bool,
-    rx_enable: bool,
-    txcnt: u8,
-    rxcnt: u8,
-    txwm_ie: bool, // watermark interrupt enable
-    rxwm_ie: bool,
-    txwm_ip: bool, // watermark interrupt pending
-    rxwm_ip: bool,
+    txctrl: u32,
+    rxctrl: u32,
+    ie: u32,
+    div: u32,
+    tx_fifo: VecDeque<u8>,
+    /// Reading `rxdata` pops a frame
+    rx_fifo: RefCell<VecDeque<u8>>,
+    /// If set, the frames take as long as with the configured baud rate
+    pub pacing: bool,
+    /// Core cycles spent on the frame in transmission and since the last frame was received
+    tx_cycles: u64,
+    rx_cycles: u64,
     backend: PeekableChannel<u8>,
+    input: RefCell<Input>,
+    /// The attempts to receive so far, they are made at the same points by the reference
+    receive_attempts: Cell<u64>,
 }
+
 impl Uart {
     pub fn default(interrupts: mpsc::Sender<events::Event>) -> (IOChannel, Self) {
         let (iochannel, channel) = PeekableChannel::channel(interrupts);
         let new_uart = Uart {
-            tx_fifo_full: false,
-            tx_enable: false,
-            rx_enable: false,
-            txcnt: 0,
-            rxcnt: 0,
-            txwm_ie: false, // watermark interrupt enable
-            rxwm_ie: false,
-            txwm_ip: false, // watermark interrupt pending
-            rxwm_ip: false,
+            txctrl: 0,
+            rxctrl: 0,
+            ie: 0,
+            div: DIV_RESET,
+            tx_fifo: VecDeque::with_capacity(FIFO_DEPTH),
+            rx_fifo: RefCell::new(VecDeque::with_capacity(FIFO_DEPTH)),
+            pacing: false,
+            tx_cycles: 0,
+            rx_cycles: 0,
             backend: channel,
+            input: RefCell::new(Input::Backend),
+            receive_attempts: Cell::new(0),
         };
         (iochannel, new_uart)
     }
-}
 
-impl Uart {
-    #[allow(clippy::match_same_arms)]
-    fn read_uart(&self, address_offset: usize) -> u8 {
-        match address_offset {
-            0x00..=0x02 => 0, // txdata Transmit data register
-            0x03 => {
-                // .31 txdata FIFO full bit
-                if self.tx_fifo_full {
-                    return 0b1000_0000;
+    /// Returns the registers to their reset values, the backend stays connected.
+    /// The frames in the FIFOs are lost.
+    pub fn reset(&mut self) {
+        self.txctrl = 0;
+        self.rxctrl = 0;
+        self.ie = 0;
+        self.div = DIV_RESET;
+        self.tx_fifo.clear();
+        self.rx_fifo.get_mut().clear();
+        self.tx_cycles = 0;
+        self.rx_cycles = 0;
+    }
+
+    /// Keeps the frames taken from the backend, until they are taken for the reference.
+    pub fn record_input(&mut self) {
+        *self.input.get_mut() = Input::Recorded(Vec::new());
+    }
+
+    /// The frames received since the last call, with the attempt to receive that took them.
+    pub fn take_input(&mut self) -> Vec<(u64, u8)> {
+        match self.input.get_mut() {
+            Input::Recorded(frames) => std::mem::take(frames),
+            Input::Backend | Input::Replayed(_) => Vec::new(),
+        }
+    }
+
+    /// Receives the `frames` another UART took, instead of the ones of the backend.
+    pub fn replay_input(&mut self, frames: Vec<(u64, u8)>) {
+        match self.input.get_mut() {
+            Input::Replayed(replayed) => replayed.extend(frames),
+            input => *input = Input::Replayed(frames.into()),
+        }
+    }
+
+    fn tx_enabled(&self) -> bool {
+        self.txctrl & CTRL_EN != 0
+    }
+
+    fn rx_enabled(&self) -> bool {
+        self.rxctrl & CTRL_EN != 0
+    }
+
+    /// The core cycles one frame takes: a start bit, eight data bits and one or two stop bits.
+    /// The bit rate is the clock divided by `div + 1`, the UART runs with the core clock.
+    fn frame_cycles(&self) -> u64 {
+        let bits = if self.txctrl & TXCTRL_NSTOP == 0 {
+            10
+        } else {
+            11
+        };
+        u64::from(self.div + 1) * bits
+    }
+
+    fn ip(&self) -> u32 {
+        let mut ip = 0;
+        if self.tx_fifo.len() < ((self.txctrl & CTRL_CNT) >> 16) as usize {
+            ip |= IP_TXWM;
+        }
+        if self.rx_fifo.borrow().len() > ((self.rxctrl & CTRL_CNT) >> 16) as usize {
+            ip |= IP_RXWM;
+        }
+        ip
+    }
+
+    /// Without pacing, the frames are sent as soon as the transmitter is enabled.
+    fn transmit(&mut self) {
+        if self.pacing || !self.tx_enabled() {
+            return;
+        }
+        for data in self.tx_fifo.drain(..) {
+            self.backend.write_cb(data);
+        }
+    }
+
+    /// Moves up to `limit` received frames from the backend into the RX FIFO.
+    /// A frame that does not fit anymore is lost, like on an overrun.
+    fn receive(&self, limit: usize) {
+        if !self.rx_enabled() {
+            return;
+        }
+        let attempt = self.receive_attempts.get();
+        self.receive_attempts.set(attempt + 1);
+        let mut input = self.input.borrow_mut();
+        let mut rx_fifo = self.rx_fifo.borrow_mut();
+        for _ in 0..limit {
+            let data = match &mut *input {
+                Input::Backend => self.backend.read_cb(),
+                Input::Recorded(frames) => self
+                    .backend
+                    .read_cb()
+                    .inspect(|&data| frames.push((attempt, data))),
+                Input::Replayed(frames) => {
+                    if frames.front().is_some_and(|&(taken, _)| taken == attempt) {
+                        frames.pop_front().map(|(_, data)| data)
+                    } else {
+                        None
+                    }
                 }
-                0
+            };
+            let Some(data) = data else {
+                return;
+            };
+            if rx_fifo.len() < FIFO_DEPTH {
+                rx_fifo.push_back(data);
             }
-            0x04 => {
-                // ...0x07 rxdata Receive data register
-                if let Some(data) = self.backend.read_cb() {
-                    return data;
+        }
+    }
+
+    /// Without pacing, the RX FIFO is filled as soon as the firmware looks at it.
+    fn receive_unpaced(&self) {
+        if !self.pacing {
+            let free = FIFO_DEPTH - self.rx_fifo.borrow().len();
+            self.receive(free);
+        }
+    }
+}
+
+impl MmapPeripheral for Uart {
+    fn read(&self, offset: usize) -> u8 {
+        let byte = offset % 4;
+        let value = match offset - byte {
+            TXDATA if self.tx_fifo.len() == FIFO_DEPTH => DATA_FLAG,
+            RXDATA => {
+                if byte == 3 {
+                    self.receive_unpaced();
                 }
-                0
-            }
-            0x05..=0x06 => 0,
-            0x07 => {
-                // .31 rxdata FIFO empty bit
-                if !self.backend.has_data() {
-                    return 0b1000_0000;
+                let mut rx_fifo = self.rx_fifo.borrow_mut();
+                match byte {
+                    0 => u32::from(rx_fifo.pop_front().unwrap_or(0)),
+                    3 if rx_fifo.is_empty() => DATA_FLAG,
+                    _ => 0,
                 }
-                0
             }
-            0x08 => {
-                // ...0x0B txctrl Transmit control register
-                let mut ret = 0x00;
-                if self.tx_enable {
-                    ret |= 0x01; // tx enable
-                }
-                // ret |= 0x02; // num stopbits: Hardcoded always 0
-                ret
+            TXCTRL => self.txctrl,
+            RXCTRL => self.rxctrl,
+            IE => self.ie,
+            IP => {
+                self.receive_unpaced();
+                self.ip()
             }
-            0x09 => 0,
-            0x0A => {
-                // ...0x0B txctrl Transmit control register
-                // Bit 16..17..18 are for txcnt / watermark
-                self.txcnt & 0b111
+            DIV => self.div,
+            _ => 0,
+        };
+        (value >> (byte * 8)) as u8
+    }
+
+    /// A frame written while the TX FIFO is full is lost.
+    fn write(&mut self, offset: usize, value: u8) {
+        let byte = offset % 4;
+        match offset - byte {
+            TXDATA if byte == 0 && self.tx_fifo.len() < FIFO_DEPTH => {
+                self.tx_fifo.push_back(value);
             }
-            0x0B => 0,
-            0x0C => {
-                // ...0x0F rxctrl Receive control register
-                // First bit is rx en
-                if self.rx_enable {
-                    return 1;
-                }
-                0
+            TXCTRL => {
+                write_byte_of(&mut self.txctrl, byte, value);
+                self.txctrl &= CTRL_CNT | TXCTRL_NSTOP | CTRL_EN;
             }
-            0x0D => 0,
-            0x0E => {
-                // Bit 16..17..18 are for rxcnt
-                self.rxcnt & 0b111
+            RXCTRL => {
+                write_byte_of(&mut self.rxctrl, byte, value);
+                self.rxctrl &= CTRL_CNT | CTRL_EN;
             }
-            0x0F => 0,
-            0x10 => {
-                // ie UART interrupt enable
-                let mut ret = 0x00;
-                if self.txwm_ie {
-                    ret |= 0x01; // txwm Transmit watermark interrupt enable
-                }
-                if self.rxwm_ie {
-                    ret |= 0x02; // rxwm Receive watermark interrupt enable
-                }
-                ret
+            IE => {
+                write_byte_of(&mut self.ie, byte, value);
+                self.ie &= IP_TXWM | IP_RXWM;
             }
-            0x11..=0x13 => 0,
-            0x14 => {
-                // ip UART interrupt pending
-                let mut ret = 0x00;
-                if self.txwm_ip {
-                    ret |= 0x01; // txwm Transmit watermark interrupt pending
-                }
-                if self.rxwm_ip {
-                    ret |= 0x02; // rxwm Receive watermark interrupt pending
-                }
-                ret
+            DIV => {
+                write_byte_of(&mut self.div, byte, value);
+                self.div &= DIV_MASK;
             }
-            0x15..=0x17 => 0,
-            0x18 => 0, // TODO: div Baud rate divisor
-            _ => panic!("UART read-access out of bounds: {address_offset:}"),
+            _ => (),
         }
+        self.transmit();
     }
 
-    #[allow(clippy::match_same_arms)]
-    fn write_uart(&mut self, address_offset: usize, value: u8) {
-        match address_offset {
-            0x00 => {
-                // txdata Transmit data register
-                if self.tx_enable {
-                    self.backend.write_cb(value);
-                }
-            }
-            0x01..=0x07 => (),
-            0x08 => {
-                // ...0x0B txctrl Transmit control register
-                if (value & 0b1) != 0 {
-                    self.tx_enable = true;
-                }
-                if (value & 0b10) != 0 {
-                    // stopbits ignored
-                }
-            }
-            0x09 => (),
-            0x0A => {
-                // ...0x0B txctrl Transmit control register
-                // Bit 16..17..18 are for txcnt / watermark
-                self.txcnt = value & 0b111;
-            }
-            0x0B => (),
-            0x0C => {
-                // ...0x0F rxctrl Receive control register
-                // First bit is rx en
-                if (value & 0b1) != 0 {
-                    self.rx_enable = true;
-                }
-            }
-            0x0D => (),
-            0x0E => {
-                // Bit 16..17..18 are for rxcnt
-                self.rxcnt = value & 0b111;
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        let pending = self.ip() & self.ie;
+        if pending == 0 {
+            None
+        } else {
+            Some(pending)
+        }
+    }
+
+    /// With pacing, the frame at the head of the TX FIFO leaves it once it is sent completely.
+    /// Frames are received at the same rate.
+    fn tick(&mut self, cycles: u64) {
+        if self.pacing && self.tx_enabled() && !self.tx_fifo.is_empty() {
+            self.tx_cycles += cycles;
+            let frame_cycles = self.frame_cycles();
+            while self.tx_cycles >= frame_cycles {
+                let Some(data) = self.tx_fifo.pop_front() else {
+                    break;
+                };
+                self.backend.write_cb(data);
+                self.tx_cycles -= frame_cycles;
             }
-            0x0F => (),
-            0x10 => {
-                // ie UART interrupt enable
-                self.txwm_ie = (value & 0b1) != 0;
-                self.rxwm_ie = (value & 0b10) != 0;
+            if self.tx_fifo.is_empty() {
+                self.tx_cycles = 0;
             }
-            0x11..=0x17 => (),
-            0x18 => (),        // div Baud rate divisor
-            0x19..=0x1C => (), // FIXME: Needed to ger RIOT running
-            _ => panic!("UART write-access out of bounds: {address_offset:}"),
         }
-    }
-}
 
-impl MmapPeripheral for Uart {
-    fn read(&self, offset: usize) -> u8 {
-        self.read_uart(offset)
-    }
-    fn write(&mut self, offset: usize, value: u8) {
-        self.write_uart(offset, value);
-    }
-    fn pending_interrupt(&self) -> Option<InterruptReason> {
-        if self.rx_enable && self.rxwm_ie && self.backend.has_data() {
-            Some(0)
+        self.rx_cycles += cycles;
+        let interval = if self.pacing {
+            self.frame_cycles()
         } else {
-            None
+            POLL_INTERVAL
+        };
+        if self.rx_cycles >= interval {
+            let frames = self.rx_cycles / interval;
+            self.rx_cycles %= interval;
+            if self.pacing {
+                self.receive(usize::try_from(frames).unwrap_or(usize::MAX));
+            } else {
+                self.receive_unpaced();
+            }
         }
     }
 }
@@ -0,0 +1,155 @@
+//! The watchdog timer (WDT) of the always-on (AON) domain of the Hifive1b is scoped for this file.
+//! Its counter runs with the real time clock, once the scaled count `wdogs` reaches `wdogcmp0`
+//! it raises its interrupt and, if enabled, resets the system.
+//! The registers are locked: every write has to be preceded by writing the key to `wdogkey`,
+//! the write locks them again. Writing the food to `wdogfeed` restarts the counter.
+use crate::periph::InterruptReason;
+use crate::periph::MmapPeripheral;
+
+const WDOGCFG: usize = 0x00;
+const WDOGCOUNT: usize = 0x08;
+const WDOGS: usize = 0x10;
+const WDOGFEED: usize = 0x18;
+const WDOGKEY: usize = 0x1C;
+const WDOGCMP0: usize = 0x20;
+
+const KEY: u32 = 0x0051_F15E;
+const FOOD: u32 = 0x0D09_F00D;
+
+const CFG_SCALE: u32 = 0xF;
+const CFG_RSTEN: u32 = 1 << 8;
+const CFG_ZEROCMP: u32 = 1 << 9;
+const CFG_ENALWAYS: u32 = 1 << 12;
+const CFG_COREAWAKE: u32 = 1 << 13;
+const CFG_IP0: u32 = 1 << 28;
+const CFG_MASK: u32 = CFG_SCALE | CFG_RSTEN | CFG_ZEROCMP | CFG_ENALWAYS | CFG_COREAWAKE | CFG_IP0;
+
+/// The counter is 31 bits wide, `wdogs` shows 16 bits of it
+const COUNT_MASK: u64 = 0x7FFF_FFFF;
+const CMP_MASK: u32 = 0xFFFF;
+
+pub struct Watchdog {
+    cfg: u32,
+    count: u32,
+    cmp0: u32,
+    unlocked: bool,
+    /// The value written to `wdogfeed` or `wdogkey`, it takes effect with its last byte
+    written: u32,
+    reset_requested: bool,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self {
+            cfg: 0,
+            count: 0,
+            cmp0: CMP_MASK,
+            unlocked: false,
+            written: 0,
+            reset_requested: false,
+        }
+    }
+
+    /// Returns true once, after the counter reached the compare value with `wdogrsten` set.
+    pub fn take_reset_request(&mut self) -> bool {
+        std::mem::take(&mut self.reset_requested)
+    }
+
+    /// The core never sleeps, so it is always awake
+    fn running(&self) -> bool {
+        self.cfg & (CFG_ENALWAYS | CFG_COREAWAKE) != 0
+    }
+
+    fn scale(&self) -> u32 {
+        self.cfg & CFG_SCALE
+    }
+
+    fn wdogs(&self) -> u32 {
+        (self.count >> self.scale()) & CMP_MASK
+    }
+
+    /// Stores one byte of a register, words are written from the least significant byte up.
+    /// A register is only written while unlocked, its last byte locks the registers again.
+    fn write_locked(&mut self, register: usize, byte: usize, value: u8) {
+        if !self.unlocked {
+            return;
+        }
+        let target = match register {
+            WDOGCFG => &mut self.cfg,
+            WDOGCOUNT => &mut self.count,
+            WDOGCMP0 => &mut self.cmp0,
+            _ => &mut self.written,
+        };
+        *target &= !(0xFF << (byte * 8));
+        *target |= u32::from(value) << (byte * 8);
+        self.cfg &= CFG_MASK;
+        self.count &= COUNT_MASK as u32;
+        self.cmp0 &= CMP_MASK;
+        if byte == 3 {
+            if register == WDOGFEED && self.written == FOOD {
+                self.count = 0;
+            }
+            self.unlocked = false;
+        }
+    }
+}
+
+impl MmapPeripheral for Watchdog {
+    fn read(&self, offset: usize) -> u8 {
+        let byte = offset % 4;
+        let value = match offset - byte {
+            WDOGCFG => self.cfg,
+            WDOGCOUNT => self.count,
+            WDOGS => self.wdogs(),
+            WDOGKEY => u32::from(self.unlocked),
+            WDOGCMP0 => self.cmp0,
+            _ => 0,
+        };
+        (value >> (byte * 8)) as u8
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        let byte = offset % 4;
+        match offset - byte {
+            WDOGKEY => {
+                self.written &= !(0xFF << (byte * 8));
+                self.written |= u32::from(value) << (byte * 8);
+                if byte == 3 {
+                    self.unlocked = self.written == KEY;
+                }
+            }
+            register @ (WDOGCFG | WDOGCOUNT | WDOGFEED | WDOGCMP0) => {
+                self.write_locked(register, byte, value);
+            }
+            _ => (),
+        }
+    }
+
+    fn pending_interrupt(&self) -> Option<InterruptReason> {
+        if self.cfg & CFG_IP0 == 0 {
+            None
+        } else {
+            Some(CFG_IP0)
+        }
+    }
+
+    /// `cycles` of the real time clock.
+    /// If `wdogzerocmp` is set, the counter restarts from zero once it reaches the compare value.
+    fn tick(&mut self, cycles: u64) {
+        if !self.running() {
+            return;
+        }
+        let threshold = u64::from(self.cmp0) << self.scale();
+        let mut count = u64::from(self.count) + cycles;
+        if count >= threshold {
+            self.cfg |= CFG_IP0;
+            if self.cfg & CFG_RSTEN != 0 {
+                self.reset_requested = true;
+            }
+            if self.cfg & CFG_ZEROCMP != 0 {
+                count = (count - threshold).checked_rem(threshold).unwrap_or(0);
+            }
+        }
+        self.count = (count & COUNT_MASK) as u32;
+    }
+}
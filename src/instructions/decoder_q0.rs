@@ -1,3 +1,4 @@
+use crate::cpu::Xlen;
 use crate::instructions::{bit_from_to, Immediate, Instruction, RDindex, RS1index};
 
 #[derive(Debug, PartialEq)]
@@ -29,6 +30,11 @@ fn get_imm(inst: u32) -> Immediate {
     ((((inst >> 10) & 0b111) << 3) + bit_from_to(inst, 5, 6) + bit_from_to(inst, 6, 2)) as i32
 }
 
+/* Valid for CL and CS of doublewords */
+fn get_dimm(inst: u32) -> Immediate {
+    ((((inst >> 10) & 0b111) << 3) + bit_from_to(inst, 5, 6) + bit_from_to(inst, 6, 7)) as i32
+}
+
 fn get_opcode(instruction: u32) -> anyhow::Result<OpCode> {
     match instruction >> 13 {
         0b000 => Ok(OpCode::ADDI4SPN),
@@ -43,7 +49,7 @@ fn get_opcode(instruction: u32) -> anyhow::Result<OpCode> {
     }
 }
 
-pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
+pub fn decode(instruction: u32, xlen: Xlen) -> anyhow::Result<Instruction> {
     if instruction == 0 {
         return Err(anyhow::anyhow!("Instruction is zero"));
     }
@@ -54,10 +60,29 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
     match op {
         OpCode::FLD => Err(anyhow::anyhow!("C.FLD not implemented")),
         OpCode::LW => Ok(Instruction::CLW(rdindex, rs1index, imm)),
+        OpCode::FLW if xlen == Xlen::Rv64 => {
+            Ok(Instruction::CLD(rdindex, rs1index, get_dimm(instruction)))
+        }
         OpCode::FLW => Err(anyhow::anyhow!("C.FLW not implemented")),
-        OpCode::RESERVED => Err(anyhow::anyhow!("Reserved instruction")),
+        OpCode::RESERVED => {
+            /* Zcb uses the reserved space for byte and halfword loads and stores */
+            let uimm =
+                (bit_from_to(instruction, 5, 1) + bit_from_to(instruction, 6, 0)) as Immediate;
+            let halfword_uimm = bit_from_to(instruction, 5, 1) as Immediate;
+            match ((instruction >> 10) & 0b111, (instruction >> 6) & 0b1) {
+                (0b000, _) => Ok(Instruction::CLBU(rdindex, rs1index, uimm)),
+                (0b001, 0) => Ok(Instruction::CLHU(rdindex, rs1index, halfword_uimm)),
+                (0b001, 1) => Ok(Instruction::CLH(rdindex, rs1index, halfword_uimm)),
+                (0b010, _) => Ok(Instruction::CSB(rs1index, rdindex, uimm)),
+                (0b011, 0) => Ok(Instruction::CSH(rs1index, rdindex, halfword_uimm)),
+                _ => Err(anyhow::anyhow!("Reserved instruction")),
+            }
+        }
         OpCode::FSD => Err(anyhow::anyhow!("C.FSD not implemented")),
         OpCode::SW => Ok(Instruction::CSW(rs1index, rdindex, imm)),
+        OpCode::FSW if xlen == Xlen::Rv64 => {
+            Ok(Instruction::CSD(rs1index, rdindex, get_dimm(instruction)))
+        }
         OpCode::FSW => Err(anyhow::anyhow!("C.FSW not implemented")),
         OpCode::ADDI4SPN => {
             let imm = (bit_from_to(instruction, 5, 3)
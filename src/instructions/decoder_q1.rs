@@ -1,3 +1,4 @@
+use crate::cpu::Xlen;
 use crate::instructions::{bit_from_to, sign_extend, Immediate, Instruction, RDindex, RS1index};
 
 #[derive(Debug, PartialEq)]
@@ -81,7 +82,7 @@ fn get_opcode(instruction: u32) -> anyhow::Result<OpCode> {
     }
 }
 
-pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
+pub fn decode(instruction: u32, xlen: Xlen) -> anyhow::Result<Instruction> {
     let op = get_opcode(instruction)?;
     match op {
         OpCode::ADDI => {
@@ -92,6 +93,12 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let imm = sign_extend(get_imm(instruction), 6) as Immediate;
             Ok(Instruction::CADDI(rdindex, imm))
         }
+        OpCode::JAL if xlen == Xlen::Rv64 => {
+            /* RV64 reuses the encoding of C.JAL */
+            let rdindex = ((instruction >> 7) & 0b1_1111) as RDindex;
+            let imm = sign_extend(get_imm(instruction), 6) as Immediate;
+            Ok(Instruction::CADDIW(rdindex, imm))
+        }
         OpCode::JAL => {
             let imm = get_jimm(instruction);
             Ok(Instruction::CJAL(imm))
@@ -104,7 +111,10 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
         OpCode::LUI => {
             let rdindex = ((instruction >> 7) & 0b1_1111) as RDindex;
             assert!(rdindex != 0);
-            if rdindex == 2 {
+            if get_imm(instruction) == 0 && rdindex % 2 == 1 && rdindex < 16 {
+                /* Zcmop reuses C.LUI with a zero immediate, the register names the operation */
+                Ok(Instruction::CMOP(rdindex as u32))
+            } else if rdindex == 2 {
                 Ok(Instruction::CADDI16SP(2, get_addi16spimm(instruction)))
             } else {
                 Ok(Instruction::CLUI(
@@ -125,6 +135,25 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
                     rs1index,
                     sign_extend(imm, 6) as Immediate,
                 )),
+                0b11 if (instruction >> 12) & 1 == 1 => {
+                    let rs2index = (((instruction >> 2) & 0b111) + 8) as RS1index;
+                    match opt56 {
+                        0b00 if xlen == Xlen::Rv64 => Ok(Instruction::CSUBW(rs1index, rs2index)),
+                        0b01 if xlen == Xlen::Rv64 => Ok(Instruction::CADDW(rs1index, rs2index)),
+                        /* Zcb */
+                        0b10 => Ok(Instruction::CMUL(rs1index, rs2index)),
+                        0b11 => match (instruction >> 2) & 0b111 {
+                            0b000 => Ok(Instruction::CZEXTB(rs1index)),
+                            0b001 => Ok(Instruction::CSEXTB(rs1index)),
+                            0b010 => Ok(Instruction::CZEXTH(rs1index)),
+                            0b011 => Ok(Instruction::CSEXTH(rs1index)),
+                            0b100 if xlen == Xlen::Rv64 => Ok(Instruction::CZEXTW(rs1index)),
+                            0b101 => Ok(Instruction::CNOT(rs1index)),
+                            _ => Err(anyhow::anyhow!("Reserved instruction")),
+                        },
+                        _ => Err(anyhow::anyhow!("Reserved instruction")),
+                    }
+                }
                 0b11 => {
                     let rs2index = (((instruction >> 2) & 0b111) + 8) as RS1index;
                     match opt56 {
@@ -1,3 +1,4 @@
+use crate::cpu::Xlen;
 use crate::instructions::{bit_from_to, Immediate, Instruction, RDindex, RS1index};
 
 #[derive(Debug, PartialEq)]
@@ -52,11 +53,29 @@ fn get_css_offset(inst: u32) -> Immediate {
         + bit_from_to(inst, 12, 5)) as Immediate
 }
 
+fn get_ci_doubleword_offset(inst: u32) -> Immediate {
+    (bit_from_to(inst, 2, 6)
+        + bit_from_to(inst, 3, 7)
+        + bit_from_to(inst, 4, 8)
+        + bit_from_to(inst, 5, 3)
+        + bit_from_to(inst, 6, 4)
+        + bit_from_to(inst, 12, 5)) as Immediate
+}
+
+fn get_css_doubleword_offset(inst: u32) -> Immediate {
+    (bit_from_to(inst, 7, 6)
+        + bit_from_to(inst, 8, 7)
+        + bit_from_to(inst, 9, 8)
+        + bit_from_to(inst, 10, 3)
+        + bit_from_to(inst, 11, 4)
+        + bit_from_to(inst, 12, 5)) as Immediate
+}
+
 fn get_shamt(inst: u32) -> i32 {
     (((inst >> 2) & 0b1_1111) + bit_from_to(inst, 12, 5)) as i32
 }
 
-pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
+pub fn decode(instruction: u32, xlen: Xlen) -> anyhow::Result<Instruction> {
     let op = get_opcode(instruction)?;
     match op {
         OpCode::SLLI => {
@@ -70,6 +89,11 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let imm = get_ci_offset(instruction);
             Ok(Instruction::CLWSP(rdindex, imm))
         }
+        OpCode::FLWSP if xlen == Xlen::Rv64 => {
+            let rdindex = get_rd(instruction);
+            let imm = get_ci_doubleword_offset(instruction);
+            Ok(Instruction::CLDSP(rdindex, imm))
+        }
         OpCode::FLWSP => Err(anyhow::anyhow!("FLWSP not implemented")),
         OpCode::MISC => {
             let rdindex = get_rd(instruction);
@@ -93,6 +117,11 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let imm = get_css_offset(instruction);
             Ok(Instruction::CSWSP(rsindex, imm))
         }
+        OpCode::FSWSP if xlen == Xlen::Rv64 => {
+            let rsindex = get_rs(instruction);
+            let imm = get_css_doubleword_offset(instruction);
+            Ok(Instruction::CSDSP(rsindex, imm))
+        }
         OpCode::FSWSP => Err(anyhow::anyhow!("FSWSP not implemented")),
     }
 }
@@ -1,3 +1,4 @@
+use crate::cpu::Xlen;
 use crate::instructions::{sign_extend, Immediate, Instruction, RDindex, RS1index, RS2index};
 
 type Funct3 = u32;
@@ -157,8 +158,17 @@ fn get_opcode(instruction: u32) -> anyhow::Result<OpCode> {
     }
 }
 
+/// Instructions that only exist in RV64
+fn rv64_only(xlen: Xlen, instruction: Instruction) -> anyhow::Result<Instruction> {
+    if xlen == Xlen::Rv64 {
+        Ok(instruction)
+    } else {
+        Err(anyhow::anyhow!("{instruction:?} is only valid in RV64"))
+    }
+}
+
 #[allow(clippy::too_many_lines)]
-pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
+pub fn decode(instruction: u32, xlen: Xlen) -> anyhow::Result<Instruction> {
     let op = get_opcode(instruction)?;
 
     match op {
@@ -171,8 +181,10 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
                 0b000 => Ok(Instruction::LB(rd_index, rs1, i_imm)),
                 0b001 => Ok(Instruction::LH(rd_index, rs1, i_imm)),
                 0b010 => Ok(Instruction::LW(rd_index, rs1, i_imm)),
+                0b011 => rv64_only(xlen, Instruction::LD(rd_index, rs1, i_imm)),
                 0b100 => Ok(Instruction::LBU(rd_index, rs1, i_imm)),
                 0b101 => Ok(Instruction::LHU(rd_index, rs1, i_imm)),
+                0b110 => rv64_only(xlen, Instruction::LWU(rd_index, rs1, i_imm)),
                 _ => Err(anyhow::anyhow!("Invalid funct3 I-Type")),
             }
         }
@@ -182,7 +194,11 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let rd_index: RDindex = rd(instruction);
             let rs1: RS1index = rs1(instruction);
             let i_imm: Immediate = immediate_i(instruction);
-            Ok(Instruction::FENCE(rd_index, rs1, i_imm))
+            match funct3(instruction) {
+                0b000 => Ok(Instruction::FENCE(rd_index, rs1, i_imm)),
+                0b001 => Ok(Instruction::FENCEI(rd_index, rs1, i_imm)),
+                _ => Err(anyhow::anyhow!("Invalid funct3 MISCMEM")),
+            }
         }
         OpCode::OPIMM => {
             /* All OPIMM are I-Type instructions */
@@ -213,7 +229,20 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let u_imm: Immediate = immediate_u(instruction);
             Ok(Instruction::AUIPC(rd_index, u_imm))
         }
-        OpCode::OPIMM32 => Err(anyhow::anyhow!("Not implemented: OPIMM32")),
+        OpCode::OPIMM32 => {
+            /* I-Type instructions operating on the lower 32 bits */
+            let rd_index: RDindex = rd(instruction);
+            let rs1: RS1index = rs1(instruction);
+            let i_imm: Immediate = immediate_i(instruction);
+            let inst = match funct3(instruction) {
+                0b000 => Instruction::ADDIW(rd_index, rs1, i_imm),
+                0b001 => Instruction::SLLIW(rd_index, rs1, i_imm),
+                0b101 if funct7(instruction) == 0 => Instruction::SRLIW(rd_index, rs1, i_imm),
+                0b101 => Instruction::SRAIW(rd_index, rs1, i_imm),
+                _ => return Err(anyhow::anyhow!("Invalid funct3 OPIMM32")),
+            };
+            rv64_only(xlen, inst)
+        }
         OpCode::LEN48 => Err(anyhow::anyhow!("Not implemented: LEN48")),
         OpCode::STORE => {
             /* STOREs are S-Type */
@@ -224,6 +253,7 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
                 0b000 => Ok(Instruction::SB(rs1, rs2, s_imm)),
                 0b001 => Ok(Instruction::SH(rs1, rs2, s_imm)),
                 0b010 => Ok(Instruction::SW(rs1, rs2, s_imm)),
+                0b011 => rv64_only(xlen, Instruction::SD(rs1, rs2, s_imm)),
                 _ => Err(anyhow::anyhow!("Invalid funct3 S-Type")),
             }
         }
@@ -233,6 +263,23 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let rd: RDindex = rd(instruction);
             let rs1: RS1index = rs1(instruction);
             let rs2: RS2index = rs2(instruction);
+            if funct3(instruction) == 0b011 {
+                let inst = match funct5(instruction) {
+                    0b00010 => Instruction::LRD(rd, rs1),
+                    0b00011 => Instruction::SCD(rd, rs1, rs2),
+                    0b00001 => Instruction::AMOSWAPD(rd, rs1, rs2),
+                    0b00000 => Instruction::AMOADDD(rd, rs1, rs2),
+                    0b00100 => Instruction::AMOXORD(rd, rs1, rs2),
+                    0b01100 => Instruction::AMOANDD(rd, rs1, rs2),
+                    0b01000 => Instruction::AMOORD(rd, rs1, rs2),
+                    0b10000 => Instruction::AMOMIND(rd, rs1, rs2),
+                    0b10100 => Instruction::AMOMAXD(rd, rs1, rs2),
+                    0b11000 => Instruction::AMOMINUD(rd, rs1, rs2),
+                    0b11100 => Instruction::AMOMAXUD(rd, rs1, rs2),
+                    _ => return Err(anyhow::anyhow!("Invalid funct5 AMO-Type")),
+                };
+                return rv64_only(xlen, inst);
+            }
             match funct5(instruction) {
                 0b00010 => Ok(Instruction::LRW(rd, rs1)),
                 0b00011 => Ok(Instruction::SCW(rd, rs1, rs2)),
@@ -254,6 +301,14 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let rs1: RS1index = rs1(instruction);
             let rs2: RS2index = rs2(instruction);
 
+            match (funct7(instruction), funct3(instruction)) {
+                /* Zicond */
+                (0b000_0111, 0b101) => return Ok(Instruction::CZEROEQZ(rd_index, rs1, rs2)),
+                (0b000_0111, 0b111) => return Ok(Instruction::CZERONEZ(rd_index, rs1, rs2)),
+                (0b000_0111, _) => return Err(anyhow::anyhow!("Invalid funct3 Zicond")),
+                _ => {}
+            }
+
             let is_m_extension = funct7(instruction) & 0b1 == 1;
             match funct3(instruction) {
                 0b000 => {
@@ -321,7 +376,26 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let u_imm: Immediate = immediate_u(instruction);
             Ok(Instruction::LUI(rd_index, u_imm))
         }
-        OpCode::OP32 => Err(anyhow::anyhow!("Not implemented: OP32")),
+        OpCode::OP32 => {
+            /* R-Type instructions operating on the lower 32 bits */
+            let rd_index: RDindex = rd(instruction);
+            let rs1: RS1index = rs1(instruction);
+            let rs2: RS2index = rs2(instruction);
+            let inst = match (funct7(instruction), funct3(instruction)) {
+                (0b000_0000, 0b000) => Instruction::ADDW(rd_index, rs1, rs2),
+                (0b010_0000, 0b000) => Instruction::SUBW(rd_index, rs1, rs2),
+                (0b000_0000, 0b001) => Instruction::SLLW(rd_index, rs1, rs2),
+                (0b000_0000, 0b101) => Instruction::SRLW(rd_index, rs1, rs2),
+                (0b010_0000, 0b101) => Instruction::SRAW(rd_index, rs1, rs2),
+                (0b000_0001, 0b000) => Instruction::MULW(rd_index, rs1, rs2),
+                (0b000_0001, 0b100) => Instruction::DIVW(rd_index, rs1, rs2),
+                (0b000_0001, 0b101) => Instruction::DIVUW(rd_index, rs1, rs2),
+                (0b000_0001, 0b110) => Instruction::REMW(rd_index, rs1, rs2),
+                (0b000_0001, 0b111) => Instruction::REMUW(rd_index, rs1, rs2),
+                _ => return Err(anyhow::anyhow!("Invalid funct7/funct3 OP32")),
+            };
+            rv64_only(xlen, inst)
+        }
         OpCode::LEN64 => Err(anyhow::anyhow!("Not implemented: LEN64")),
         OpCode::MADD => Err(anyhow::anyhow!("Not implemented: MADD")),
         OpCode::MSUB => Err(anyhow::anyhow!("Not implemented: MSUB")),
@@ -365,13 +439,31 @@ pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
             let rs1: RS1index = rs1(instruction);
             let sys_imm: u32 = immediate_system(instruction);
             match funct3(instruction) {
+                0b000 if funct7(instruction) == 0b000_1001 => {
+                    Ok(Instruction::SFENCEVMA(rs1, rs2(instruction)))
+                }
                 0b000 => match sys_imm {
                     0b0000_0000_0000 => Ok(Instruction::ECALL()),
                     0b0000_0000_0001 => Ok(Instruction::EBREAK()),
+                    0b0001_0000_0010 => Ok(Instruction::SRET()),
                     0b0011_0000_0010 => Ok(Instruction::MRET()),
                     0b0001_0000_0101 => Ok(Instruction::WFI()),
                     _ => Err(anyhow::anyhow!("Invalid SYSTEM instruction immediate")),
                 },
+                0b100 if instruction & 0xB000_0000 == 0x8000_0000 => {
+                    /* Zimop, the operation number is scattered over the immediate */
+                    let n_upper = (instruction >> 30) & 0b1;
+                    let n_middle = (instruction >> 26) & 0b11;
+                    if (instruction >> 25) & 0b1 == 1 {
+                        let n = (n_upper << 2) | n_middle;
+                        Ok(Instruction::MOPRR(rd_index, rs1, rs2(instruction), n))
+                    } else if (instruction >> 22) & 0b111 == 0b111 {
+                        let n = (n_upper << 4) | (n_middle << 2) | ((instruction >> 20) & 0b11);
+                        Ok(Instruction::MOPR(rd_index, rs1, n))
+                    } else {
+                        Err(anyhow::anyhow!("Invalid Zimop instruction"))
+                    }
+                }
                 0b001 => Ok(Instruction::CSRRW(rd_index, rs1, sys_imm)),
                 0b010 => Ok(Instruction::CSRRS(rd_index, rs1, sys_imm)),
                 0b011 => Ok(Instruction::CSRRC(rd_index, rs1, sys_imm)),
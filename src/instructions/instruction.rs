@@ -6,10 +6,10 @@
 //!  - Provide classification via `is_ziscr()`, `is_m()` and `is_compressed()`
 //!  - Pretty print `Instruction`
 use crate::cpu::index_to_name;
-use crate::instructions::{Immediate, RDindex, RS1index, RS2index};
+use crate::instructions::{Immediate, RDindex, RS1index, RS2index, Rindex};
 
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     /* RV32I */
     LUI(RDindex, Immediate),
@@ -53,6 +53,20 @@ pub enum Instruction {
     ECALL(),
     EBREAK(),
     MRET(),
+    SRET(),
+    /* RV64I */
+    LWU(RDindex, RS1index, Immediate),
+    LD(RDindex, RS1index, Immediate),
+    SD(RS1index, RS2index, Immediate),
+    ADDIW(RDindex, RS1index, Immediate),
+    SLLIW(RDindex, RS1index, Immediate),
+    SRLIW(RDindex, RS1index, Immediate),
+    SRAIW(RDindex, RS1index, Immediate),
+    ADDW(RDindex, RS1index, RS2index),
+    SUBW(RDindex, RS1index, RS2index),
+    SLLW(RDindex, RS1index, RS2index),
+    SRLW(RDindex, RS1index, RS2index),
+    SRAW(RDindex, RS1index, RS2index),
     /* Zicsr */
     CSRRW(RDindex, RS1index, u32),
     CSRRS(RDindex, RS1index, u32),
@@ -69,6 +83,12 @@ pub enum Instruction {
     DIVU(RDindex, RS1index, RS2index),
     REM(RDindex, RS1index, RS2index),
     REMU(RDindex, RS1index, RS2index),
+    /* RV64M */
+    MULW(RDindex, RS1index, RS2index),
+    DIVW(RDindex, RS1index, RS2index),
+    DIVUW(RDindex, RS1index, RS2index),
+    REMW(RDindex, RS1index, RS2index),
+    REMUW(RDindex, RS1index, RS2index),
     /* A */
     LRW(RDindex, RS1index),
     SCW(RDindex, RS1index, RS2index),
@@ -81,6 +101,18 @@ pub enum Instruction {
     AMOMAXW(RDindex, RS1index, RS2index),
     AMOMINUW(RDindex, RS1index, RS2index),
     AMOMAXUW(RDindex, RS1index, RS2index),
+    /* RV64A */
+    LRD(RDindex, RS1index),
+    SCD(RDindex, RS1index, RS2index),
+    AMOSWAPD(RDindex, RS1index, RS2index),
+    AMOADDD(RDindex, RS1index, RS2index),
+    AMOXORD(RDindex, RS1index, RS2index),
+    AMOANDD(RDindex, RS1index, RS2index),
+    AMOORD(RDindex, RS1index, RS2index),
+    AMOMIND(RDindex, RS1index, RS2index),
+    AMOMAXD(RDindex, RS1index, RS2index),
+    AMOMINUD(RDindex, RS1index, RS2index),
+    AMOMAXUD(RDindex, RS1index, RS2index),
     /* Compressed Q1 */
     CADDI4SPN(RDindex, Immediate),
     CFLD(RDindex, RS1index, Immediate),
@@ -110,6 +142,9 @@ pub enum Instruction {
     CJ(Immediate),
     CBEQZ(RS1index, Immediate),
     CBNEZ(RS1index, Immediate),
+    CADDIW(RDindex, Immediate),
+    CSUBW(RDindex, RS2index),
+    CADDW(RDindex, RS2index),
     /* Compressed Q3 */
     CSLLI(RDindex, Immediate),
     CFLDSP(RDindex, Immediate),
@@ -123,8 +158,39 @@ pub enum Instruction {
     CFSDSP(RS2index, Immediate),
     CSWSP(RS2index, Immediate),
     CFSWSP(RS2index, Immediate),
+    CLDSP(RDindex, Immediate),
+    CSDSP(RS2index, Immediate),
+    /* Zcb */
+    CLBU(RDindex, RS1index, Immediate),
+    CLHU(RDindex, RS1index, Immediate),
+    CLH(RDindex, RS1index, Immediate),
+    CSB(RS1index, RS2index, Immediate),
+    CSH(RS1index, RS2index, Immediate),
+    CZEXTB(RDindex),
+    CSEXTB(RDindex),
+    CZEXTH(RDindex),
+    CSEXTH(RDindex),
+    CZEXTW(RDindex),
+    CNOT(RDindex),
+    CMUL(RDindex, RS2index),
+    /* Zcmop */
+    CMOP(u32),
+    /* Zifencei */
+    FENCEI(RDindex, RS1index, Immediate),
+    /* Zicond */
+    CZEROEQZ(RDindex, RS1index, RS2index),
+    CZERONEZ(RDindex, RS1index, RS2index),
+    /* Zbb and Zba, only as the expansion of Zcb, their own encodings are not decoded */
+    SEXTB(RDindex, RS1index),
+    SEXTH(RDindex, RS1index),
+    ZEXTH(RDindex, RS1index),
+    ADDUW(RDindex, RS1index, RS2index),
+    /* Zimop */
+    MOPR(RDindex, RS1index, u32),
+    MOPRR(RDindex, RS1index, RS2index, u32),
     /* Priv */
     WFI(),
+    SFENCEVMA(RS1index, RS2index),
 }
 
 impl Instruction {
@@ -139,14 +205,18 @@ impl Instruction {
                 Instruction::LW(rdindex, rs1index, cuimmediate)
             }
             Instruction::CFLW(_rdindex, _rs1index, _cuimmediate) => todo!(),
-            Instruction::CLD(_rdindex, _rs1index, _cuimmediate) => todo!(),
+            Instruction::CLD(rdindex, rs1index, cuimmediate) => {
+                Instruction::LD(rdindex, rs1index, cuimmediate)
+            }
             Instruction::CFSD(_rdindex, _rs1index, _cuimmediate) => todo!(),
             Instruction::CSQ(_rdindex, _rs1index, _cuimmediate) => todo!(),
             Instruction::CSW(rdindex, rs1index, cuimmediate) => {
                 Instruction::SW(rdindex, rs1index, cuimmediate)
             }
             Instruction::CFSW(_rdindex, _rs1index, _cuimmediate) => todo!(),
-            Instruction::CSD(_rdindex, _rs1index, _cuimmediate) => todo!(),
+            Instruction::CSD(rdindex, rs1index, cuimmediate) => {
+                Instruction::SD(rdindex, rs1index, cuimmediate)
+            }
             Instruction::CNOP(_rdindex, _cnzimmediate) => todo!(),
             Instruction::CADDI(rdindex, cnzimmediate) => {
                 Instruction::ADDI(rdindex, rdindex, cnzimmediate)
@@ -171,6 +241,11 @@ impl Instruction {
             Instruction::CJ(cjimmediate) => Instruction::JAL(0, cjimmediate),
             Instruction::CBEQZ(rs1index, cimmediate) => Instruction::BEQ(rs1index, 0, cimmediate),
             Instruction::CBNEZ(rs1index, cimmediate) => Instruction::BNE(rs1index, 0, cimmediate),
+            Instruction::CADDIW(rdindex, cimmediate) => {
+                Instruction::ADDIW(rdindex, rdindex, cimmediate)
+            }
+            Instruction::CSUBW(rdindex, rs2index) => Instruction::SUBW(rdindex, rdindex, rs2index),
+            Instruction::CADDW(rdindex, rs2index) => Instruction::ADDW(rdindex, rdindex, rs2index),
             Instruction::CSLLI(rdindex, cnzuimmediate) => {
                 Instruction::SLLI(rdindex, rdindex, cnzuimmediate)
             }
@@ -187,6 +262,33 @@ impl Instruction {
                 Instruction::SW(2, rs2index, cluimmediate)
             }
             Instruction::CFSWSP(_rs2index, _cluimmediate) => todo!(),
+            Instruction::CLDSP(rdindex, cuimmediate) => Instruction::LD(rdindex, 2, cuimmediate),
+            Instruction::CSDSP(rs2index, cluimmediate) => {
+                Instruction::SD(2, rs2index, cluimmediate)
+            }
+            Instruction::CLBU(rdindex, rs1index, cuimmediate) => {
+                Instruction::LBU(rdindex, rs1index, cuimmediate)
+            }
+            Instruction::CLHU(rdindex, rs1index, cuimmediate) => {
+                Instruction::LHU(rdindex, rs1index, cuimmediate)
+            }
+            Instruction::CLH(rdindex, rs1index, cuimmediate) => {
+                Instruction::LH(rdindex, rs1index, cuimmediate)
+            }
+            Instruction::CSB(rs1index, rs2index, cuimmediate) => {
+                Instruction::SB(rs1index, rs2index, cuimmediate)
+            }
+            Instruction::CSH(rs1index, rs2index, cuimmediate) => {
+                Instruction::SH(rs1index, rs2index, cuimmediate)
+            }
+            Instruction::CZEXTB(rdindex) => Instruction::ANDI(rdindex, rdindex, 0xFF),
+            Instruction::CSEXTB(rdindex) => Instruction::SEXTB(rdindex, rdindex),
+            Instruction::CZEXTH(rdindex) => Instruction::ZEXTH(rdindex, rdindex),
+            Instruction::CSEXTH(rdindex) => Instruction::SEXTH(rdindex, rdindex),
+            Instruction::CZEXTW(rdindex) => Instruction::ADDUW(rdindex, rdindex, 0),
+            Instruction::CNOT(rdindex) => Instruction::XORI(rdindex, rdindex, -1),
+            Instruction::CMUL(rdindex, rs2index) => Instruction::MUL(rdindex, rdindex, rs2index),
+            Instruction::CMOP(_n) => Instruction::ADDI(0, 0, 0),
             _ => panic!(),
         }
     }
@@ -215,8 +317,156 @@ impl Instruction {
                 | Self::DIVU(..)
                 | Self::REM(..)
                 | Self::REMU(..)
+                | Self::MULW(..)
+                | Self::DIVW(..)
+                | Self::DIVUW(..)
+                | Self::REMW(..)
+                | Self::REMUW(..)
+                | Self::CMUL(..)
         )
     }
+    /// Instructions that may not continue at the next address, or that change the state
+    /// a basic block was translated for, are the last ones of their block.
+    pub fn is_block_end(&self) -> bool {
+        self.is_zicsr()
+            || matches!(
+                self,
+                Self::JAL(..)
+                    | Self::JALR(..)
+                    | Self::BEQ(..)
+                    | Self::BNE(..)
+                    | Self::BLT(..)
+                    | Self::BGE(..)
+                    | Self::BLTU(..)
+                    | Self::BGEU(..)
+                    | Self::ECALL()
+                    | Self::EBREAK()
+                    | Self::MRET()
+                    | Self::SRET()
+                    | Self::WFI()
+                    | Self::FENCEI(..)
+                    | Self::SFENCEVMA(..)
+                    | Self::CJAL(..)
+                    | Self::CJ(..)
+                    | Self::CBEQZ(..)
+                    | Self::CBNEZ(..)
+                    | Self::CJR(..)
+                    | Self::CJALR(..)
+                    | Self::CEBREAK()
+            )
+    }
+    /// The integer registers named by the instruction, used to reject x16-x31 in RV32E.
+    #[allow(clippy::too_many_lines)]
+    pub fn registers(&self) -> Vec<Rindex> {
+        match *self {
+            // The rs1 field of the immediate CSR instructions holds the immediate
+            Self::LUI(a, _)
+            | Self::AUIPC(a, _)
+            | Self::JAL(a, _)
+            | Self::CSRRWI(a, _, _)
+            | Self::CSRRSI(a, _, _)
+            | Self::CSRRCI(a, _, _) => vec![a],
+            Self::JALR(a, b, _)
+            | Self::BEQ(a, b, _)
+            | Self::BNE(a, b, _)
+            | Self::BLT(a, b, _)
+            | Self::BGE(a, b, _)
+            | Self::BLTU(a, b, _)
+            | Self::BGEU(a, b, _)
+            | Self::LB(a, b, _)
+            | Self::LH(a, b, _)
+            | Self::LW(a, b, _)
+            | Self::LBU(a, b, _)
+            | Self::LHU(a, b, _)
+            | Self::SB(a, b, _)
+            | Self::SH(a, b, _)
+            | Self::SW(a, b, _)
+            | Self::ADDI(a, b, _)
+            | Self::SLTI(a, b, _)
+            | Self::SLTIU(a, b, _)
+            | Self::XORI(a, b, _)
+            | Self::ORI(a, b, _)
+            | Self::ANDI(a, b, _)
+            | Self::SLLI(a, b, _)
+            | Self::SRLI(a, b, _)
+            | Self::SRAI(a, b, _)
+            | Self::FENCE(a, b, _)
+            | Self::LWU(a, b, _)
+            | Self::LD(a, b, _)
+            | Self::SD(a, b, _)
+            | Self::ADDIW(a, b, _)
+            | Self::SLLIW(a, b, _)
+            | Self::SRLIW(a, b, _)
+            | Self::SRAIW(a, b, _)
+            | Self::CSRRW(a, b, _)
+            | Self::CSRRS(a, b, _)
+            | Self::CSRRC(a, b, _)
+            | Self::FENCEI(a, b, _)
+            | Self::MOPR(a, b, _)
+            | Self::SEXTB(a, b)
+            | Self::SEXTH(a, b)
+            | Self::ZEXTH(a, b)
+            | Self::LRW(a, b)
+            | Self::LRD(a, b)
+            | Self::SFENCEVMA(a, b) => vec![a, b],
+            Self::ADD(a, b, c)
+            | Self::SUB(a, b, c)
+            | Self::SLL(a, b, c)
+            | Self::SLT(a, b, c)
+            | Self::SLTU(a, b, c)
+            | Self::XOR(a, b, c)
+            | Self::SRL(a, b, c)
+            | Self::SRA(a, b, c)
+            | Self::OR(a, b, c)
+            | Self::AND(a, b, c)
+            | Self::ADDW(a, b, c)
+            | Self::SUBW(a, b, c)
+            | Self::SLLW(a, b, c)
+            | Self::SRLW(a, b, c)
+            | Self::SRAW(a, b, c)
+            | Self::MUL(a, b, c)
+            | Self::MULH(a, b, c)
+            | Self::MULHSU(a, b, c)
+            | Self::MULHU(a, b, c)
+            | Self::DIV(a, b, c)
+            | Self::DIVU(a, b, c)
+            | Self::REM(a, b, c)
+            | Self::REMU(a, b, c)
+            | Self::MULW(a, b, c)
+            | Self::DIVW(a, b, c)
+            | Self::DIVUW(a, b, c)
+            | Self::REMW(a, b, c)
+            | Self::REMUW(a, b, c)
+            | Self::SCW(a, b, c)
+            | Self::AMOSWAPW(a, b, c)
+            | Self::AMOADDW(a, b, c)
+            | Self::AMOXORW(a, b, c)
+            | Self::AMOANDW(a, b, c)
+            | Self::AMOORW(a, b, c)
+            | Self::AMOMINW(a, b, c)
+            | Self::AMOMAXW(a, b, c)
+            | Self::AMOMINUW(a, b, c)
+            | Self::AMOMAXUW(a, b, c)
+            | Self::SCD(a, b, c)
+            | Self::AMOSWAPD(a, b, c)
+            | Self::AMOADDD(a, b, c)
+            | Self::AMOXORD(a, b, c)
+            | Self::AMOANDD(a, b, c)
+            | Self::AMOORD(a, b, c)
+            | Self::AMOMIND(a, b, c)
+            | Self::AMOMAXD(a, b, c)
+            | Self::AMOMINUD(a, b, c)
+            | Self::AMOMAXUD(a, b, c)
+            | Self::CZEROEQZ(a, b, c)
+            | Self::CZERONEZ(a, b, c)
+            | Self::ADDUW(a, b, c)
+            | Self::MOPRR(a, b, c, _) => vec![a, b, c],
+            Self::ECALL() | Self::EBREAK() | Self::MRET() | Self::SRET() | Self::WFI() => {
+                Vec::new()
+            }
+            _ => self.decompress().registers(),
+        }
+    }
     pub fn is_compressed(&self) -> bool {
         matches!(
             self,
@@ -247,6 +497,9 @@ impl Instruction {
                 | Self::CJ(..)
                 | Self::CBEQZ(..)
                 | Self::CBNEZ(..)
+                | Self::CADDIW(..)
+                | Self::CSUBW(..)
+                | Self::CADDW(..)
                 | Self::CSLLI(..)
                 | Self::CFLDSP(..)
                 | Self::CLWSP(..)
@@ -259,6 +512,21 @@ impl Instruction {
                 | Self::CFSDSP(..)
                 | Self::CSWSP(..)
                 | Self::CFSWSP(..)
+                | Self::CLDSP(..)
+                | Self::CSDSP(..)
+                | Self::CLBU(..)
+                | Self::CLHU(..)
+                | Self::CLH(..)
+                | Self::CSB(..)
+                | Self::CSH(..)
+                | Self::CZEXTB(..)
+                | Self::CSEXTB(..)
+                | Self::CZEXTH(..)
+                | Self::CSEXTH(..)
+                | Self::CZEXTW(..)
+                | Self::CNOT(..)
+                | Self::CMUL(..)
+                | Self::CMOP(..)
         )
     }
     #[allow(clippy::too_many_lines)]
@@ -487,6 +755,80 @@ impl Instruction {
             Instruction::ECALL() => "ecall".to_string(),
             Instruction::EBREAK() => "ebreak".to_string(),
             Instruction::MRET() => "mret".to_string(),
+            Instruction::SRET() => "sret".to_string(),
+            /* RV64I */
+            Instruction::LWU(rdindex, rs1index, iimmediate) => format!(
+                "lwu {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                iimmediate
+            ),
+            Instruction::LD(rdindex, rs1index, iimmediate) => format!(
+                "ld {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                iimmediate
+            ),
+            Instruction::SD(rs1index, rs2index, simmediate) => format!(
+                "sd {:}, {:}, {:}",
+                index_to_name(rs1index),
+                index_to_name(rs2index),
+                simmediate,
+            ),
+            Instruction::ADDIW(rdindex, rs1index, iimmediate) => format!(
+                "addiw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                iimmediate
+            ),
+            Instruction::SLLIW(rdindex, rs1index, iimmediate) => format!(
+                "slliw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                iimmediate
+            ),
+            Instruction::SRLIW(rdindex, rs1index, iimmediate) => format!(
+                "srliw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                iimmediate
+            ),
+            Instruction::SRAIW(rdindex, rs1index, iimmediate) => format!(
+                "sraiw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                iimmediate
+            ),
+            Instruction::ADDW(rdindex, rs1index, rs2index) => format!(
+                "addw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index),
+            ),
+            Instruction::SUBW(rdindex, rs1index, rs2index) => format!(
+                "subw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index),
+            ),
+            Instruction::SLLW(rdindex, rs1index, rs2index) => format!(
+                "sllw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index),
+            ),
+            Instruction::SRLW(rdindex, rs1index, rs2index) => format!(
+                "srlw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index),
+            ),
+            Instruction::SRAW(rdindex, rs1index, rs2index) => format!(
+                "sraw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index),
+            ),
             /* Zicsr */
             Instruction::CSRRW(rdindex, rs1index, iimmediate) => format!(
                 "csrrw {:}, {:}, {:}",
@@ -573,6 +915,37 @@ impl Instruction {
                 index_to_name(rs1index),
                 index_to_name(rs2index)
             ),
+            /* RV64M */
+            Instruction::MULW(rdindex, rs1index, rs2index) => format!(
+                "mulw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::DIVW(rdindex, rs1index, rs2index) => format!(
+                "divw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::DIVUW(rdindex, rs1index, rs2index) => format!(
+                "divuw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::REMW(rdindex, rs1index, rs2index) => format!(
+                "remw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::REMUW(rdindex, rs1index, rs2index) => format!(
+                "remuw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
             Instruction::LRW(rdindex, rs1index) => format!(
                 "lrw {:}, {:}",
                 index_to_name(rdindex),
@@ -638,6 +1011,72 @@ impl Instruction {
                 index_to_name(rs1index),
                 index_to_name(rs2index)
             ),
+            /* RV64A */
+            Instruction::LRD(rdindex, rs1index) => format!(
+                "lrd {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index)
+            ),
+            Instruction::SCD(rdindex, rs1index, rs2index) => format!(
+                "scd {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOSWAPD(rdindex, rs1index, rs2index) => format!(
+                "amoswapd {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOADDD(rdindex, rs1index, rs2index) => format!(
+                "amoaddd {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOXORD(rdindex, rs1index, rs2index) => format!(
+                "amoxord {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOANDD(rdindex, rs1index, rs2index) => format!(
+                "amoandd {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOORD(rdindex, rs1index, rs2index) => format!(
+                "amoord {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOMIND(rdindex, rs1index, rs2index) => format!(
+                "amomind {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOMAXD(rdindex, rs1index, rs2index) => format!(
+                "amomaxd {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOMINUD(rdindex, rs1index, rs2index) => format!(
+                "amominud {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::AMOMAXUD(rdindex, rs1index, rs2index) => format!(
+                "amomaxud {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
             Instruction::CADDI4SPN(rdindex, cnzuimmediate) => {
                 format!("c.addi4spn {:}, {:}", index_to_name(rdindex), cnzuimmediate)
             }
@@ -753,6 +1192,19 @@ impl Instruction {
             Instruction::CBNEZ(rs1index, cimmediate) => {
                 format!("c.bnez {:}, {:}", index_to_name(rs1index), cimmediate)
             }
+            Instruction::CADDIW(rdindex, cimmediate) => {
+                format!("c.addiw {:}, {:}", index_to_name(rdindex), cimmediate)
+            }
+            Instruction::CSUBW(rdindex, rs2index) => format!(
+                "c.subw {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs2index)
+            ),
+            Instruction::CADDW(rdindex, rs2index) => format!(
+                "c.addw {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs2index)
+            ),
             Instruction::CSLLI(rdindex, cnzuimmediate) => {
                 format!("c.slli {:}, {:}", index_to_name(rdindex), cnzuimmediate)
             }
@@ -789,7 +1241,110 @@ impl Instruction {
             Instruction::CFSWSP(rs2index, cluimmediate) => {
                 format!("c.fswsp {:}, {:}", index_to_name(rs2index), cluimmediate)
             }
+            Instruction::CLDSP(rdindex, cuimmediate) => {
+                format!("c.ldsp {:}, {:}", index_to_name(rdindex), cuimmediate)
+            }
+            Instruction::CSDSP(rs2index, cluimmediate) => {
+                format!("c.sdsp {:}, {:}", index_to_name(rs2index), cluimmediate)
+            }
+            Instruction::CLBU(rdindex, rs1index, cuimmediate) => format!(
+                "c.lbu {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                cuimmediate
+            ),
+            Instruction::CLHU(rdindex, rs1index, cuimmediate) => format!(
+                "c.lhu {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                cuimmediate
+            ),
+            Instruction::CLH(rdindex, rs1index, cuimmediate) => format!(
+                "c.lh {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                cuimmediate
+            ),
+            Instruction::CSB(rs1index, rs2index, cuimmediate) => format!(
+                "c.sb {:}, {:}, {:}",
+                index_to_name(rs1index),
+                index_to_name(rs2index),
+                cuimmediate
+            ),
+            Instruction::CSH(rs1index, rs2index, cuimmediate) => format!(
+                "c.sh {:}, {:}, {:}",
+                index_to_name(rs1index),
+                index_to_name(rs2index),
+                cuimmediate
+            ),
+            Instruction::CZEXTB(rdindex) => format!("c.zext.b {:}", index_to_name(rdindex)),
+            Instruction::CSEXTB(rdindex) => format!("c.sext.b {:}", index_to_name(rdindex)),
+            Instruction::CZEXTH(rdindex) => format!("c.zext.h {:}", index_to_name(rdindex)),
+            Instruction::CSEXTH(rdindex) => format!("c.sext.h {:}", index_to_name(rdindex)),
+            Instruction::CZEXTW(rdindex) => format!("c.zext.w {:}", index_to_name(rdindex)),
+            Instruction::CNOT(rdindex) => format!("c.not {:}", index_to_name(rdindex)),
+            Instruction::CMUL(rdindex, rs2index) => format!(
+                "c.mul {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs2index)
+            ),
+            Instruction::CMOP(n) => format!("c.mop.{n}"),
+            Instruction::FENCEI(rdindex, rs1index, iimmediate) => format!(
+                "fence.i {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                iimmediate
+            ),
+            Instruction::CZEROEQZ(rdindex, rs1index, rs2index) => format!(
+                "czero.eqz {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::CZERONEZ(rdindex, rs1index, rs2index) => format!(
+                "czero.nez {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::SEXTB(rdindex, rs1index) => format!(
+                "sext.b {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index)
+            ),
+            Instruction::SEXTH(rdindex, rs1index) => format!(
+                "sext.h {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index)
+            ),
+            Instruction::ZEXTH(rdindex, rs1index) => format!(
+                "zext.h {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index)
+            ),
+            Instruction::ADDUW(rdindex, rs1index, rs2index) => format!(
+                "add.uw {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
+            Instruction::MOPR(rdindex, rs1index, n) => format!(
+                "mop.r.{n} {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index)
+            ),
+            Instruction::MOPRR(rdindex, rs1index, rs2index, n) => format!(
+                "mop.rr.{n} {:}, {:}, {:}",
+                index_to_name(rdindex),
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
             Instruction::WFI() => "wfi".to_string(),
+            Instruction::SFENCEVMA(rs1index, rs2index) => format!(
+                "sfence.vma {:}, {:}",
+                index_to_name(rs1index),
+                index_to_name(rs2index)
+            ),
         }
     }
 }
@@ -11,13 +11,15 @@ mod instruction;
 
 pub use instruction::Instruction;
 
+use crate::cpu::Xlen;
+
 pub type Rindex = usize;
 pub type RDindex = Rindex;
 pub type RS1index = Rindex;
 pub type RS2index = Rindex;
 
-pub type RS1value = u32;
-pub type RS2value = u32;
+pub type RS1value = u64;
+pub type RS2value = u64;
 
 pub type Immediate = i32;
 
@@ -37,24 +39,25 @@ pub fn bit_from_to(inst: u32, from: u32, to: u32) -> u32 {
     ((inst >> from) & 1) << to
 }
 
-pub fn decode(instruction: u32) -> anyhow::Result<Instruction> {
+/// Some compressed encodings differ between RV32 and RV64, hence the `xlen`.
+pub fn decode(instruction: u32, xlen: Xlen) -> anyhow::Result<Instruction> {
     let encoding_quadrant = instruction & 0b11;
     match encoding_quadrant {
         0 => {
             /* compressed 16 bit wide */
-            decoder_q0::decode(instruction & 0xFFFF)
+            decoder_q0::decode(instruction & 0xFFFF, xlen)
         }
         1 => {
             /* compressed 16 bit wide */
-            decoder_q1::decode(instruction & 0xFFFF)
+            decoder_q1::decode(instruction & 0xFFFF, xlen)
         }
         2 => {
             /* compressed 16 bit wide */
-            decoder_q2::decode(instruction & 0xFFFF)
+            decoder_q2::decode(instruction & 0xFFFF, xlen)
         }
         3 => {
             /* regular 32 bit wide */
-            decoder_q3::decode(instruction)
+            decoder_q3::decode(instruction, xlen)
         }
         _ => {
             unreachable!()
@@ -1,10 +1,31 @@
 //! Emulation of hardware peripherals is scoped for this file.
 //! Currently, only memory mapped peripherals are available via `trait MmapPeripheral`.
 
+use std::ops::{BitAnd, BitOr, Not, Shl};
+
 pub type InterruptReason = u32;
 
 pub trait MmapPeripheral: Send {
+    /// Reads do not mutate the bus, registers with read side effects keep their state in a `Cell`.
+    /// A word is read from its most significant byte down, so a side effect that happens with
+    /// the lowest byte comes after the flags in the upper bytes were read.
     fn read(&self, offset: usize) -> u8;
     fn write(&mut self, offset: usize, value: u8);
     fn pending_interrupt(&self) -> Option<InterruptReason>;
+    /// Advances the peripheral by the given amount of cycles of its clock, the core clock unless noted otherwise.
+    fn tick(&mut self, _cycles: u64) {}
+}
+
+/// Replaces the byte `byte` of the register `target` by `value`, as the bus writes byte by byte.
+pub fn write_byte_of<R>(target: &mut R, byte: usize, value: u8)
+where
+    R: Copy
+        + From<u8>
+        + Shl<usize, Output = R>
+        + BitAnd<Output = R>
+        + BitOr<Output = R>
+        + Not<Output = R>,
+{
+    let shift = byte * 8;
+    *target = (*target & !(R::from(0xFF) << shift)) | (R::from(value) << shift);
 }
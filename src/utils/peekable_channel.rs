@@ -29,10 +29,6 @@ impl<T: Send + Default + 'static> PeekableChannel<T> {
         ((tx1, rx2), new_self)
     }
 
-    pub fn has_data(&self) -> bool {
-        self.peek_reader.has_data()
-    }
-
     pub fn read_cb(&self) -> Option<T> {
         self.peek_reader.try_recv()
     }
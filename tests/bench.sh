@@ -0,0 +1,16 @@
+
+set -e
+
+banner() {
+	printf "##\n# %s\n##\n\n" "${1}"
+}
+
+banner "Build benchmark"
+riscv64-unknown-elf-gcc -march=rv32imac -mabi=ilp32 -nostdlib -nostartfiles -Ttext=0x20000000 -o bench/bench.elf bench/bench.S
+riscv64-unknown-elf-objcopy -O binary bench/bench.elf bench/bench.bin
+
+for engine in interpreter block; do
+	banner "Run benchmark with the ${engine} engine"
+	# The headless mode terminates on EOF at stdin, keep it open until the benchmark is done
+	../target/release/triops --headless --testing --benchmark --engine "${engine}" --bin bench/bench.bin < <(sleep 600)
+done
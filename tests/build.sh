@@ -20,11 +20,6 @@ for file in src/isa/rv32ui-p-* src/isa/rv32um-p-* src/isa/rv32uc-p-*; do
 	name=${file##*/}
 	printf "Running test case '%s':\t" "$name"
 
-	if [ "${name}" = rv32ui-p-fence_i ]; then
-		printf "SKIP\n"
-		continue
-	fi
-
 	# riscv64-unknown-elf-objcopy --strip-debug -O binary ${file} ./test.hex
 
 	ret=0; ../target/debug/rv --file ${file} --headless --testing &> /dev/null || ret=$?